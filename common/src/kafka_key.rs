@@ -0,0 +1,42 @@
+//! Keys given to the messages published to Kafka.
+//!
+//! Kafka assigns messages with the same key to the same partition, so the key
+//! determines both how a topic can be scaled across partitions and which
+//! messages are guaranteed to be consumed in the order they were published.
+
+use crate::DigitizerId;
+
+/// Key for messages produced per digitiser (e.g. traces, digitiser event lists
+/// and histograms), preserving the order of the messages from each digitiser.
+pub fn digitiser_key(digitiser_id: DigitizerId) -> String {
+    format!("digitiser-{digitiser_id}")
+}
+
+/// Key for messages produced per frame from the data of all digitisers
+/// (e.g. aggregated event lists).
+///
+/// Consumers such as stream-to-file discard a frame numbered lower than one
+/// they have already seen, so all frames share one partition to stay in order.
+pub const FRAME_KEY: &str = "frame";
+
+/// Key for run control messages.
+///
+/// The stop of each run must be consumed before the start of the next, so all
+/// run control messages share one partition regardless of the run.
+pub const RUN_KEY: &str = "run";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(digitiser_key(4), "digitiser-4");
+    }
+
+    #[test]
+    fn keys_are_distinct_between_kinds() {
+        assert_ne!(digitiser_key(1), FRAME_KEY);
+        assert_ne!(FRAME_KEY, RUN_KEY);
+    }
+}
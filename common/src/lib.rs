@@ -1,3 +1,4 @@
+pub mod kafka_key;
 pub mod metrics;
 
 use rdkafka::config::ClientConfig;
@@ -8,6 +8,8 @@ pub mod messages_received {
     pub enum MessageKind {
         Trace,
         Event,
+        RunStart,
+        RunStop,
         Unknown,
     }
 
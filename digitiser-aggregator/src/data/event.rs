@@ -1,6 +1,6 @@
 use super::{Accumulate, DigitiserData};
 use crate::frame::AggregatedFrame;
-use supermusr_common::{Channel, Intensity, Time};
+use supermusr_common::{Channel, DigitizerId, Intensity, Time};
 use supermusr_streaming_types::{
     aev1_frame_assembled_event_v1_generated::{
         finish_frame_assembled_event_list_message_buffer, FrameAssembledEventListMessage,
@@ -13,23 +13,32 @@ use supermusr_streaming_types::{
 
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct EventData {
-    time: Vec<Time>,
-    intensity: Vec<Intensity>,
-    channel: Vec<Channel>,
+    pub(crate) time: Vec<Time>,
+    pub(crate) intensity: Vec<Intensity>,
+    pub(crate) channel: Vec<Channel>,
+    /// ID of the digitiser each event was detected by
+    pub(crate) digitiser_id: Vec<DigitizerId>,
 }
 
 impl EventData {
     #[cfg(test)]
-    pub(crate) fn new(time: Vec<Time>, intensity: Vec<Intensity>, channel: Vec<Channel>) -> Self {
+    pub(crate) fn new(
+        time: Vec<Time>,
+        intensity: Vec<Intensity>,
+        channel: Vec<Channel>,
+        digitiser_id: Vec<DigitizerId>,
+    ) -> Self {
         Self {
             time,
             intensity,
             channel,
+            digitiser_id,
         }
     }
 
     #[cfg(test)]
     pub(crate) fn dummy_data(
+        digitiser_id: DigitizerId,
         time_offset: Time,
         events_per_channel: usize,
         channels: &[Channel],
@@ -50,10 +59,13 @@ impl EventData {
             .copied()
             .collect();
 
+        let digitiser_id = vec![digitiser_id; channels.len() * events_per_channel];
+
         Self {
             time,
             intensity,
             channel,
+            digitiser_id,
         }
     }
 
@@ -62,6 +74,7 @@ impl EventData {
             time: Vec::with_capacity(capacity),
             intensity: Vec::with_capacity(capacity),
             channel: Vec::with_capacity(capacity),
+            digitiser_id: Vec::with_capacity(capacity),
         }
     }
 
@@ -72,14 +85,16 @@ impl EventData {
 
 impl<'a> From<DigitizerEventListMessage<'a>> for EventData {
     fn from(msg: DigitizerEventListMessage<'a>) -> Self {
-        let time = msg.time().unwrap().iter().collect();
+        let time: Vec<Time> = msg.time().unwrap().iter().collect();
         let intensity = msg.voltage().unwrap().iter().collect();
         let channel = msg.channel().unwrap().iter().collect();
+        let digitiser_id = vec![msg.digitizer_id(); time.len()];
 
         Self {
             time,
             intensity,
             channel,
+            digitiser_id,
         }
     }
 }
@@ -93,6 +108,7 @@ impl Accumulate<EventData> for DigitiserData<EventData> {
                 acc.time.append(&mut value.1.time);
                 acc.intensity.append(&mut value.1.intensity);
                 acc.channel.append(&mut value.1.channel);
+                acc.digitiser_id.append(&mut value.1.digitiser_id);
                 acc
             })
     }
@@ -118,6 +134,7 @@ impl From<AggregatedFrame<EventData>> for Vec<u8> {
             time: Some(fbb.create_vector::<Time>(&frame.digitiser_data.time)),
             voltage: Some(fbb.create_vector::<Intensity>(&frame.digitiser_data.intensity)),
             channel: Some(fbb.create_vector::<Channel>(&frame.digitiser_data.channel)),
+            complete: !frame.flagged_incomplete,
         };
         let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
 
@@ -136,7 +153,7 @@ mod test {
 
     #[test]
     fn dummy_data_creation() {
-        let data = EventData::dummy_data(2, 5, &[0, 1, 2]);
+        let data = EventData::dummy_data(3, 2, 5, &[0, 1, 2]);
 
         assert_eq!(data.time, [2, 3, 4, 5, 6, 2, 3, 4, 5, 6, 2, 3, 4, 5, 6]);
 
@@ -146,6 +163,8 @@ mod test {
         );
 
         assert_eq!(data.channel, [0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2]);
+
+        assert_eq!(data.digitiser_id, [3; 15]);
     }
 
     #[test]
@@ -171,6 +190,7 @@ mod test {
                 time: Some(fbb.create_vector::<Time>(&[1, 2, 8, 9, 7])),
                 voltage: Some(fbb.create_vector::<Intensity>(&[2, 8, 8, 2, 7])),
                 channel: Some(fbb.create_vector::<Channel>(&[1, 3, 1, 0, 4])),
+                complete: true,
             };
             let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
 
@@ -190,10 +210,13 @@ mod test {
                     veto_flags: 4,
                 },
                 digitiser_ids: vec![0, 1],
+                missing_digitiser_ids: vec![],
+                flagged_incomplete: false,
                 digitiser_data: EventData {
                     time: vec![1, 2, 8, 9, 7],
                     intensity: vec![2, 8, 8, 2, 7],
                     channel: vec![1, 3, 1, 0, 4],
+                    digitiser_id: vec![0, 0, 0, 1, 1],
                 },
             };
 
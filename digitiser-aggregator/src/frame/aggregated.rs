@@ -1,6 +1,5 @@
 use super::partial::PartialFrame;
 use crate::data::{Accumulate, DigitiserData};
-#[cfg(test)]
 use supermusr_common::DigitizerId;
 use supermusr_streaming_types::FrameMetadata;
 
@@ -8,21 +7,36 @@ pub(crate) struct AggregatedFrame<D> {
     pub(crate) metadata: FrameMetadata,
     #[cfg(test)]
     pub(crate) digitiser_ids: Vec<DigitizerId>,
+    /// Expected digitisers that did not report data for this frame
+    pub(crate) missing_digitiser_ids: Vec<DigitizerId>,
+    /// Set if the frame is published while incomplete and should be marked as such
+    pub(crate) flagged_incomplete: bool,
     pub(crate) digitiser_data: D,
 }
 
-impl<D> From<PartialFrame<D>> for AggregatedFrame<D>
+impl<D> AggregatedFrame<D>
 where
     DigitiserData<D>: Accumulate<D>,
 {
-    fn from(mut partial: PartialFrame<D>) -> Self {
+    pub(super) fn from_partial(
+        mut partial: PartialFrame<D>,
+        expected_digitisers: &[DigitizerId],
+    ) -> Self {
         Self {
             metadata: partial.metadata.clone(),
             #[cfg(test)]
             digitiser_ids: partial.digitiser_ids(),
+            missing_digitiser_ids: partial.missing_digitiser_ids(expected_digitisers),
+            flagged_incomplete: false,
             digitiser_data: <DigitiserData<D> as Accumulate<D>>::accumulate(
                 &mut partial.digitiser_data,
             ),
         }
     }
 }
+
+impl<D> AggregatedFrame<D> {
+    pub(crate) fn is_complete(&self) -> bool {
+        self.missing_digitiser_ids.is_empty()
+    }
+}
@@ -1,14 +1,40 @@
 use super::{aggregated::AggregatedFrame, partial::PartialFrame};
 use crate::data::{Accumulate, DigitiserData};
+use chrono::{DateTime, Utc};
 use std::{collections::VecDeque, time::Duration};
 use supermusr_common::DigitizerId;
 use supermusr_streaming_types::FrameMetadata;
+use tracing::warn;
+
+/// Number of emitted frames remembered in order to recognise late data for them.
+const EMITTED_FRAME_HISTORY: usize = 64;
+
+/// Data timestamped this many seconds before the latest emitted frame is taken to mean that
+/// the digitiser clocks have been reset, e.g. at the start of a run, rather than to be late.
+const CLOCK_RESET_SECONDS: i64 = 10;
+
+/// Reasons for rejecting data pushed to a [FrameCache].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LateData {
+    /// The frame the data belongs to has already been emitted
+    FrameAlreadyEmitted,
+    /// The data belongs to a frame earlier than one that has already been emitted
+    FrameOutOfOrder,
+    /// The digitiser has already sent data for the frame
+    DuplicateDigitiser,
+}
 
 pub(crate) struct FrameCache<D> {
     ttl: Duration,
     expected_digitisers: Vec<DigitizerId>,
 
+    /// Frames being assembled, in timestamp order
     frames: VecDeque<PartialFrame<D>>,
+
+    /// Metadata of the most recently emitted frames
+    emitted: VecDeque<FrameMetadata>,
+    /// Timestamp of the latest emitted frame, data for earlier frames is rejected
+    emitted_until: Option<DateTime<Utc>>,
 }
 
 impl<D> FrameCache<D>
@@ -20,37 +46,93 @@ where
             ttl,
             expected_digitisers,
             frames: Default::default(),
+            emitted: Default::default(),
+            emitted_until: None,
         }
     }
 
-    pub(crate) fn push(&mut self, digitiser_id: DigitizerId, metadata: FrameMetadata, data: D) {
+    pub(crate) fn push(
+        &mut self,
+        digitiser_id: DigitizerId,
+        metadata: FrameMetadata,
+        data: D,
+    ) -> Result<(), LateData> {
+        if self.emitted.contains(&metadata) {
+            return Err(LateData::FrameAlreadyEmitted);
+        }
+        if let Some(emitted_until) = self.emitted_until {
+            if metadata.timestamp < emitted_until - chrono::Duration::seconds(CLOCK_RESET_SECONDS) {
+                warn!(
+                    "Frame at {} is long before the latest emitted frame at {emitted_until}, assuming the clocks were reset",
+                    metadata.timestamp
+                );
+                self.emitted_until = None;
+                self.emitted.clear();
+            } else if metadata.timestamp <= emitted_until {
+                return Err(LateData::FrameOutOfOrder);
+            }
+        }
+
         match self
             .frames
             .iter_mut()
             .find(|frame| frame.metadata == metadata)
         {
             Some(frame) => {
+                if frame.digitiser_ids().contains(&digitiser_id) {
+                    return Err(LateData::DuplicateDigitiser);
+                }
                 frame.push(digitiser_id, data);
             }
             None => {
+                let index = self
+                    .frames
+                    .partition_point(|frame| frame.metadata.timestamp <= metadata.timestamp);
                 let mut frame = PartialFrame::new(self.ttl, metadata);
                 frame.push(digitiser_id, data);
-                self.frames.push_back(frame);
+                self.frames.insert(index, frame);
             }
         }
+
+        Ok(())
+    }
+
+    /// Number of frames currently being assembled.
+    pub(crate) fn len(&self) -> usize {
+        self.frames.len()
     }
 
+    /// Returns the earliest frame if it is ready to be emitted.
+    ///
+    /// A frame is ready when it is complete, it has expired, or a later frame
+    /// is complete. As each digitiser sends its frames in order, in the latter
+    /// case the missing data will not arrive.
     pub(crate) fn poll(&mut self) -> Option<AggregatedFrame<D>> {
-        match self.frames.front() {
-            Some(frame) => {
-                if frame.is_complete(&self.expected_digitisers) || frame.is_expired() {
-                    Some(self.frames.pop_front().unwrap().into())
-                } else {
-                    None
-                }
-            }
-            None => None,
+        let frame = self.frames.front()?;
+
+        let ready = frame.is_complete(&self.expected_digitisers)
+            || frame.is_expired()
+            || self
+                .frames
+                .iter()
+                .any(|frame| frame.is_complete(&self.expected_digitisers));
+
+        if !ready {
+            return None;
         }
+
+        let frame = self.frames.pop_front()?;
+
+        self.emitted_until = Some(frame.metadata.timestamp);
+        self.emitted.push_back(frame.metadata.clone());
+        if self.emitted.len() > EMITTED_FRAME_HISTORY {
+            self.emitted.pop_front();
+        }
+
+        Some(AggregatedFrame::from_partial(
+            frame,
+            &self.expected_digitisers,
+        ))
     }
 }
 
@@ -58,7 +140,6 @@ where
 mod test {
     use super::*;
     use crate::data::EventData;
-    use chrono::Utc;
 
     #[test]
     fn one_frame_in_one_frame_out() {
@@ -75,29 +156,51 @@ mod test {
 
         assert!(cache.poll().is_none());
 
-        cache.push(0, frame_1.clone(), EventData::dummy_data(0, 5, &[0, 1, 2]));
+        cache
+            .push(
+                0,
+                frame_1.clone(),
+                EventData::dummy_data(0, 0, 5, &[0, 1, 2]),
+            )
+            .unwrap();
 
         assert!(cache.poll().is_none());
 
-        cache.push(1, frame_1.clone(), EventData::dummy_data(0, 5, &[3, 4, 5]));
+        cache
+            .push(
+                1,
+                frame_1.clone(),
+                EventData::dummy_data(1, 0, 5, &[3, 4, 5]),
+            )
+            .unwrap();
 
         assert!(cache.poll().is_none());
 
-        cache.push(4, frame_1.clone(), EventData::dummy_data(0, 5, &[6, 7, 8]));
+        cache
+            .push(
+                4,
+                frame_1.clone(),
+                EventData::dummy_data(4, 0, 5, &[6, 7, 8]),
+            )
+            .unwrap();
 
         assert!(cache.poll().is_none());
 
-        cache.push(
-            8,
-            frame_1.clone(),
-            EventData::dummy_data(0, 5, &[9, 10, 11]),
-        );
+        cache
+            .push(
+                8,
+                frame_1.clone(),
+                EventData::dummy_data(8, 0, 5, &[9, 10, 11]),
+            )
+            .unwrap();
 
         {
             let frame = cache.poll().unwrap();
 
             assert_eq!(frame.metadata, frame_1);
 
+            assert!(frame.is_complete());
+
             let mut dids = frame.digitiser_ids;
             dids.sort();
             assert_eq!(dids, &[0, 1, 4, 8]);
@@ -116,6 +219,7 @@ mod test {
                         5, 5, 5, 5, 5, 6, 6, 6, 6, 6, 7, 7, 7, 7, 7, 8, 8, 8, 8, 8, 9, 9, 9, 9, 9,
                         10, 10, 10, 10, 10, 11, 11, 11, 11, 11
                     ],
+                    [vec![0; 15], vec![1; 15], vec![4; 15], vec![8; 15]].concat(),
                 )
             );
         }
@@ -138,19 +242,33 @@ mod test {
 
         assert!(cache.poll().is_none());
 
-        cache.push(0, frame_1.clone(), EventData::dummy_data(0, 5, &[0, 1, 2]));
+        cache
+            .push(
+                0,
+                frame_1.clone(),
+                EventData::dummy_data(0, 0, 5, &[0, 1, 2]),
+            )
+            .unwrap();
 
         assert!(cache.poll().is_none());
 
-        cache.push(1, frame_1.clone(), EventData::dummy_data(0, 5, &[3, 4, 5]));
+        cache
+            .push(
+                1,
+                frame_1.clone(),
+                EventData::dummy_data(1, 0, 5, &[3, 4, 5]),
+            )
+            .unwrap();
 
         assert!(cache.poll().is_none());
 
-        cache.push(
-            8,
-            frame_1.clone(),
-            EventData::dummy_data(0, 5, &[9, 10, 11]),
-        );
+        cache
+            .push(
+                8,
+                frame_1.clone(),
+                EventData::dummy_data(8, 0, 5, &[9, 10, 11]),
+            )
+            .unwrap();
 
         assert!(cache.poll().is_none());
 
@@ -161,6 +279,8 @@ mod test {
 
             assert_eq!(frame.metadata, frame_1);
 
+            assert_eq!(frame.missing_digitiser_ids, &[4]);
+
             let mut dids = frame.digitiser_ids;
             dids.sort();
             assert_eq!(dids, &[0, 1, 8]);
@@ -177,10 +297,170 @@ mod test {
                         0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4,
                         5, 5, 5, 5, 5, 9, 9, 9, 9, 9, 10, 10, 10, 10, 10, 11, 11, 11, 11, 11
                     ],
+                    [vec![0; 15], vec![1; 15], vec![8; 15]].concat(),
                 )
             );
         }
 
         assert!(cache.poll().is_none());
     }
+
+    fn frame_metadata(frame_number: u32, timestamp: DateTime<Utc>) -> FrameMetadata {
+        FrameMetadata {
+            timestamp,
+            period_number: 1,
+            protons_per_pulse: 8,
+            running: true,
+            frame_number,
+            veto_flags: 4,
+        }
+    }
+
+    #[test]
+    fn reordered_frames_out_in_timestamp_order() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1]);
+
+        let now = Utc::now();
+        let frame_1 = frame_metadata(1, now);
+        let frame_2 = frame_metadata(2, now + chrono::Duration::milliseconds(20));
+
+        for digitiser_id in [0, 1] {
+            cache
+                .push(
+                    digitiser_id,
+                    frame_2.clone(),
+                    EventData::dummy_data(digitiser_id, 0, 5, &[0]),
+                )
+                .unwrap();
+            cache
+                .push(
+                    digitiser_id,
+                    frame_1.clone(),
+                    EventData::dummy_data(digitiser_id, 0, 5, &[0]),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(cache.poll().unwrap().metadata, frame_1);
+        assert_eq!(cache.poll().unwrap().metadata, frame_2);
+        assert!(cache.poll().is_none());
+    }
+
+    #[test]
+    fn complete_frame_releases_earlier_incomplete_frame() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_secs(60), vec![0, 1]);
+
+        let now = Utc::now();
+        let frame_1 = frame_metadata(1, now);
+        let frame_2 = frame_metadata(2, now + chrono::Duration::milliseconds(20));
+
+        cache
+            .push(0, frame_1.clone(), EventData::dummy_data(0, 0, 5, &[0]))
+            .unwrap();
+        cache
+            .push(0, frame_2.clone(), EventData::dummy_data(0, 0, 5, &[0]))
+            .unwrap();
+
+        assert!(cache.poll().is_none());
+
+        cache
+            .push(1, frame_2.clone(), EventData::dummy_data(1, 0, 5, &[0]))
+            .unwrap();
+
+        let frame = cache.poll().unwrap();
+        assert_eq!(frame.metadata, frame_1);
+        assert_eq!(frame.missing_digitiser_ids, &[1]);
+
+        let frame = cache.poll().unwrap();
+        assert_eq!(frame.metadata, frame_2);
+        assert!(frame.is_complete());
+
+        assert!(cache.poll().is_none());
+    }
+
+    #[tokio::test]
+    async fn late_data_is_rejected() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_millis(100), vec![0, 1]);
+
+        let now = Utc::now();
+        let frame_1 = frame_metadata(1, now);
+        let frame_2 = frame_metadata(2, now + chrono::Duration::milliseconds(20));
+
+        cache
+            .push(0, frame_2.clone(), EventData::dummy_data(0, 0, 5, &[0]))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(105)).await;
+
+        assert_eq!(cache.poll().unwrap().metadata, frame_2);
+
+        assert_eq!(
+            cache.push(1, frame_2.clone(), EventData::dummy_data(1, 0, 5, &[0])),
+            Err(LateData::FrameAlreadyEmitted)
+        );
+        assert_eq!(
+            cache.push(1, frame_1.clone(), EventData::dummy_data(1, 0, 5, &[0])),
+            Err(LateData::FrameOutOfOrder)
+        );
+
+        assert!(cache.poll().is_none());
+    }
+
+    #[test]
+    fn duplicate_digitiser_is_rejected() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_secs(60), vec![0, 1]);
+
+        let frame_1 = frame_metadata(1, Utc::now());
+
+        cache
+            .push(0, frame_1.clone(), EventData::dummy_data(0, 0, 5, &[0]))
+            .unwrap();
+        assert_eq!(
+            cache.push(0, frame_1.clone(), EventData::dummy_data(0, 0, 5, &[0])),
+            Err(LateData::DuplicateDigitiser)
+        );
+        assert!(cache.poll().is_none());
+
+        cache
+            .push(1, frame_1.clone(), EventData::dummy_data(1, 0, 5, &[0]))
+            .unwrap();
+
+        let frame = cache.poll().unwrap();
+        assert!(frame.is_complete());
+        assert_eq!(frame.digitiser_ids, &[0, 1]);
+        assert_eq!(frame.digitiser_data.time.len(), 10);
+    }
+
+    #[test]
+    fn clock_reset_is_accepted() {
+        let mut cache = FrameCache::<EventData>::new(Duration::from_secs(60), vec![0]);
+
+        let now = Utc::now();
+        let frame_1 = frame_metadata(1, now);
+        let frame_2 = frame_metadata(2, now - chrono::Duration::seconds(5));
+        let frame_3 = frame_metadata(3, now - chrono::Duration::hours(1));
+        let frame_4 = frame_metadata(4, frame_3.timestamp + chrono::Duration::milliseconds(20));
+
+        cache
+            .push(0, frame_1.clone(), EventData::dummy_data(0, 0, 5, &[0]))
+            .unwrap();
+        assert_eq!(cache.poll().unwrap().metadata, frame_1);
+
+        // Slightly earlier data is late, but much earlier data follows a reset of the clocks
+        assert_eq!(
+            cache.push(0, frame_2.clone(), EventData::dummy_data(0, 0, 5, &[0])),
+            Err(LateData::FrameOutOfOrder)
+        );
+        for frame in [&frame_3, &frame_4] {
+            cache
+                .push(0, frame.clone(), EventData::dummy_data(0, 0, 5, &[0]))
+                .unwrap();
+            assert_eq!(&cache.poll().unwrap().metadata, frame);
+        }
+
+        assert_eq!(
+            cache.push(0, frame_3.clone(), EventData::dummy_data(0, 0, 5, &[0])),
+            Err(LateData::FrameAlreadyEmitted)
+        );
+    }
 }
@@ -0,0 +1,237 @@
+use super::AggregatedFrame;
+use clap::ValueEnum;
+use std::collections::VecDeque;
+
+/// What to do with frames that an expected digitiser did not report data for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CompletenessPolicy {
+    /// Publish the data from the digitisers that did report
+    #[default]
+    Publish,
+    /// Drop the data from all digitisers
+    Drop,
+    /// Publish the data from the digitisers that did report, marked as incomplete
+    Flag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DropReason {
+    /// The frame was incomplete and the policy is to drop incomplete frames
+    Incomplete,
+    /// The frame was incomplete and would have been the first or last frame of a run
+    RunBoundary,
+}
+
+pub(crate) enum Outcome<D> {
+    Publish(AggregatedFrame<D>),
+    Drop(AggregatedFrame<D>, DropReason),
+}
+
+/// Applies a [CompletenessPolicy] to aggregated frames, and ensures that the
+/// first and last frames published for each run (as given by the `running`
+/// flag of the frame metadata) are frames that every expected digitiser reported.
+pub(crate) struct CompletenessFilter<D> {
+    policy: CompletenessPolicy,
+    max_withheld: usize,
+
+    in_run: bool,
+    /// Incomplete frames of the current run, withheld until a later complete
+    /// frame shows that they are not the last frame of the run
+    withheld: VecDeque<AggregatedFrame<D>>,
+}
+
+impl<D> CompletenessFilter<D> {
+    pub(crate) fn new(policy: CompletenessPolicy, max_withheld: usize) -> Self {
+        Self {
+            policy,
+            max_withheld,
+            in_run: false,
+            withheld: Default::default(),
+        }
+    }
+
+    /// Number of incomplete frames currently withheld.
+    pub(crate) fn withheld_len(&self) -> usize {
+        self.withheld.len()
+    }
+
+    /// Returns what should be done with the given frame, and any frames previously withheld.
+    pub(crate) fn push(&mut self, frame: AggregatedFrame<D>) -> Vec<Outcome<D>> {
+        let mut outcomes = Vec::new();
+
+        if !frame.metadata.running {
+            if self.in_run {
+                // The run has ended, so any withheld frames were the last of the run
+                self.in_run = false;
+                outcomes.extend(
+                    self.withheld
+                        .drain(..)
+                        .map(|frame| Outcome::Drop(frame, DropReason::RunBoundary)),
+                );
+            }
+            outcomes.push(self.apply_policy(frame));
+        } else if !self.in_run {
+            if frame.is_complete() {
+                self.in_run = true;
+                outcomes.push(Outcome::Publish(frame));
+            } else {
+                outcomes.push(Outcome::Drop(frame, DropReason::RunBoundary));
+            }
+        } else if frame.is_complete() {
+            while let Some(withheld) = self.withheld.pop_front() {
+                outcomes.push(self.apply_policy(withheld));
+            }
+            outcomes.push(Outcome::Publish(frame));
+        } else {
+            self.withheld.push_back(frame);
+
+            // Bound the memory used if a digitiser stops reporting mid run, at
+            // the cost of the last frame of the run possibly being incomplete
+            while self.withheld.len() > self.max_withheld {
+                let withheld = self.withheld.pop_front().unwrap();
+                outcomes.push(self.apply_policy(withheld));
+            }
+        }
+
+        outcomes
+    }
+
+    fn apply_policy(&self, mut frame: AggregatedFrame<D>) -> Outcome<D> {
+        if frame.is_complete() {
+            return Outcome::Publish(frame);
+        }
+
+        match self.policy {
+            CompletenessPolicy::Publish => Outcome::Publish(frame),
+            CompletenessPolicy::Drop => Outcome::Drop(frame, DropReason::Incomplete),
+            CompletenessPolicy::Flag => {
+                frame.flagged_incomplete = true;
+                Outcome::Publish(frame)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::EventData;
+    use chrono::Utc;
+    use supermusr_common::DigitizerId;
+    use supermusr_streaming_types::FrameMetadata;
+
+    fn frame(
+        frame_number: u32,
+        running: bool,
+        missing_digitiser_ids: Vec<DigitizerId>,
+    ) -> AggregatedFrame<EventData> {
+        AggregatedFrame {
+            metadata: FrameMetadata {
+                timestamp: Utc::now(),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running,
+                frame_number,
+                veto_flags: 4,
+            },
+            digitiser_ids: vec![],
+            missing_digitiser_ids,
+            flagged_incomplete: false,
+            digitiser_data: EventData::dummy_data(0, 0, 5, &[0, 1, 2]),
+        }
+    }
+
+    /// Summarises outcomes as (frame number, dropped reason, flagged incomplete).
+    fn summarise(outcomes: Vec<Outcome<EventData>>) -> Vec<(u32, Option<DropReason>, bool)> {
+        outcomes
+            .into_iter()
+            .map(|outcome| match outcome {
+                Outcome::Publish(frame) => {
+                    (frame.metadata.frame_number, None, frame.flagged_incomplete)
+                }
+                Outcome::Drop(frame, reason) => (frame.metadata.frame_number, Some(reason), false),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn incomplete_frames_outside_run() {
+        let mut publish = CompletenessFilter::new(CompletenessPolicy::Publish, 10);
+        assert_eq!(
+            summarise(publish.push(frame(1, false, vec![4]))),
+            [(1, None, false)]
+        );
+
+        let mut drop = CompletenessFilter::new(CompletenessPolicy::Drop, 10);
+        assert_eq!(
+            summarise(drop.push(frame(1, false, vec![4]))),
+            [(1, Some(DropReason::Incomplete), false)]
+        );
+
+        let mut flag = CompletenessFilter::new(CompletenessPolicy::Flag, 10);
+        assert_eq!(
+            summarise(flag.push(frame(1, false, vec![4]))),
+            [(1, None, true)]
+        );
+        assert_eq!(
+            summarise(flag.push(frame(2, false, vec![]))),
+            [(2, None, false)]
+        );
+    }
+
+    #[test]
+    fn run_starts_on_complete_frame() {
+        let mut filter = CompletenessFilter::new(CompletenessPolicy::Publish, 10);
+
+        assert_eq!(
+            summarise(filter.push(frame(1, true, vec![4]))),
+            [(1, Some(DropReason::RunBoundary), false)]
+        );
+        assert_eq!(
+            summarise(filter.push(frame(2, true, vec![]))),
+            [(2, None, false)]
+        );
+        assert!(filter.push(frame(3, true, vec![4])).is_empty());
+        assert_eq!(
+            summarise(filter.push(frame(4, true, vec![]))),
+            [(3, None, false), (4, None, false)]
+        );
+    }
+
+    #[test]
+    fn run_ends_on_complete_frame() {
+        let mut filter = CompletenessFilter::new(CompletenessPolicy::Flag, 10);
+
+        assert_eq!(
+            summarise(filter.push(frame(1, true, vec![]))),
+            [(1, None, false)]
+        );
+        assert!(filter.push(frame(2, true, vec![0])).is_empty());
+        assert!(filter.push(frame(3, true, vec![4])).is_empty());
+        assert_eq!(
+            summarise(filter.push(frame(4, false, vec![4]))),
+            [
+                (2, Some(DropReason::RunBoundary), false),
+                (3, Some(DropReason::RunBoundary), false),
+                (4, None, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn withheld_frames_are_bounded() {
+        let mut filter = CompletenessFilter::new(CompletenessPolicy::Drop, 2);
+
+        assert_eq!(
+            summarise(filter.push(frame(1, true, vec![]))),
+            [(1, None, false)]
+        );
+        assert!(filter.push(frame(2, true, vec![4])).is_empty());
+        assert!(filter.push(frame(3, true, vec![4])).is_empty());
+        assert_eq!(
+            summarise(filter.push(frame(4, true, vec![4]))),
+            [(2, Some(DropReason::Incomplete), false)]
+        );
+        assert_eq!(filter.withheld_len(), 2);
+    }
+}
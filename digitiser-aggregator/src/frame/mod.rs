@@ -1,6 +1,8 @@
 mod aggregated;
 mod cache;
+mod completeness;
 mod partial;
 
 pub(crate) use aggregated::AggregatedFrame;
-pub(crate) use cache::FrameCache;
+pub(crate) use cache::{FrameCache, LateData};
+pub(crate) use completeness::{CompletenessFilter, CompletenessPolicy, DropReason, Outcome};
@@ -37,6 +37,18 @@ impl<D> PartialFrame<D> {
         self.digitiser_ids() == expected_digitisers
     }
 
+    pub(super) fn missing_digitiser_ids(
+        &self,
+        expected_digitisers: &[DigitizerId],
+    ) -> Vec<DigitizerId> {
+        let digitiser_ids = self.digitiser_ids();
+        expected_digitisers
+            .iter()
+            .filter(|id| !digitiser_ids.contains(id))
+            .copied()
+            .collect()
+    }
+
     pub(super) fn is_expired(&self) -> bool {
         Instant::now() > self.expiry
     }
@@ -1,9 +1,15 @@
 mod data;
 mod frame;
+mod metrics;
+mod output;
 
-use crate::data::EventData;
+use crate::{
+    data::EventData,
+    frame::{CompletenessFilter, CompletenessPolicy, FrameCache, Outcome},
+    output::{FrameSerialiser, OutputFormat},
+};
 use clap::Parser;
-use frame::FrameCache;
+use kagiyama::{AlwaysReady, Watcher};
 use rdkafka::{
     consumer::{stream_consumer::StreamConsumer, CommitMode, Consumer},
     message::{BorrowedMessage, Message},
@@ -11,7 +17,7 @@ use rdkafka::{
     util::Timeout,
 };
 use std::{net::SocketAddr, time::Duration};
-use supermusr_common::DigitizerId;
+use supermusr_common::{kafka_key::FRAME_KEY, DigitizerId};
 use supermusr_streaming_types::dev1_digitizer_event_v1_generated::{
     digitizer_event_list_message_buffer_has_identifier, root_as_digitizer_event_list_message,
 };
@@ -38,6 +44,19 @@ struct Cli {
     #[clap(long)]
     output_topic: String,
 
+    /// Format of the messages published to the output topic
+    #[clap(long, value_enum, default_value_t = OutputFormat::Aev1)]
+    output_format: OutputFormat,
+
+    /// Source name given in ev42/ev44 messages
+    #[clap(long, default_value = "digitiser-aggregator")]
+    source_name: String,
+
+    /// Number of channels per digitiser, used to give each channel of each digitiser a unique
+    /// detector ID in ev42/ev44 messages, for which it is required
+    #[clap(long, required_if_eq_any = [("output_format", "ev42"), ("output_format", "ev44")])]
+    channels_per_digitiser: Option<u32>,
+
     #[clap(short, long)]
     digitiser_ids: Vec<DigitizerId>,
 
@@ -47,6 +66,18 @@ struct Cli {
     #[clap(long, default_value = "500")]
     cache_poll_ms: u64,
 
+    /// What to do with frames that not every expected digitiser reported
+    #[clap(long, value_enum, default_value_t = CompletenessPolicy::Publish)]
+    completeness_policy: CompletenessPolicy,
+
+    /// Maximum number of incomplete frames held back within a run while waiting to find
+    /// out whether they are the last frame of the run. Once exceeded, the oldest frame is
+    /// released. This bounds the memory held to this many aggregated frames, each of which
+    /// takes about 11 bytes per event, e.g. about 11 MB for the default of 100 frames of
+    /// 10,000 events
+    #[clap(long, default_value = "100")]
+    max_withheld_frames: usize,
+
     #[clap(long, default_value = "127.0.0.1:9090")]
     observability_address: SocketAddr,
 }
@@ -57,6 +88,10 @@ async fn main() {
 
     let args = Cli::parse();
 
+    let mut watcher = Watcher::<AlwaysReady>::default();
+    metrics::register(&watcher);
+    watcher.start_server(args.observability_address).await;
+
     let consumer: StreamConsumer = supermusr_common::generate_kafka_client_config(
         &args.broker,
         &args.username,
@@ -85,20 +120,30 @@ async fn main() {
 
     let mut cache = FrameCache::<EventData>::new(ttl, args.digitiser_ids);
 
+    let mut filter =
+        CompletenessFilter::<EventData>::new(args.completeness_policy, args.max_withheld_frames);
+
+    let mut serialiser = FrameSerialiser::new(
+        args.output_format,
+        args.source_name,
+        // Only given, and only used, for the ESS formats
+        args.channels_per_digitiser.unwrap_or_default(),
+    );
+
     let mut cache_poll_interval = tokio::time::interval(Duration::from_millis(args.cache_poll_ms));
     loop {
         tokio::select! {
             event = consumer.recv() => {
                 match event {
                     Ok(msg) => {
-                        on_message(&mut cache, &producer, &args.output_topic, &msg).await;
+                        on_message(&mut cache, &mut filter, &mut serialiser, &producer, &args.output_topic, &msg).await;
                         consumer.commit_message(&msg, CommitMode::Async).unwrap();
                     }
                     Err(e) => warn!("Kafka error: {}", e),
                 };
             }
             _ = cache_poll_interval.tick() => {
-                cache_poll(&mut cache, &producer, &args.output_topic).await;
+                cache_poll(&mut cache, &mut filter, &mut serialiser, &producer, &args.output_topic).await;
             }
         }
     }
@@ -106,6 +151,8 @@ async fn main() {
 
 async fn on_message(
     cache: &mut FrameCache<EventData>,
+    filter: &mut CompletenessFilter<EventData>,
+    serialiser: &mut FrameSerialiser,
     producer: &FutureProducer,
     output_topic: &str,
     msg: &BorrowedMessage<'_>,
@@ -115,38 +162,118 @@ async fn on_message(
             match root_as_digitizer_event_list_message(payload) {
                 Ok(msg) => {
                     debug!("Event packet: metadata: {:?}", msg.metadata());
-                    cache.push(msg.digitizer_id(), msg.metadata().into(), msg.into());
-                    cache_poll(cache, producer, output_topic).await;
+                    let digitiser_id = msg.digitizer_id();
+                    metrics::MESSAGES_RECEIVED
+                        .get_or_create(&metrics::MessagesReceivedLabels::new(
+                            metrics::MessageKind::Event,
+                        ))
+                        .inc();
+                    metrics::DIGITISER_MESSAGES_RECEIVED
+                        .get_or_create(&metrics::DigitiserLabels::new(digitiser_id))
+                        .inc();
+                    if let Err(late) = cache.push(digitiser_id, msg.metadata().into(), msg.into()) {
+                        warn!("Rejected data from digitiser {digitiser_id}: {late:?}");
+                        metrics::LATE_DATA_REJECTED
+                            .get_or_create(&metrics::LateDataLabels::new(digitiser_id, late))
+                            .inc();
+                    }
+                    cache_poll(cache, filter, serialiser, producer, output_topic).await;
                 }
                 Err(e) => {
                     warn!("Failed to parse message: {}", e);
+                    metrics::FAILURES
+                        .get_or_create(&metrics::FailureLabels::new(
+                            metrics::FailureKind::UnableToDecodeMessage,
+                        ))
+                        .inc();
                 }
             }
         } else {
             warn!("Unexpected message type on topic \"{}\"", msg.topic());
+            metrics::MESSAGES_RECEIVED
+                .get_or_create(&metrics::MessagesReceivedLabels::new(
+                    metrics::MessageKind::Unknown,
+                ))
+                .inc();
         }
     }
 }
 
 async fn cache_poll(
     cache: &mut FrameCache<EventData>,
+    filter: &mut CompletenessFilter<EventData>,
+    serialiser: &mut FrameSerialiser,
     producer: &FutureProducer,
     output_topic: &str,
 ) {
-    if let Some(frame) = cache.poll() {
-        let data: Vec<u8> = frame.into();
-
-        match producer
-            .send(
-                FutureRecord::to(output_topic)
-                    .payload(&data)
-                    .key(&"todo".to_string()),
-                Timeout::After(Duration::from_millis(100)),
-            )
-            .await
-        {
-            Ok(r) => debug!("Delivery: {:?}", r),
-            Err(e) => error!("Delivery failed: {:?}", e),
-        };
+    while let Some(frame) = cache.poll() {
+        metrics::FRAMES_RELEASED
+            .get_or_create(&metrics::FramesReleasedLabels::new(frame.is_complete()))
+            .inc();
+        for digitiser_id in &frame.missing_digitiser_ids {
+            metrics::FRAME_MISSING_DIGITISERS
+                .get_or_create(&metrics::DigitiserLabels::new(*digitiser_id))
+                .inc();
+        }
+
+        for outcome in filter.push(frame) {
+            match outcome {
+                Outcome::Publish(frame) => {
+                    let event_count = frame.digitiser_data.event_count();
+                    let frame_number = frame.metadata.frame_number;
+                    let data = match serialiser.serialise(frame) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            error!("Failed to serialise frame {frame_number}: {e:?}");
+                            metrics::FAILURES
+                                .get_or_create(&metrics::FailureLabels::new(
+                                    metrics::FailureKind::DataProcessingFailed,
+                                ))
+                                .inc();
+                            continue;
+                        }
+                    };
+
+                    match producer
+                        .send(
+                            FutureRecord::to(output_topic).payload(&data).key(FRAME_KEY),
+                            Timeout::After(Duration::from_millis(100)),
+                        )
+                        .await
+                    {
+                        Ok(r) => {
+                            debug!("Delivery: {:?}", r);
+                            metrics::FRAMES_PUBLISHED.inc();
+                            metrics::EVENTS_PER_FRAME.observe(event_count as f64);
+                        }
+                        Err(e) => {
+                            error!("Delivery failed: {:?}", e);
+                            metrics::FAILURES
+                                .get_or_create(&metrics::FailureLabels::new(
+                                    metrics::FailureKind::KafkaPublishFailed,
+                                ))
+                                .inc();
+                        }
+                    };
+                }
+                Outcome::Drop(frame, reason) => {
+                    warn!(
+                        "Dropped frame {} ({:?}), missing digitisers: {:?}",
+                        frame.metadata.frame_number, reason, frame.missing_digitiser_ids
+                    );
+                    metrics::FRAMES_DROPPED
+                        .get_or_create(&metrics::FramesDroppedLabels::new(reason))
+                        .inc();
+                    for digitiser_id in frame.missing_digitiser_ids {
+                        metrics::DROPPED_FRAME_MISSING_DIGITISERS
+                            .get_or_create(&metrics::DigitiserLabels::new(digitiser_id))
+                            .inc();
+                    }
+                }
+            }
+        }
     }
+
+    metrics::FRAMES_BUFFERED.set(cache.len() as i64);
+    metrics::FRAMES_WITHHELD.set(filter.withheld_len() as i64);
 }
@@ -0,0 +1,222 @@
+use crate::frame::{DropReason, LateData};
+use kagiyama::{
+    prometheus::{
+        self as prometheus_client,
+        encoding::{EncodeLabelSet, EncodeLabelValue},
+        metrics::{
+            counter::Counter,
+            family::Family,
+            gauge::Gauge,
+            histogram::{exponential_buckets, Histogram},
+        },
+    },
+    AlwaysReady, Watcher,
+};
+use lazy_static::lazy_static;
+pub(crate) use supermusr_common::metrics::{
+    failures::{FailureKind, FailureLabels},
+    messages_received::{MessageKind, MessagesReceivedLabels},
+};
+use supermusr_common::DigitizerId;
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelValue)]
+pub(crate) enum DropReasonLabel {
+    Incomplete,
+    RunBoundary,
+}
+
+impl From<DropReason> for DropReasonLabel {
+    fn from(reason: DropReason) -> Self {
+        match reason {
+            DropReason::Incomplete => Self::Incomplete,
+            DropReason::RunBoundary => Self::RunBoundary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct FramesDroppedLabels {
+    reason: DropReasonLabel,
+}
+
+impl FramesDroppedLabels {
+    pub(crate) fn new(reason: DropReason) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct DigitiserLabels {
+    digitiser_id: DigitizerId,
+}
+
+impl DigitiserLabels {
+    pub(crate) fn new(digitiser_id: DigitizerId) -> Self {
+        Self { digitiser_id }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelValue)]
+pub(crate) enum ReleaseReason {
+    /// Every expected digitiser reported
+    Completed,
+    /// Released without every expected digitiser, once the frame timed out
+    /// or a later frame completed
+    Expired,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct FramesReleasedLabels {
+    reason: ReleaseReason,
+}
+
+impl FramesReleasedLabels {
+    pub(crate) fn new(complete: bool) -> Self {
+        Self {
+            reason: if complete {
+                ReleaseReason::Completed
+            } else {
+                ReleaseReason::Expired
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelValue)]
+pub(crate) enum LateDataLabel {
+    FrameAlreadyEmitted,
+    FrameOutOfOrder,
+    DuplicateDigitiser,
+}
+
+impl From<LateData> for LateDataLabel {
+    fn from(late: LateData) -> Self {
+        match late {
+            LateData::FrameAlreadyEmitted => Self::FrameAlreadyEmitted,
+            LateData::FrameOutOfOrder => Self::FrameOutOfOrder,
+            LateData::DuplicateDigitiser => Self::DuplicateDigitiser,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct LateDataLabels {
+    digitiser_id: DigitizerId,
+    kind: LateDataLabel,
+}
+
+impl LateDataLabels {
+    pub(crate) fn new(digitiser_id: DigitizerId, late: LateData) -> Self {
+        Self {
+            digitiser_id,
+            kind: late.into(),
+        }
+    }
+}
+
+pub(crate) fn register(watcher: &Watcher<AlwaysReady>) {
+    let mut registry = watcher.metrics_registry();
+
+    let registry = registry.sub_registry_with_prefix("digitiseraggregator");
+
+    registry.register(
+        "messages_received",
+        "Messages received by type from incomming Kafka topic",
+        MESSAGES_RECEIVED.clone(),
+    );
+
+    registry.register(
+        "digitiser_messages_received",
+        "Event messages received from each digitiser",
+        DIGITISER_MESSAGES_RECEIVED.clone(),
+    );
+
+    registry.register("failures", "Failures by type", FAILURES.clone());
+
+    registry.register(
+        "frames_released",
+        "Frames released from the cache, by whether they completed or expired",
+        FRAMES_RELEASED.clone(),
+    );
+
+    registry.register(
+        "frames_withheld",
+        "Incomplete frames currently withheld by the completeness policy",
+        FRAMES_WITHHELD.clone(),
+    );
+
+    registry.register(
+        "frames_buffered",
+        "Frames currently being assembled in the cache",
+        FRAMES_BUFFERED.clone(),
+    );
+
+    registry.register(
+        "frame_missing_digitisers",
+        "Digitisers missing from frames released from the cache",
+        FRAME_MISSING_DIGITISERS.clone(),
+    );
+
+    registry.register(
+        "events_per_frame",
+        "Number of events in each published frame",
+        EVENTS_PER_FRAME.clone(),
+    );
+
+    registry.register(
+        "frames_published",
+        "Frames succesfully published",
+        FRAMES_PUBLISHED.clone(),
+    );
+
+    registry.register(
+        "frames_dropped",
+        "Frames dropped by reason",
+        FRAMES_DROPPED.clone(),
+    );
+
+    registry.register(
+        "dropped_frame_missing_digitisers",
+        "Digitisers missing from dropped frames",
+        DROPPED_FRAME_MISSING_DIGITISERS.clone(),
+    );
+
+    registry.register(
+        "invalid_channel_events_dropped",
+        "Events dropped from ev42/ev44 messages as their channel is beyond the channels per digitiser",
+        INVALID_CHANNEL_EVENTS_DROPPED.clone(),
+    );
+
+    registry.register(
+        "late_data_rejected",
+        "Digitiser messages rejected as their frame was already emitted or already had their data",
+        LATE_DATA_REJECTED.clone(),
+    );
+}
+
+lazy_static! {
+    pub(crate) static ref MESSAGES_RECEIVED: Family::<MessagesReceivedLabels, Counter> =
+        Family::<MessagesReceivedLabels, Counter>::default();
+    pub(crate) static ref DIGITISER_MESSAGES_RECEIVED: Family::<DigitiserLabels, Counter> =
+        Family::<DigitiserLabels, Counter>::default();
+    pub(crate) static ref FAILURES: Family::<FailureLabels, Counter> =
+        Family::<FailureLabels, Counter>::default();
+    pub(crate) static ref FRAMES_RELEASED: Family::<FramesReleasedLabels, Counter> =
+        Family::<FramesReleasedLabels, Counter>::default();
+    pub(crate) static ref FRAMES_BUFFERED: Gauge = Gauge::default();
+    pub(crate) static ref FRAMES_WITHHELD: Gauge = Gauge::default();
+    pub(crate) static ref FRAME_MISSING_DIGITISERS: Family::<DigitiserLabels, Counter> =
+        Family::<DigitiserLabels, Counter>::default();
+    pub(crate) static ref EVENTS_PER_FRAME: Histogram =
+        Histogram::new(exponential_buckets(1.0, 2.0, 20));
+    pub(crate) static ref FRAMES_PUBLISHED: Counter = Counter::default();
+    pub(crate) static ref FRAMES_DROPPED: Family::<FramesDroppedLabels, Counter> =
+        Family::<FramesDroppedLabels, Counter>::default();
+    pub(crate) static ref DROPPED_FRAME_MISSING_DIGITISERS: Family::<DigitiserLabels, Counter> =
+        Family::<DigitiserLabels, Counter>::default();
+    pub(crate) static ref INVALID_CHANNEL_EVENTS_DROPPED: Counter = Counter::default();
+    pub(crate) static ref LATE_DATA_REJECTED: Family::<LateDataLabels, Counter> =
+        Family::<LateDataLabels, Counter>::default();
+}
@@ -0,0 +1,305 @@
+use crate::{data::EventData, frame::AggregatedFrame, metrics};
+use clap::ValueEnum;
+use supermusr_common::{Channel, DigitizerId, Time};
+use supermusr_streaming_types::{
+    ecs_ev42_events_generated::{
+        finish_event_message_buffer, EventMessage, EventMessageArgs, FacilityData,
+    },
+    ecs_ev44_events_generated::{
+        finish_event_44_message_buffer, Event44Message, Event44MessageArgs,
+    },
+    ecs_is84_isis_events_generated::{ISISData, ISISDataArgs, RunState},
+    flatbuffers::FlatBufferBuilder,
+};
+use tracing::warn;
+
+/// Format of the messages published for each aggregated frame.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Frame assembled event list (aev1)
+    #[default]
+    Aev1,
+    /// ESS event message (ev42)
+    Ev42,
+    /// ESS event message with multiple reference times (ev44)
+    Ev44,
+}
+
+/// Reasons for failing to serialise a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SerialiseError {
+    /// The timestamp of the frame cannot be given in nanoseconds since the Unix epoch
+    TimestampOutOfRange,
+}
+
+/// Serialises aggregated frames into messages of an [OutputFormat].
+pub(crate) struct FrameSerialiser {
+    format: OutputFormat,
+    source_name: String,
+    /// Used to give each channel of each digitiser a unique detector ID
+    channels_per_digitiser: u32,
+    /// Consecutive ID given to ESS event messages, allowing missing messages to be detected
+    next_message_id: u64,
+}
+
+impl FrameSerialiser {
+    pub(crate) fn new(
+        format: OutputFormat,
+        source_name: String,
+        channels_per_digitiser: u32,
+    ) -> Self {
+        Self {
+            format,
+            source_name,
+            channels_per_digitiser,
+            next_message_id: 0,
+        }
+    }
+
+    pub(crate) fn serialise(
+        &mut self,
+        frame: AggregatedFrame<EventData>,
+    ) -> Result<Vec<u8>, SerialiseError> {
+        match self.format {
+            OutputFormat::Aev1 => Ok(frame.into()),
+            OutputFormat::Ev42 => self.serialise_ev42(&frame),
+            OutputFormat::Ev44 => self.serialise_ev44(&frame),
+        }
+    }
+
+    /// Detector ID of a channel of a digitiser, none if the channel is beyond the channels
+    /// of a digitiser, as it would be given the ID of a channel of the next digitiser.
+    fn detector_id(&self, digitiser_id: DigitizerId, channel: Channel) -> Option<u32> {
+        (channel < self.channels_per_digitiser)
+            .then(|| digitiser_id as u32 * self.channels_per_digitiser + channel)
+    }
+
+    /// Times of flight and detector IDs of the events, dropping those of invalid channels.
+    fn detector_events(&self, data: &EventData) -> (Vec<Time>, Vec<u32>) {
+        let (time, detector_id): (Vec<_>, Vec<_>) = data
+            .time
+            .iter()
+            .zip(data.digitiser_id.iter().zip(data.channel.iter()))
+            .filter_map(|(time, (digitiser_id, channel))| {
+                Some((*time, self.detector_id(*digitiser_id, *channel)?))
+            })
+            .unzip();
+
+        let dropped = data.time.len() - time.len();
+        if dropped > 0 {
+            warn!(
+                "Dropped {dropped} events of channels beyond the {} channels per digitiser",
+                self.channels_per_digitiser
+            );
+            metrics::INVALID_CHANNEL_EVENTS_DROPPED.inc_by(dropped as u64);
+        }
+        (time, detector_id)
+    }
+
+    fn message_id(&mut self) -> u64 {
+        let message_id = self.next_message_id;
+        self.next_message_id += 1;
+        message_id
+    }
+
+    fn serialise_ev42(
+        &mut self,
+        frame: &AggregatedFrame<EventData>,
+    ) -> Result<Vec<u8>, SerialiseError> {
+        let pulse_time = pulse_time(frame)?;
+        let (time_of_flight, detector_id) = self.detector_events(&frame.digitiser_data);
+
+        let mut fbb = FlatBufferBuilder::new();
+
+        let isis_data = ISISDataArgs {
+            period_number: frame.metadata.period_number as u32,
+            run_state: if frame.metadata.running {
+                RunState::RUNNING
+            } else {
+                RunState::SETUP
+            },
+            proton_charge: 0.0,
+        };
+        let isis_data = ISISData::create(&mut fbb, &isis_data);
+
+        let message = EventMessageArgs {
+            source_name: Some(fbb.create_string(&self.source_name)),
+            message_id: self.message_id(),
+            pulse_time: pulse_time as u64,
+            time_of_flight: Some(fbb.create_vector(&time_of_flight)),
+            detector_id: Some(fbb.create_vector(&detector_id)),
+            facility_specific_data_type: FacilityData::ISISData,
+            facility_specific_data: Some(isis_data.as_union_value()),
+        };
+        let message = EventMessage::create(&mut fbb, &message);
+
+        finish_event_message_buffer(&mut fbb, message);
+
+        Ok(fbb.finished_data().to_vec())
+    }
+
+    fn serialise_ev44(
+        &mut self,
+        frame: &AggregatedFrame<EventData>,
+    ) -> Result<Vec<u8>, SerialiseError> {
+        let pulse_time = pulse_time(frame)?;
+        let (time_of_flight, detector_id) = self.detector_events(&frame.digitiser_data);
+
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time_of_flight: Vec<i32> = time_of_flight.into_iter().map(|time| time as i32).collect();
+        let pixel_id: Vec<i32> = detector_id.into_iter().map(|id| id as i32).collect();
+
+        let message = Event44MessageArgs {
+            source_name: Some(fbb.create_string(&self.source_name)),
+            message_id: self.message_id() as i64,
+            reference_time: Some(fbb.create_vector(&[pulse_time])),
+            reference_time_index: Some(fbb.create_vector(&[0])),
+            time_of_flight: Some(fbb.create_vector(&time_of_flight)),
+            pixel_id: Some(fbb.create_vector(&pixel_id)),
+        };
+        let message = Event44Message::create(&mut fbb, &message);
+
+        finish_event_44_message_buffer(&mut fbb, message);
+
+        Ok(fbb.finished_data().to_vec())
+    }
+}
+
+/// Time of the frame in nanoseconds since the Unix epoch.
+fn pulse_time(frame: &AggregatedFrame<EventData>) -> Result<i64, SerialiseError> {
+    frame
+        .metadata
+        .timestamp
+        .timestamp_nanos_opt()
+        .ok_or(SerialiseError::TimestampOutOfRange)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use supermusr_streaming_types::{
+        ecs_ev42_events_generated::root_as_event_message,
+        ecs_ev44_events_generated::root_as_event_44_message, FrameMetadata,
+    };
+
+    fn test_frame() -> AggregatedFrame<EventData> {
+        AggregatedFrame {
+            metadata: FrameMetadata {
+                timestamp: Utc::now(),
+                period_number: 1,
+                protons_per_pulse: 8,
+                running: true,
+                frame_number: 1337,
+                veto_flags: 4,
+            },
+            digitiser_ids: vec![0, 2],
+            missing_digitiser_ids: vec![],
+            flagged_incomplete: false,
+            digitiser_data: EventData::new(
+                vec![1, 2, 8, 9, 7],
+                vec![2, 8, 8, 2, 7],
+                vec![1, 3, 1, 0, 4],
+                vec![0, 0, 2, 2, 2],
+            ),
+        }
+    }
+
+    #[test]
+    fn aggregate_frame_to_ev42() {
+        let mut serialiser = FrameSerialiser::new(OutputFormat::Ev42, "test".to_owned(), 8);
+
+        let frame = test_frame();
+        let timestamp = frame.metadata.timestamp;
+
+        let first = serialiser.serialise(frame).unwrap();
+        let first = root_as_event_message(&first).unwrap();
+
+        assert_eq!(first.source_name(), Some("test"));
+        assert_eq!(first.message_id(), 0);
+        assert_eq!(
+            first.pulse_time(),
+            timestamp.timestamp_nanos_opt().unwrap() as u64
+        );
+        assert_eq!(
+            first.time_of_flight().unwrap().iter().collect::<Vec<_>>(),
+            [1, 2, 8, 9, 7]
+        );
+        assert_eq!(
+            first.detector_id().unwrap().iter().collect::<Vec<_>>(),
+            [1, 3, 17, 16, 20]
+        );
+
+        let isis_data = first.facility_specific_data_as_isisdata().unwrap();
+        assert_eq!(isis_data.period_number(), 1);
+        assert_eq!(isis_data.run_state(), RunState::RUNNING);
+
+        let second = serialiser.serialise(test_frame()).unwrap();
+        let second = root_as_event_message(&second).unwrap();
+
+        assert_eq!(second.message_id(), 1);
+    }
+
+    #[test]
+    fn aggregate_frame_to_ev44() {
+        let mut serialiser = FrameSerialiser::new(OutputFormat::Ev44, "test".to_owned(), 5);
+
+        let frame = test_frame();
+        let timestamp = frame.metadata.timestamp;
+
+        let message = serialiser.serialise(frame).unwrap();
+        let message = root_as_event_44_message(&message).unwrap();
+
+        assert_eq!(message.source_name(), "test");
+        assert_eq!(message.message_id(), 0);
+        assert_eq!(
+            message.reference_time().iter().collect::<Vec<_>>(),
+            [timestamp.timestamp_nanos_opt().unwrap()]
+        );
+        assert_eq!(
+            message.reference_time_index().iter().collect::<Vec<_>>(),
+            [0]
+        );
+        assert_eq!(
+            message.time_of_flight().unwrap().iter().collect::<Vec<_>>(),
+            [1, 2, 8, 9, 7]
+        );
+        assert_eq!(
+            message.pixel_id().unwrap().iter().collect::<Vec<_>>(),
+            [1, 3, 11, 10, 14]
+        );
+    }
+
+    #[test]
+    fn channels_beyond_digitiser_are_dropped() {
+        // Channels 3 and 4 would be given the IDs of channels of the next digitiser
+        let mut serialiser = FrameSerialiser::new(OutputFormat::Ev42, "test".to_owned(), 3);
+
+        let message = serialiser.serialise(test_frame()).unwrap();
+        let message = root_as_event_message(&message).unwrap();
+
+        assert_eq!(
+            message.time_of_flight().unwrap().iter().collect::<Vec<_>>(),
+            [1, 8, 9]
+        );
+        assert_eq!(
+            message.detector_id().unwrap().iter().collect::<Vec<_>>(),
+            [1, 7, 6]
+        );
+    }
+
+    #[test]
+    fn unrepresentable_timestamp_is_an_error() {
+        for format in [OutputFormat::Ev42, OutputFormat::Ev44] {
+            let mut frame = test_frame();
+            frame.metadata.timestamp = Utc.with_ymd_and_hms(2300, 1, 1, 0, 0, 0).unwrap();
+
+            let mut serialiser = FrameSerialiser::new(format, "test".to_owned(), 8);
+            assert_eq!(
+                serialiser.serialise(frame),
+                Err(SerialiseError::TimestampOutOfRange)
+            );
+        }
+    }
+}
@@ -108,7 +108,9 @@ async fn main() -> Result<()> {
                                                 args.time_bin_width,
                                                 edges.clone(),
                                             ))
-                                            .key("test"),
+                                            .key(&supermusr_common::kafka_key::digitiser_key(
+                                                thing.digitizer_id(),
+                                            )),
                                         Duration::from_secs(0),
                                     )
                                     .await
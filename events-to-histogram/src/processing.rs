@@ -163,6 +163,7 @@ mod tests {
             time,
             channel,
             voltage,
+            ..Default::default()
         };
         let message = DigitizerEventListMessage::create(&mut fbb, &message);
         finish_digitizer_event_list_message_buffer(&mut fbb, message);
@@ -90,12 +90,12 @@
           // import ./events-to-histogram {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;}
           // import ./kafka-daq-report {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;}
           // import ./run-simulator {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;}
-          // import ./simulator {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;}
+          // import ./simulator {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs hdf5-joined;}
           // import ./stream-to-file {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs hdf5-joined;}
           // import ./trace-archiver {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs hdf5-joined;}
           // import ./trace-archiver-tdengine {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;}
           // import ./trace-reader {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;}
-          // import ./trace-to-events {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs;};
+          // import ./trace-to-events {inherit pkgs naersk' version git_revision nativeBuildInputs buildInputs hdf5-joined;};
       }
     );
 }
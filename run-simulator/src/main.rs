@@ -6,6 +6,7 @@ use rdkafka::{
     util::Timeout,
 };
 use std::time::Duration;
+use supermusr_common::kafka_key::RUN_KEY;
 use supermusr_streaming_types::{
     ecs_6s4t_run_stop_generated::{finish_run_stop_buffer, RunStop, RunStopArgs},
     ecs_pl72_run_start_generated::{finish_run_start_buffer, RunStart, RunStartArgs},
@@ -88,9 +89,7 @@ async fn main() {
     // Send bytes to the broker
     match producer
         .send(
-            FutureRecord::to(&cli.topic)
-                .payload(&bytes)
-                .key(&"Run".to_string()),
+            FutureRecord::to(&cli.topic).payload(&bytes).key(RUN_KEY),
             Timeout::After(Duration::from_millis(100)),
         )
         .await
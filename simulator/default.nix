@@ -5,6 +5,7 @@
   git_revision,
   nativeBuildInputs,
   buildInputs,
+  hdf5-joined,
 }: rec {
   simulator = naersk'.buildPackage {
     name = "simulator";
@@ -19,6 +20,8 @@
     overrideMain = p: {
       GIT_REVISION = git_revision;
     };
+
+    HDF5_DIR = "${hdf5-joined}";
   };
 
   simulator-container-image = pkgs.dockerTools.buildImage {
@@ -0,0 +1,289 @@
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, ValueEnum};
+use rand::{seq::SliceRandom, Rng};
+use std::str::FromStr;
+use supermusr_common::{DigitizerId, FrameNumber};
+
+/// The messages of a digitiser, either in every frame or in a single frame,
+/// given as "digitiser_id" or "digitiser_id:frame_number".
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DigitiserFrames {
+    digitiser_id: DigitizerId,
+    frame_number: Option<FrameNumber>,
+}
+
+impl FromStr for DigitiserFrames {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (digitiser_id, frame_number) = match s.split_once(':') {
+            Some((digitiser_id, frame_number)) => (digitiser_id, Some(frame_number.parse()?)),
+            None => (s, None),
+        };
+        Ok(Self {
+            digitiser_id: digitiser_id.parse()?,
+            frame_number,
+        })
+    }
+}
+
+impl DigitiserFrames {
+    fn includes(&self, digitiser_id: DigitizerId, frame_number: FrameNumber) -> bool {
+        self.digitiser_id == digitiser_id
+            && !matches!(self.frame_number, Some(frame) if frame != frame_number)
+    }
+}
+
+/// A fixed offset of the timestamps of a digitiser, given as "digitiser_id:nanoseconds".
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Desync {
+    digitiser_id: DigitizerId,
+    offset: i64,
+}
+
+impl FromStr for Desync {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (digitiser_id, offset) = s
+            .split_once(':')
+            .ok_or(anyhow!("expected digitiser_id:nanoseconds"))?;
+        Ok(Self {
+            digitiser_id: digitiser_id.parse()?,
+            offset: offset.parse()?,
+        })
+    }
+}
+
+/// A delay of the messages of a digitiser until after the messages of later frames,
+/// given as "digitiser_id:frames" for every frame or "digitiser_id:frame_number:frames"
+/// for a single frame.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Delay {
+    digitiser_frames: DigitiserFrames,
+    frames: u32,
+}
+
+impl FromStr for Delay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (digitiser_frames, frames) = s
+            .rsplit_once(':')
+            .ok_or(anyhow!("expected digitiser_id:frames"))?;
+        Ok(Self {
+            digitiser_frames: digitiser_frames.parse()?,
+            frames: frames.parse()?,
+        })
+    }
+}
+
+/// Order in which the digitisers publish their messages in each frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum Order {
+    /// In the order the digitisers are given
+    #[default]
+    Given,
+    /// In the reverse of the order the digitisers are given
+    Reversed,
+    /// In a random order in each frame
+    Shuffled,
+}
+
+#[derive(Clone, Parser)]
+pub(crate) struct FaultOptions {
+    /// Largest random offset in nanoseconds, either way, of the timestamp of each digitiser in each frame
+    #[clap(long, default_value = "0")]
+    jitter: u32,
+
+    /// Offsets the timestamps of a digitiser in every frame, given as "digitiser_id:nanoseconds", which may be given more than once
+    #[clap(long)]
+    desync: Vec<Desync>,
+
+    /// Messages of a digitiser which are not published, given as "digitiser_id" for every frame or "digitiser_id:frame_number" for a single frame, which may be given more than once
+    #[clap(long)]
+    drop: Vec<DigitiserFrames>,
+
+    /// Messages of a digitiser which are published twice, given as for --drop
+    #[clap(long)]
+    duplicate: Vec<DigitiserFrames>,
+
+    /// Order in which the digitisers publish their messages in each frame
+    #[clap(long, value_enum, default_value_t = Order::Given)]
+    order: Order,
+
+    /// Messages of a digitiser which are published after the messages of the given number of later frames, given as "digitiser_id:frames" for every frame or "digitiser_id:frame_number:frames" for a single frame, which may be given more than once
+    #[clap(long)]
+    delay: Vec<Delay>,
+}
+
+impl FaultOptions {
+    /// The order in which the digitisers publish their messages in a frame.
+    pub(crate) fn order(
+        &self,
+        rng: &mut impl Rng,
+        digitiser_ids: &[DigitizerId],
+    ) -> Vec<DigitizerId> {
+        let mut digitiser_ids = digitiser_ids.to_vec();
+        match self.order {
+            Order::Given => {}
+            Order::Reversed => digitiser_ids.reverse(),
+            Order::Shuffled => digitiser_ids.shuffle(rng),
+        }
+        digitiser_ids
+    }
+
+    /// The timestamp of the messages of a digitiser, given the timestamp of the frame.
+    pub(crate) fn timestamp(
+        &self,
+        rng: &mut impl Rng,
+        timestamp: DateTime<Utc>,
+        digitiser_id: DigitizerId,
+    ) -> DateTime<Utc> {
+        let desync: i64 = self
+            .desync
+            .iter()
+            .filter(|desync| desync.digitiser_id == digitiser_id)
+            .map(|desync| desync.offset)
+            .sum();
+        let jitter = if self.jitter > 0 {
+            rng.gen_range(-(self.jitter as i64)..=self.jitter as i64)
+        } else {
+            0
+        };
+        timestamp + Duration::nanoseconds(desync + jitter)
+    }
+
+    /// The number of later frames after whose messages the messages of a digitiser in a frame are published.
+    pub(crate) fn delay(&self, digitiser_id: DigitizerId, frame_number: FrameNumber) -> u32 {
+        self.delay
+            .iter()
+            .filter(|delay| delay.digitiser_frames.includes(digitiser_id, frame_number))
+            .map(|delay| delay.frames)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The number of times each message of a digitiser is published in a frame.
+    pub(crate) fn copies(&self, digitiser_id: DigitizerId, frame_number: FrameNumber) -> usize {
+        let matches = |faults: &[DigitiserFrames]| {
+            faults
+                .iter()
+                .any(|fault| fault.includes(digitiser_id, frame_number))
+        };
+        if matches(&self.drop) {
+            0
+        } else if matches(&self.duplicate) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn options(args: &[&str]) -> FaultOptions {
+        FaultOptions::parse_from(std::iter::once("simulator").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            DigitiserFrames::from_str("3").unwrap(),
+            DigitiserFrames {
+                digitiser_id: 3,
+                frame_number: None
+            }
+        );
+        assert_eq!(
+            DigitiserFrames::from_str("3:17").unwrap(),
+            DigitiserFrames {
+                digitiser_id: 3,
+                frame_number: Some(17)
+            }
+        );
+        assert!(DigitiserFrames::from_str("3:").is_err());
+        assert!(DigitiserFrames::from_str("300").is_err());
+        assert_eq!(
+            Desync::from_str("2:-150").unwrap(),
+            Desync {
+                digitiser_id: 2,
+                offset: -150
+            }
+        );
+        assert!(Desync::from_str("2").is_err());
+        assert_eq!(
+            Delay::from_str("2:5:3").unwrap(),
+            Delay {
+                digitiser_frames: DigitiserFrames {
+                    digitiser_id: 2,
+                    frame_number: Some(5)
+                },
+                frames: 3
+            }
+        );
+        assert!(Delay::from_str("2").is_err());
+    }
+
+    #[test]
+    fn delays() {
+        let faults = options(&["--delay", "1:2", "--delay", "2:5:3"]);
+        assert_eq!(faults.delay(0, 5), 0);
+        assert_eq!(faults.delay(1, 6), 2);
+        assert_eq!(faults.delay(2, 5), 3);
+        assert_eq!(faults.delay(2, 6), 0);
+    }
+
+    #[test]
+    fn copies() {
+        let faults = options(&["--drop", "1", "--drop", "2:5", "--duplicate", "2"]);
+        assert_eq!(faults.copies(0, 5), 1);
+        assert_eq!(faults.copies(1, 5), 0);
+        assert_eq!(faults.copies(1, 6), 0);
+        assert_eq!(faults.copies(2, 5), 0);
+        assert_eq!(faults.copies(2, 6), 2);
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let frame = Utc::now();
+
+        let desynced = options(&["--desync=1:-150", "--desync", "2:40"]);
+        assert_eq!(desynced.timestamp(&mut rng, frame, 0), frame);
+        assert_eq!(
+            desynced.timestamp(&mut rng, frame, 1),
+            frame - Duration::nanoseconds(150)
+        );
+        assert_eq!(
+            desynced.timestamp(&mut rng, frame, 2),
+            frame + Duration::nanoseconds(40)
+        );
+
+        let jittered = options(&["--jitter", "10"]);
+        for _ in 0..100 {
+            let offset = jittered.timestamp(&mut rng, frame, 0) - frame;
+            assert!(offset.num_nanoseconds().unwrap().abs() <= 10);
+        }
+    }
+
+    #[test]
+    fn order() {
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let digitisers = [4, 5, 6, 7];
+        assert_eq!(options(&[]).order(&mut rng, &digitisers), digitisers);
+        assert_eq!(
+            options(&["--order", "reversed"]).order(&mut rng, &digitisers),
+            [7, 6, 5, 4]
+        );
+        let mut shuffled = options(&["--order", "shuffled"]).order(&mut rng, &digitisers);
+        shuffled.sort();
+        assert_eq!(shuffled, digitisers);
+    }
+}
@@ -0,0 +1,705 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use clap::{Parser, ValueEnum};
+use hdf5::{types::VarLenUnicode, Dataset, File, H5Type};
+use ndarray::{s, Array2};
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+use supermusr_common::{channel_index, FrameNumber, Intensity, SampleRate, CHANNELS_PER_DIGITIZER};
+use supermusr_streaming_types::{
+    dat1_digitizer_analog_trace_v1_generated::{
+        digitizer_analog_trace_message_buffer_has_identifier,
+        root_as_digitizer_analog_trace_message, DigitizerAnalogTraceMessage,
+    },
+    dev1_digitizer_event_v1_generated::{
+        digitizer_event_list_message_buffer_has_identifier, root_as_digitizer_event_list_message,
+        DigitizerEventListMessage,
+    },
+    ecs_6s4t_run_stop_generated::{root_as_run_stop, run_stop_buffer_has_identifier},
+    ecs_pl72_run_start_generated::{root_as_run_start, run_start_buffer_has_identifier},
+    frame_metadata_v1_generated::FrameMetadataV1,
+};
+use tracing::{debug, error, info};
+
+/// Formats in which the messages can be written to a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Each message in turn, prefixed by its length as a little endian 32 bit integer
+    #[default]
+    Dump,
+    /// HDF5 file in the layout of stream-to-file, holding either traces or events
+    Hdf5,
+}
+
+#[derive(Clone, Parser)]
+pub(crate) struct OutputOptions {
+    /// File to which the messages are written, instead of being published to the Kafka broker
+    #[clap(long)]
+    pub(crate) output_file: Option<PathBuf>,
+
+    /// Format of the output file
+    #[clap(long, value_enum, default_value_t = OutputFormat::Dump)]
+    output_format: OutputFormat,
+}
+
+/// Where the messages of the simulator are sent.
+pub(crate) enum Output {
+    Kafka(FutureProducer),
+    Dump(BufWriter<fs::File>),
+    Hdf5(Hdf5File),
+}
+
+impl OutputOptions {
+    pub(crate) fn is_hdf5(&self) -> bool {
+        self.output_file.is_some() && self.output_format == OutputFormat::Hdf5
+    }
+}
+
+impl Output {
+    /// Creates the output file given by the options, or connects to the Kafka broker if there is none.
+    /// As an HDF5 file holds either traces or events, `traces` gives which of them it holds.
+    pub(crate) fn new(
+        options: &OutputOptions,
+        traces: bool,
+        producer: impl FnOnce() -> Result<FutureProducer>,
+    ) -> Result<Self> {
+        Ok(match &options.output_file {
+            None => Self::Kafka(producer()?),
+            Some(path) => match options.output_format {
+                OutputFormat::Dump => Self::Dump(BufWriter::new(fs::File::create(path)?)),
+                OutputFormat::Hdf5 => Self::Hdf5(Hdf5File::create(path, traces)?),
+            },
+        })
+    }
+
+    pub(crate) fn is_file(&self) -> bool {
+        !matches!(self, Self::Kafka(_))
+    }
+
+    /// Sends a message the given number of times. The topic and key are only used by Kafka,
+    /// and a message without a topic is only written to files.
+    pub(crate) async fn send(
+        &mut self,
+        topic: Option<&str>,
+        key: &str,
+        payload: &[u8],
+        copies: usize,
+    ) -> Result<()> {
+        match self {
+            Self::Kafka(producer) => {
+                if let Some(topic) = topic {
+                    publish(producer, topic, key, payload, copies).await;
+                }
+            }
+            Self::Dump(file) => {
+                for _ in 0..copies {
+                    file.write_all(&(payload.len() as u32).to_le_bytes())?;
+                    file.write_all(payload)?;
+                }
+                file.flush()?;
+            }
+            // As stream-to-file records each frame once, duplicated messages are written once
+            Self::Hdf5(file) if copies > 0 => file.write(payload)?,
+            Self::Hdf5(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Publishes a message the given number of times.
+async fn publish(producer: &FutureProducer, topic: &str, key: &str, payload: &[u8], copies: usize) {
+    if copies == 0 {
+        debug!("Dropped message with key {key} to {topic}");
+    }
+    for _ in 0..copies {
+        let start_time = SystemTime::now();
+
+        match producer
+            .send(
+                FutureRecord::to(topic).payload(payload).key(key),
+                Timeout::After(Duration::from_millis(100)),
+            )
+            .await
+        {
+            Ok(r) => debug!("Delivery: {:?}", r),
+            Err(e) => error!("Delivery failed: {:?}", e),
+        };
+
+        info!(
+            "Send to {topic} took: {:?}",
+            SystemTime::now().duration_since(start_time).unwrap()
+        );
+    }
+}
+
+fn new_dataset<T: H5Type>(file: &File, name: &str) -> Result<Dataset> {
+    Ok(file.new_dataset::<T>().shape((0..,)).create(name)?)
+}
+
+fn append<T: H5Type>(dataset: &Dataset, values: &[T]) -> Result<()> {
+    let len = dataset.shape()[0];
+    dataset.resize((len + values.len(),))?;
+    dataset.write_slice(values, s![len..len + values.len()])?;
+    Ok(())
+}
+
+/// Inserts values at the given index of a one dimensional dataset, moving the later values.
+fn insert<T: H5Type>(dataset: &Dataset, index: usize, values: &[T]) -> Result<()> {
+    let len = dataset.shape()[0];
+    let rest = dataset.read_slice_1d::<T, _>(s![index..len])?;
+    dataset.resize((len + values.len(),))?;
+    dataset.write_slice(values, s![index..index + values.len()])?;
+    dataset.write_slice(&rest, s![index + values.len()..])?;
+    Ok(())
+}
+
+fn write_string(file: &File, name: &str, value: &str) -> Result<()> {
+    file.new_dataset::<VarLenUnicode>()
+        .create(name)?
+        .write_scalar(&VarLenUnicode::from_str(value)?)?;
+    Ok(())
+}
+
+fn milliseconds_to_time(milliseconds: u64) -> Result<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(milliseconds as i64)
+        .single()
+        .ok_or(anyhow!("invalid time of {milliseconds} ms"))
+}
+
+/// Identifies a frame by its number and timestamp, as stream-to-file does.
+type FrameKey = (FrameNumber, DateTime<Utc>);
+
+fn frame_key(metadata: &FrameMetadataV1) -> Result<FrameKey> {
+    let timestamp = *metadata
+        .timestamp()
+        .ok_or(anyhow!("frame has no timestamp"))?;
+    Ok((metadata.frame_number(), timestamp.into()))
+}
+
+/// The datasets giving the timestamp, number and start of each frame in an HDF5 file.
+struct FrameDatasets {
+    timestamp_seconds: Dataset,
+    timestamp_nanoseconds: Dataset,
+    number: Dataset,
+    start_index: Dataset,
+    /// Key and start of each frame written
+    frames: Vec<(FrameKey, usize)>,
+}
+
+impl FrameDatasets {
+    fn create(file: &File) -> Result<Self> {
+        Ok(Self {
+            timestamp_seconds: new_dataset::<u64>(file, "frame_timestamp/seconds")?,
+            timestamp_nanoseconds: new_dataset::<u32>(file, "frame_timestamp/nanoseconds")?,
+            number: new_dataset::<FrameNumber>(file, "frame_number")?,
+            start_index: new_dataset::<u32>(file, "frame_start_index")?,
+            frames: Vec::new(),
+        })
+    }
+
+    /// Index of the frame with the given key, searching from the latest frame, as messages
+    /// are rarely far behind.
+    fn find(&self, key: FrameKey) -> Option<usize> {
+        self.frames.iter().rposition(|(frame, _)| *frame == key)
+    }
+
+    fn start(&self, index: usize) -> usize {
+        self.frames[index].1
+    }
+
+    /// The start of the frame after the given frame, if any.
+    fn end(&self, index: usize) -> Option<usize> {
+        self.frames.get(index + 1).map(|&(_, start)| start)
+    }
+
+    /// Appends a frame, returning its index.
+    fn push(&mut self, key: FrameKey, start: usize) -> Result<usize> {
+        let (number, timestamp) = key;
+        append(&self.timestamp_seconds, &[timestamp.timestamp() as u64])?;
+        append(
+            &self.timestamp_nanoseconds,
+            &[timestamp.timestamp_subsec_nanos()],
+        )?;
+        append(&self.number, &[number])?;
+        append(&self.start_index, &[start as u32])?;
+        self.frames.push((key, start));
+        Ok(self.frames.len() - 1)
+    }
+
+    /// Moves the starts of the frames after the given frame by the number of values inserted
+    /// at its end.
+    fn shift_after(&mut self, index: usize, inserted: usize) -> Result<()> {
+        let later = &mut self.frames[index + 1..];
+        if later.is_empty() {
+            return Ok(());
+        }
+        for (_, start) in later.iter_mut() {
+            *start += inserted;
+        }
+        let starts: Vec<u32> = later.iter().map(|&(_, start)| start as u32).collect();
+        self.start_index.write_slice(&starts, s![index + 1..])?;
+        Ok(())
+    }
+}
+
+/// The datasets of an HDF5 file specific to traces or to events.
+enum Data {
+    /// Traces in the layout of stream-to-file, with one row of `detector_data` per channel of
+    /// each digitiser, and one entry of the frame datasets per frame.
+    Traces {
+        sample_rate: Dataset,
+        detector_data: Dataset,
+    },
+    /// Events in the layout of the event files of stream-to-file, with the events of every
+    /// digitiser in a frame following each other, and one entry of the frame datasets per frame.
+    Events {
+        time: Dataset,
+        voltage: Dataset,
+        channel: Dataset,
+    },
+}
+
+/// Writes the messages of the simulator to an HDF5 file, as stream-to-file would.
+pub(crate) struct Hdf5File {
+    file: File,
+    frames: FrameDatasets,
+    data: Data,
+}
+
+impl Hdf5File {
+    fn create(path: &Path, traces: bool) -> Result<Self> {
+        let file = File::create(path)?;
+        let data = if traces {
+            let sample_rate = file.new_dataset::<SampleRate>().create("sample_rate")?;
+            sample_rate.write_scalar(&0)?;
+            Data::Traces {
+                sample_rate,
+                detector_data: file
+                    .new_dataset::<Intensity>()
+                    .chunk((CHANNELS_PER_DIGITIZER, 1024))
+                    .shape((0.., 0..))
+                    .create("detector_data")?,
+            }
+        } else {
+            Data::Events {
+                time: new_dataset::<u32>(&file, "event_data/time")?,
+                voltage: new_dataset::<u32>(&file, "event_data/voltage")?,
+                channel: new_dataset::<u32>(&file, "event_data/channel")?,
+            }
+        };
+        Ok(Self {
+            frames: FrameDatasets::create(&file)?,
+            data,
+            file,
+        })
+    }
+
+    /// Writes the datasets of a message, which the file must be able to hold.
+    /// Only the first run start and run stop are recorded.
+    fn write(&mut self, payload: &[u8]) -> Result<()> {
+        if digitizer_analog_trace_message_buffer_has_identifier(payload) {
+            self.push_trace(&root_as_digitizer_analog_trace_message(payload)?)?;
+        } else if digitizer_event_list_message_buffer_has_identifier(payload) {
+            self.push_events(&root_as_digitizer_event_list_message(payload)?)?;
+        } else if run_start_buffer_has_identifier(payload) {
+            if !self.file.link_exists("run") {
+                let run_start = root_as_run_start(payload)?;
+                let start_time = milliseconds_to_time(run_start.start_time())?;
+                write_string(
+                    &self.file,
+                    "run/name",
+                    run_start.run_name().unwrap_or_default(),
+                )?;
+                write_string(&self.file, "run/start_time", &start_time.to_rfc3339())?;
+            }
+        } else if run_stop_buffer_has_identifier(payload) {
+            if !self.file.link_exists("run/stop_time") {
+                let stop_time = milliseconds_to_time(root_as_run_stop(payload)?.stop_time())?;
+                write_string(&self.file, "run/stop_time", &stop_time.to_rfc3339())?;
+            }
+        } else {
+            return Err(anyhow!("message of unknown type"));
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Writes the traces of a digitiser alongside those of the other digitisers in the same frame,
+    /// which may be an earlier frame if the message is late.
+    fn push_trace(&mut self, message: &DigitizerAnalogTraceMessage) -> Result<()> {
+        let Data::Traces {
+            sample_rate,
+            detector_data,
+        } = &self.data
+        else {
+            return Err(anyhow!("HDF5 file of events cannot hold traces"));
+        };
+        let metadata = message.metadata();
+
+        if sample_rate.read_scalar::<SampleRate>()? == 0 {
+            sample_rate.write_scalar(&message.sample_rate())?;
+        }
+
+        let [rows, width] = detector_data.shape()[..] else {
+            unreachable!("detector_data should be two dimensional");
+        };
+        let key = frame_key(&metadata)?;
+        let start = match self.frames.find(key) {
+            Some(index) => self.frames.start(index),
+            None => {
+                self.frames.push(key, width)?;
+                width
+            }
+        };
+
+        let channels = message.channels().ok_or(anyhow!("trace has no channels"))?;
+        let length = channels
+            .iter()
+            .map(|channel| {
+                channel
+                    .voltage()
+                    .map(|voltage| voltage.len())
+                    .unwrap_or_default()
+            })
+            .max()
+            .unwrap_or_default();
+        let first = channel_index(message.digitizer_id() as usize, 0);
+        detector_data.resize((
+            rows.max(first + CHANNELS_PER_DIGITIZER),
+            width.max(start + length),
+        ))?;
+
+        let mut values = Array2::<Intensity>::zeros((CHANNELS_PER_DIGITIZER, length));
+        for channel in channels {
+            let mut row = values.row_mut(channel.channel() as usize);
+            for (value, voltage) in row.iter_mut().zip(channel.voltage().into_iter().flatten()) {
+                *value = voltage;
+            }
+        }
+        detector_data.write_slice(
+            &values,
+            s![first..first + CHANNELS_PER_DIGITIZER, start..start + length],
+        )?;
+        Ok(())
+    }
+
+    /// Writes the events of a digitiser after those of the other digitisers in the same frame,
+    /// which may be an earlier frame if the message is late.
+    fn push_events(&mut self, message: &DigitizerEventListMessage) -> Result<()> {
+        let Data::Events {
+            time,
+            voltage,
+            channel,
+        } = &self.data
+        else {
+            return Err(anyhow!("HDF5 file of traces cannot hold events"));
+        };
+        let len = time.shape()[0];
+        let key = frame_key(&message.metadata())?;
+        let (index, end) = match self.frames.find(key) {
+            Some(index) => {
+                let end = self.frames.end(index).unwrap_or(len);
+                (index, end)
+            }
+            None => (self.frames.push(key, len)?, len),
+        };
+
+        let times: Vec<_> = message.time().into_iter().flatten().collect();
+        insert(time, end, &times)?;
+        insert(
+            voltage,
+            end,
+            &message
+                .voltage()
+                .into_iter()
+                .flatten()
+                .map(u32::from)
+                .collect::<Vec<_>>(),
+        )?;
+        insert(
+            channel,
+            end,
+            &message.channel().into_iter().flatten().collect::<Vec<_>>(),
+        )?;
+        self.frames.shift_after(index, times.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{send, Cli, Frame, Simulation};
+    use std::env;
+
+    /// A path in the temporary directory unique to the test and process.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("simulator_output_{}_{name}", std::process::id()))
+    }
+
+    /// Writes three frames of two digitisers to the output file given by the arguments.
+    async fn write(path: &Path, args: &[&str]) {
+        let cli = Cli::parse_from(
+            [
+                "simulator",
+                "--did",
+                "1,3",
+                "--time-bins",
+                "100",
+                "--events",
+                "5",
+                "--drop",
+                "3:1",
+            ]
+            .into_iter()
+            .chain(["--output-file", path.to_str().unwrap()])
+            .chain(args.iter().copied())
+            .chain(["single"]),
+        );
+        let mut simulation = Simulation::new(&cli).unwrap();
+        let mut output = Output::new(&cli.output, cli.trace_topic.is_some(), || {
+            Err(anyhow!("Kafka should not be used"))
+        })
+        .unwrap();
+        for number in 0..3 {
+            let frame = Frame::new(&cli, number, Duration::from_millis(20 * number as u64));
+            send(&mut output, &cli, &mut simulation, &frame)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn dump() {
+        let path = temp_path("dump.bin");
+        write(
+            &path,
+            &["--trace-topic", "Traces", "--event-topic", "Events"],
+        )
+        .await;
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut messages = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let length = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            messages.push(&rest[4..4 + length]);
+            rest = &rest[4 + length..];
+        }
+        // An event and a trace message of each digitiser, except the dropped one
+        assert_eq!(messages.len(), 2 * 2 * 3 - 2);
+        assert!(digitizer_event_list_message_buffer_has_identifier(
+            messages[0]
+        ));
+        let trace = root_as_digitizer_analog_trace_message(messages[1]).unwrap();
+        assert_eq!(trace.digitizer_id(), 1);
+        assert_eq!(trace.channels().unwrap().len(), CHANNELS_PER_DIGITIZER);
+    }
+
+    #[tokio::test]
+    async fn hdf5_traces() {
+        let path = temp_path("traces.h5");
+        write(
+            &path,
+            &["--trace-topic", "Traces", "--output-format", "hdf5"],
+        )
+        .await;
+        let file = File::open(&path).unwrap();
+        let _ = fs::remove_file(path);
+
+        assert_eq!(
+            file.dataset("sample_rate")
+                .unwrap()
+                .read_scalar::<SampleRate>()
+                .unwrap(),
+            1_000_000_000
+        );
+        assert_eq!(
+            file.dataset("frame_number")
+                .unwrap()
+                .read_raw::<FrameNumber>()
+                .unwrap(),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            file.dataset("frame_start_index")
+                .unwrap()
+                .read_raw::<u32>()
+                .unwrap(),
+            [0, 100, 200]
+        );
+
+        let detector_data = file.dataset("detector_data").unwrap();
+        assert_eq!(detector_data.shape(), [4 * CHANNELS_PER_DIGITIZER, 300]);
+        let data = detector_data.read_2d::<Intensity>().unwrap();
+        // Digitiser 3 was dropped from frame 1, and digitisers 0 and 2 are not emulated
+        assert!(data.slice(s![8..16, ..]).iter().all(|&value| value > 0));
+        assert!(data
+            .slice(s![24..32, 100..200])
+            .iter()
+            .all(|&value| value == 0));
+        assert!(data.slice(s![24..32, 200..]).iter().all(|&value| value > 0));
+        assert!(data.slice(s![..8, ..]).iter().all(|&value| value == 0));
+    }
+
+    #[tokio::test]
+    async fn hdf5_events() {
+        let path = temp_path("events.h5");
+        write(
+            &path,
+            &["--event-topic", "Events", "--output-format", "hdf5"],
+        )
+        .await;
+        let file = File::open(&path).unwrap();
+        let _ = fs::remove_file(path);
+
+        assert!(!file.link_exists("detector_data"));
+        assert!(!file.link_exists("digitiser_id"));
+        assert_eq!(
+            file.dataset("frame_number")
+                .unwrap()
+                .read_raw::<FrameNumber>()
+                .unwrap(),
+            [0, 1, 2]
+        );
+        for name in [
+            "event_data/time",
+            "event_data/voltage",
+            "event_data/channel",
+        ] {
+            assert_eq!(
+                file.dataset(name)
+                    .unwrap()
+                    .dtype()
+                    .unwrap()
+                    .to_descriptor()
+                    .unwrap(),
+                u32::type_descriptor()
+            );
+        }
+        let starts = file
+            .dataset("frame_start_index")
+            .unwrap()
+            .read_raw::<u32>()
+            .unwrap();
+        let events = file.dataset("event_data/time").unwrap().shape()[0];
+        assert!(starts.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(*starts.last().unwrap() as usize <= events);
+    }
+
+    /// The time, voltage and channel of an event.
+    type Event = (u32, u32, u32);
+
+    /// The frame numbers, timestamps in seconds and sorted events of each frame of an HDF5 file
+    /// of events.
+    fn read_frames(path: &Path) -> (Vec<FrameNumber>, Vec<u64>, Vec<Vec<Event>>) {
+        let file = File::open(path).unwrap();
+        let read = |name| file.dataset(name).unwrap().read_raw::<u32>().unwrap();
+        let (time, voltage, channel) = (
+            read("event_data/time"),
+            read("event_data/voltage"),
+            read("event_data/channel"),
+        );
+        let mut ends = read("frame_start_index");
+        ends.push(time.len() as u32);
+        let events = ends
+            .windows(2)
+            .map(|pair| {
+                let mut events: Vec<_> = (pair[0] as usize..pair[1] as usize)
+                    .map(|i| (time[i], voltage[i], channel[i]))
+                    .collect();
+                events.sort();
+                events
+            })
+            .collect();
+        (
+            read("frame_number"),
+            file.dataset("frame_timestamp/seconds")
+                .unwrap()
+                .read_raw::<u64>()
+                .unwrap(),
+            events,
+        )
+    }
+
+    #[tokio::test]
+    async fn hdf5_delayed() {
+        let args = [
+            "--seed",
+            "2",
+            "--start-time",
+            "2024-01-01T00:00:00Z",
+            "--output-format",
+            "hdf5",
+        ];
+        let delayed_args = [&args[..], &["--delay", "1:0:1"]].concat();
+
+        let path = temp_path("on_time_events.h5");
+        write(&path, &[&args[..], &["--event-topic", "Events"]].concat()).await;
+        let on_time = read_frames(&path);
+        let _ = fs::remove_file(path);
+
+        // The events of digitiser 1 in frame 0 arrive after those of frame 1, but join frame 0
+        let path = temp_path("delayed_events.h5");
+        write(
+            &path,
+            &[&delayed_args[..], &["--event-topic", "Events"]].concat(),
+        )
+        .await;
+        let delayed = read_frames(&path);
+        let _ = fs::remove_file(path);
+        assert_eq!(delayed.0, [0, 1, 2]);
+        // With this seed, frame 0 has events to be delayed
+        assert!(!on_time.2[0].is_empty());
+        assert_eq!(delayed, on_time);
+
+        let path = temp_path("on_time_traces.h5");
+        write(&path, &[&args[..], &["--trace-topic", "Traces"]].concat()).await;
+        let file = File::open(&path).unwrap();
+        let on_time = file
+            .dataset("detector_data")
+            .unwrap()
+            .read_2d::<Intensity>()
+            .unwrap();
+        let _ = fs::remove_file(path);
+
+        let path = temp_path("delayed_traces.h5");
+        write(
+            &path,
+            &[&delayed_args[..], &["--trace-topic", "Traces"]].concat(),
+        )
+        .await;
+        let file = File::open(&path).unwrap();
+        let _ = fs::remove_file(path);
+        assert_eq!(
+            file.dataset("frame_number")
+                .unwrap()
+                .read_raw::<FrameNumber>()
+                .unwrap(),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            file.dataset("frame_start_index")
+                .unwrap()
+                .read_raw::<u32>()
+                .unwrap(),
+            [0, 100, 200]
+        );
+        assert_eq!(
+            file.dataset("detector_data")
+                .unwrap()
+                .read_2d::<Intensity>()
+                .unwrap(),
+            on_time
+        );
+    }
+}
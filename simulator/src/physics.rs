@@ -0,0 +1,372 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use rand::Rng;
+use rand_distr::{Distribution, Exp, Normal, Poisson};
+use serde::Deserialize;
+use std::{
+    f64::consts::PI,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use supermusr_common::{Channel, DigitizerId, FrameNumber, Intensity, CHANNELS_PER_DIGITIZER};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PulseShape {
+    /// Rises exponentially with the rise time and decays exponentially with the decay time, starting at the time of the event
+    BiExponential,
+    /// Gaussian centred on the time of the event, with the rise time as its standard deviation
+    Gaussian,
+}
+
+#[derive(Clone, Parser)]
+pub(crate) struct PhysicsOptions {
+    /// Mean lifetime of the muons in nanoseconds
+    #[clap(long, default_value = "2197")]
+    muon_lifetime: f64,
+
+    /// Asymmetry of the decay, between 0 and 1
+    #[clap(long, default_value = "0")]
+    asymmetry: f64,
+
+    /// Frequency in MHz at which the muon spins precess
+    #[clap(long, default_value = "0")]
+    precession_frequency: f64,
+
+    /// Time in nanoseconds from the start of each frame at which the muons arrive
+    #[clap(long, default_value = "0")]
+    muon_arrival_time: f64,
+
+    /// Shape of the pulse of each event
+    #[clap(long, value_enum, default_value_t = PulseShape::BiExponential)]
+    pulse_shape: PulseShape,
+
+    /// Rise time of the pulses in nanoseconds
+    #[clap(long, default_value = "2")]
+    pulse_rise_time: f64,
+
+    /// Decay time of bi-exponential pulses in nanoseconds
+    #[clap(long, default_value = "10")]
+    pulse_decay_time: f64,
+
+    /// Mean height of the pulses, negative for pulses below the baseline
+    #[clap(long, default_value = "200", allow_negative_numbers = true)]
+    pulse_amplitude: f64,
+
+    /// Standard deviation of the heights of the pulses
+    #[clap(long, default_value = "20")]
+    pulse_amplitude_spread: f64,
+
+    /// Value of the traces without pulses or noise
+    #[clap(long, default_value = "1000")]
+    baseline: f64,
+
+    /// Standard deviation of the Gaussian noise added to each sample
+    #[clap(long, default_value = "5")]
+    noise: f64,
+}
+
+/// Parameters of the events and traces which a scenario step can change,
+/// each replacing the value given on the command line if set.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PhysicsOverrides {
+    muon_lifetime: Option<f64>,
+    asymmetry: Option<f64>,
+    precession_frequency: Option<f64>,
+    muon_arrival_time: Option<f64>,
+    pulse_shape: Option<PulseShape>,
+    pulse_rise_time: Option<f64>,
+    pulse_decay_time: Option<f64>,
+    pulse_amplitude: Option<f64>,
+    pulse_amplitude_spread: Option<f64>,
+    baseline: Option<f64>,
+    noise: Option<f64>,
+}
+
+/// A muon decay detected in a channel, which gives a pulse in the trace of the channel.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TrueEvent {
+    pub(crate) channel: Channel,
+    /// Time in nanoseconds from the start of the frame
+    pub(crate) time: f64,
+    pub(crate) amplitude: f64,
+}
+
+impl PhysicsOptions {
+    /// Returns these options with the given overrides applied.
+    pub(crate) fn with(&self, overrides: &PhysicsOverrides) -> Self {
+        Self {
+            muon_lifetime: overrides.muon_lifetime.unwrap_or(self.muon_lifetime),
+            asymmetry: overrides.asymmetry.unwrap_or(self.asymmetry),
+            precession_frequency: overrides
+                .precession_frequency
+                .unwrap_or(self.precession_frequency),
+            muon_arrival_time: overrides
+                .muon_arrival_time
+                .unwrap_or(self.muon_arrival_time),
+            pulse_shape: overrides.pulse_shape.unwrap_or(self.pulse_shape),
+            pulse_rise_time: overrides.pulse_rise_time.unwrap_or(self.pulse_rise_time),
+            pulse_decay_time: overrides.pulse_decay_time.unwrap_or(self.pulse_decay_time),
+            pulse_amplitude: overrides.pulse_amplitude.unwrap_or(self.pulse_amplitude),
+            pulse_amplitude_spread: overrides
+                .pulse_amplitude_spread
+                .unwrap_or(self.pulse_amplitude_spread),
+            baseline: overrides.baseline.unwrap_or(self.baseline),
+            noise: overrides.noise.unwrap_or(self.noise),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.asymmetry) {
+            return Err(anyhow!("asymmetry must be between 0 and 1"));
+        }
+        for (name, value) in [
+            ("muon-lifetime", self.muon_lifetime),
+            ("pulse-rise-time", self.pulse_rise_time),
+            ("pulse-decay-time", self.pulse_decay_time),
+        ] {
+            if value <= 0.0 {
+                return Err(anyhow!("{name} must be positive"));
+            }
+        }
+        if matches!(self.pulse_shape, PulseShape::BiExponential)
+            && self.pulse_rise_time >= self.pulse_decay_time
+        {
+            return Err(anyhow!(
+                "pulse-rise-time must be less than pulse-decay-time for bi-exponential pulses"
+            ));
+        }
+        if self.pulse_amplitude_spread < 0.0 || self.noise < 0.0 {
+            return Err(anyhow!(
+                "pulse-amplitude-spread and noise must not be negative"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Draws the time after its arrival at which a muon decays, given the decay is detected in the channel.
+    ///
+    /// The channels are taken to be equally spaced around the sample, so the precession of the
+    /// spins is seen in each channel with a phase given by its position.
+    fn decay_time(&self, rng: &mut impl Rng, channel: Channel) -> f64 {
+        let lifetime = Exp::new(1.0 / self.muon_lifetime).expect("lifetime should be validated");
+        let phase = 2.0 * PI * channel as f64 / CHANNELS_PER_DIGITIZER as f64;
+        let angular_frequency = 2.0 * PI * self.precession_frequency * 1e-3;
+        loop {
+            let time = lifetime.sample(rng);
+            let weight = 1.0 + self.asymmetry * (angular_frequency * time + phase).cos();
+            if rng.gen::<f64>() * (1.0 + self.asymmetry) < weight {
+                return time;
+            }
+        }
+    }
+
+    /// Draws the events of a single frame, the number of which is Poisson distributed with the given mean.
+    /// Events after the end of the frame are discarded.
+    /// The events are ordered by channel and then by time.
+    pub(crate) fn events(
+        &self,
+        rng: &mut impl Rng,
+        mean_events: f64,
+        frame_length: usize,
+    ) -> Vec<TrueEvent> {
+        let count = match Poisson::new(mean_events) {
+            Ok(poisson) => poisson.sample(rng) as usize,
+            Err(_) => 0,
+        };
+        let amplitude = Normal::new(self.pulse_amplitude, self.pulse_amplitude_spread)
+            .expect("amplitude spread should be validated");
+
+        let mut events: Vec<_> = (0..count)
+            .map(|_| {
+                let channel = rng.gen_range(0..CHANNELS_PER_DIGITIZER) as Channel;
+                TrueEvent {
+                    channel,
+                    time: self.muon_arrival_time + self.decay_time(rng, channel),
+                    amplitude: amplitude.sample(rng),
+                }
+            })
+            .filter(|event| event.time < frame_length as f64)
+            .collect();
+        events.sort_by(|a, b| a.channel.cmp(&b.channel).then(a.time.total_cmp(&b.time)));
+        events
+    }
+
+    /// Value of a pulse of unit height, at a time in nanoseconds after the time of its event.
+    fn pulse(&self, time: f64) -> f64 {
+        match self.pulse_shape {
+            PulseShape::BiExponential if time < 0.0 => 0.0,
+            PulseShape::BiExponential => {
+                let (rise, decay) = (self.pulse_rise_time, self.pulse_decay_time);
+                let shape = |time: f64| (-time / decay).exp() - (-time / rise).exp();
+                let peak_time = rise * decay * (decay / rise).ln() / (decay - rise);
+                shape(time) / shape(peak_time)
+            }
+            PulseShape::Gaussian => (-0.5 * (time / self.pulse_rise_time).powi(2)).exp(),
+        }
+    }
+
+    /// Times relative to the time of its event outside which a pulse is negligible.
+    fn pulse_extent(&self) -> (f64, f64) {
+        match self.pulse_shape {
+            PulseShape::BiExponential => (0.0, 10.0 * self.pulse_decay_time),
+            PulseShape::Gaussian => (-5.0 * self.pulse_rise_time, 5.0 * self.pulse_rise_time),
+        }
+    }
+
+    /// Generates the trace of a channel, with one sample per nanosecond, by adding the pulses
+    /// of the events of the channel and noise to the baseline. Pulses which overlap pile up.
+    pub(crate) fn trace(
+        &self,
+        rng: &mut impl Rng,
+        events: &[TrueEvent],
+        channel: Channel,
+        length: usize,
+    ) -> Vec<Intensity> {
+        let noise = Normal::new(0.0, self.noise).expect("noise should be validated");
+        let mut values: Vec<f64> = (0..length)
+            .map(|_| self.baseline + noise.sample(rng))
+            .collect();
+
+        let (before, after) = self.pulse_extent();
+        for event in events.iter().filter(|event| event.channel == channel) {
+            let first = (event.time + before).ceil().max(0.0) as usize;
+            let last = (event.time + after).floor().max(0.0) as usize;
+            for (sample, value) in values.iter_mut().enumerate().take(last + 1).skip(first) {
+                *value += event.amplitude * self.pulse(sample as f64 - event.time);
+            }
+        }
+
+        values
+            .into_iter()
+            .map(|value| value.round().clamp(0.0, Intensity::MAX as f64) as Intensity)
+            .collect()
+    }
+}
+
+/// Writes the true events of each frame as CSV, in the format read by `trace-to-events scan`.
+pub(crate) struct TruthFile(BufWriter<File>);
+
+impl TruthFile {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "digitiser_id,frame_number,channel,time,amplitude")?;
+        Ok(Self(file))
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        digitiser_id: DigitizerId,
+        frame_number: FrameNumber,
+        events: &[TrueEvent],
+    ) -> Result<()> {
+        for event in events {
+            writeln!(
+                self.0,
+                "{digitiser_id},{frame_number},{},{},{}",
+                event.channel, event.time, event.amplitude
+            )?;
+        }
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn options(args: &[&str]) -> PhysicsOptions {
+        PhysicsOptions::parse_from(std::iter::once("simulator").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn validation() {
+        assert!(options(&[]).validate().is_ok());
+        assert!(options(&["--asymmetry", "1.5"]).validate().is_err());
+        assert!(options(&["--muon-lifetime", "0"]).validate().is_err());
+        assert!(options(&["--pulse-rise-time", "20"]).validate().is_err());
+        assert!(
+            options(&["--pulse-rise-time", "20", "--pulse-shape", "gaussian"])
+                .validate()
+                .is_ok()
+        );
+        assert!(options(&["--noise=-1"]).validate().is_err());
+    }
+
+    #[test]
+    fn decay() {
+        let options = options(&["--muon-arrival-time", "100"]);
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let events = options.events(&mut rng, 100_000.0, 1_000_000);
+
+        let mean = events.iter().map(|event| event.time - 100.0).sum::<f64>() / events.len() as f64;
+        assert!((mean - 2197.0).abs() < 50.0);
+        assert!(events.iter().all(|event| event.time >= 100.0));
+        assert!(events
+            .windows(2)
+            .all(|pair| (pair[0].channel, pair[0].time) <= (pair[1].channel, pair[1].time)));
+
+        // Events are discarded after the end of the frame
+        let events = options.events(&mut rng, 1000.0, 2000);
+        assert!(events.iter().all(|event| event.time < 2000.0));
+    }
+
+    #[test]
+    fn precession() {
+        // With full asymmetry, channel 0 sees no decays half a precession period after arrival
+        let options = options(&["--asymmetry", "1", "--precession-frequency", "10"]);
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let events = options.events(&mut rng, 100_000.0, 1_000_000);
+        let count = |from: f64| {
+            events
+                .iter()
+                .filter(|event| {
+                    event.channel == 0 && event.time >= from && event.time < from + 10.0
+                })
+                .count()
+        };
+        assert!(count(0.0) > 10 * count(45.0));
+    }
+
+    #[test]
+    fn pulses() {
+        let options = options(&["--noise", "0", "--pulse-amplitude", "-100"]);
+        let events = vec![
+            TrueEvent {
+                channel: 1,
+                time: 10.0,
+                amplitude: -100.0,
+            },
+            TrueEvent {
+                channel: 1,
+                time: 12.0,
+                amplitude: -100.0,
+            },
+            TrueEvent {
+                channel: 2,
+                time: 10.0,
+                amplitude: -100.0,
+            },
+        ];
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let trace = options.trace(&mut rng, &events, 1, 200);
+
+        assert_eq!(trace.len(), 200);
+        assert!(trace[..=10].iter().all(|&value| value == 1000));
+        assert!(trace[199] == 1000);
+        // The pulses pile up beyond the height of each
+        let lowest = *trace.iter().min().unwrap();
+        assert!(lowest < 900);
+
+        let trace = options.trace(&mut rng, &events, 3, 200);
+        assert!(trace.iter().all(|&value| value == 1000));
+
+        let peak = options.pulse(2.0 * 10.0 * (10.0f64 / 2.0).ln() / 8.0);
+        assert!((peak - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{
+    output::Output,
+    physics::{PhysicsOptions, PhysicsOverrides},
+    send, send_delayed, Cli, Frame, Simulation,
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Deserialize;
+use std::{fs, path::PathBuf, time::Duration};
+use supermusr_common::{kafka_key::RUN_KEY, DigitizerId, FrameNumber};
+use supermusr_streaming_types::{
+    ecs_6s4t_run_stop_generated::{finish_run_stop_buffer, RunStop, RunStopArgs},
+    ecs_pl72_run_start_generated::{finish_run_start_buffer, RunStart, RunStartArgs},
+    flatbuffers::FlatBufferBuilder,
+};
+use tokio::time::{self, Instant};
+use tracing::info;
+
+#[derive(Clone, Parser)]
+pub(crate) struct ScenarioArgs {
+    /// Scenario file, as YAML or JSON. See README.md.
+    #[clap(long)]
+    file: PathBuf,
+
+    /// Topic to publish run start and run stop messages to, which is not needed when writing to a file
+    #[clap(long)]
+    control_topic: Option<String>,
+}
+
+/// A group of consecutive frames.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Frames {
+    /// Number of frames
+    count: u32,
+    /// Frames per second
+    rate: f64,
+    /// Mean number of decays in each frame of each digitiser, before those after the end of the
+    /// frame are discarded, instead of `--events`
+    #[serde(default)]
+    events: Option<f64>,
+    /// Parameters of the events and traces, instead of those given on the command line
+    #[serde(default)]
+    physics: PhysicsOverrides,
+}
+
+/// A step of the timeline of a scenario, taken in the order given.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Step {
+    RunStart {
+        run_name: String,
+        instrument_name: String,
+    },
+    RunStop {
+        run_name: String,
+    },
+    Frames(Frames),
+    /// Sets the period number of subsequent frames
+    Period(u64),
+    /// Sets the veto flags of subsequent frames, repeating the pattern each frame
+    VetoFlags(Vec<u16>),
+    /// Stops the given digitisers publishing messages
+    Dropout(Vec<DigitizerId>),
+    /// Resumes publishing of messages by the given digitisers
+    Restore(Vec<DigitizerId>),
+    /// Waits for the given number of milliseconds
+    Wait(u64),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    /// Digitisers to emulate, instead of `--did`
+    #[serde(default)]
+    digitisers: Option<Vec<DigitizerId>>,
+    /// Number of the first frame
+    #[serde(default)]
+    start_frame: FrameNumber,
+    /// Each step is a map of its name to its parameters
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    timeline: Vec<Step>,
+}
+
+/// What the simulator does at each point of a scenario.
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    RunStart {
+        run_name: String,
+        instrument_name: String,
+        /// Time at which the run starts, since the start of the scenario
+        offset: Duration,
+    },
+    RunStop {
+        run_name: String,
+        offset: Duration,
+    },
+    /// Publishes the messages of a frame, then waits for the given time before the next action
+    Frame(Frame, Duration),
+    Wait(Duration),
+}
+
+impl Scenario {
+    /// Parses a scenario given as YAML or JSON, JSON being a subset of YAML.
+    fn parse(text: &str) -> Result<Self> {
+        let scenario: Self = serde_yaml::from_str(text)?;
+        for step in &scenario.timeline {
+            if let Step::Frames(frames) = step {
+                if frames.rate <= 0.0 || Duration::try_from_secs_f64(1.0 / frames.rate).is_err() {
+                    return Err(anyhow!(
+                        "frame rate must be positive, and large enough that the interval between frames can be represented"
+                    ));
+                }
+            }
+            if let Step::VetoFlags(pattern) = step {
+                if pattern.is_empty() {
+                    return Err(anyhow!("veto flag pattern must not be empty"));
+                }
+            }
+        }
+        Ok(scenario)
+    }
+
+    /// Checks the parameters of the events and traces of each step, given those of the command line.
+    fn validate_physics(&self, physics: &PhysicsOptions) -> Result<()> {
+        for step in &self.timeline {
+            if let Step::Frames(frames) = step {
+                physics.with(&frames.physics).validate()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn has_run_control(&self) -> bool {
+        self.timeline
+            .iter()
+            .any(|step| matches!(step, Step::RunStart { .. } | Step::RunStop { .. }))
+    }
+
+    /// Turns the timeline into the actions of the simulator.
+    /// Frames are flagged as running after a run start and until a run stop,
+    /// or always if the scenario has no run starts.
+    fn plan(&self, digitizer_ids: &[DigitizerId], mean_events: f64) -> Vec<Action> {
+        let mut active: Vec<DigitizerId> = self
+            .digitisers
+            .clone()
+            .unwrap_or_else(|| digitizer_ids.to_vec());
+        let mut frame_number = self.start_frame;
+        let mut period_number = 0;
+        let mut veto_flags = vec![0];
+        let mut veto_index = 0;
+        let mut offset = Duration::ZERO;
+        let mut running = !self
+            .timeline
+            .iter()
+            .any(|step| matches!(step, Step::RunStart { .. }));
+
+        let mut actions = Vec::new();
+        for step in &self.timeline {
+            match step {
+                Step::RunStart {
+                    run_name,
+                    instrument_name,
+                } => {
+                    running = true;
+                    actions.push(Action::RunStart {
+                        run_name: run_name.clone(),
+                        instrument_name: instrument_name.clone(),
+                        offset,
+                    });
+                }
+                Step::RunStop { run_name } => {
+                    running = false;
+                    actions.push(Action::RunStop {
+                        run_name: run_name.clone(),
+                        offset,
+                    });
+                }
+                Step::Frames(frames) => {
+                    let interval = Duration::from_secs_f64(1.0 / frames.rate);
+                    for _ in 0..frames.count {
+                        let frame = Frame {
+                            number: frame_number,
+                            offset,
+                            period_number,
+                            veto_flags: veto_flags[veto_index],
+                            running,
+                            mean_events: frames.events.unwrap_or(mean_events),
+                            digitizer_ids: active.clone(),
+                            physics: frames.physics.clone(),
+                        };
+                        actions.push(Action::Frame(frame, interval));
+                        frame_number += 1;
+                        offset += interval;
+                        veto_index += 1;
+                        if veto_index == veto_flags.len() {
+                            veto_index = 0;
+                        }
+                    }
+                }
+                Step::Period(period) => period_number = *period,
+                Step::VetoFlags(pattern) => {
+                    veto_flags = pattern.clone();
+                    veto_index = 0;
+                }
+                Step::Dropout(digitisers) => active.retain(|id| !digitisers.contains(id)),
+                Step::Restore(digitisers) => {
+                    let all = self.digitisers.as_deref().unwrap_or(digitizer_ids);
+                    active = all
+                        .iter()
+                        .filter(|id| active.contains(id) || digitisers.contains(id))
+                        .copied()
+                        .collect();
+                }
+                Step::Wait(milliseconds) => {
+                    let duration = Duration::from_millis(*milliseconds);
+                    actions.push(Action::Wait(duration));
+                    offset += duration;
+                }
+            }
+        }
+        actions
+    }
+}
+
+fn create_run_start_command(
+    fbb: &mut FlatBufferBuilder<'_>,
+    start_time: DateTime<Utc>,
+    run_name: &str,
+    instrument_name: &str,
+) {
+    let run_start = RunStartArgs {
+        start_time: start_time
+            .signed_duration_since(DateTime::UNIX_EPOCH)
+            .num_milliseconds() as u64,
+        run_name: Some(fbb.create_string(run_name)),
+        instrument_name: Some(fbb.create_string(instrument_name)),
+        ..Default::default()
+    };
+    let message = RunStart::create(fbb, &run_start);
+    finish_run_start_buffer(fbb, message);
+}
+
+fn create_run_stop_command(
+    fbb: &mut FlatBufferBuilder<'_>,
+    stop_time: DateTime<Utc>,
+    run_name: &str,
+) {
+    let run_stop = RunStopArgs {
+        stop_time: stop_time
+            .signed_duration_since(DateTime::UNIX_EPOCH)
+            .num_milliseconds() as u64,
+        run_name: Some(fbb.create_string(run_name)),
+        ..Default::default()
+    };
+    let message = RunStop::create(fbb, &run_stop);
+    finish_run_stop_buffer(fbb, message);
+}
+
+/// Replays a scenario file, sending the messages of each step of its timeline in turn.
+/// When writing to a file, the messages are written without waiting for the time of each step.
+pub(crate) async fn run(
+    output: &mut Output,
+    cli: &Cli,
+    args: &ScenarioArgs,
+    simulation: &mut Simulation<'_>,
+) -> Result<()> {
+    let scenario = fs::read_to_string(&args.file)
+        .map_err(anyhow::Error::from)
+        .and_then(|text| Scenario::parse(&text))
+        .and_then(|scenario| {
+            scenario.validate_physics(&cli.physics)?;
+            Ok(scenario)
+        })
+        .map_err(|e| anyhow!("{}: {e}", args.file.display()))?;
+    if scenario.has_run_control() && args.control_topic.is_none() && !output.is_file() {
+        return Err(anyhow!(
+            "--control-topic is required by the run starts and stops of the scenario"
+        ));
+    }
+
+    let actions = scenario.plan(&cli.digitizer_ids, cli.events_per_frame as f64);
+    info!("Replaying {} actions of the scenario", actions.len());
+
+    let mut next = Instant::now();
+    for action in actions {
+        if !output.is_file() {
+            time::sleep_until(next).await;
+            next = Instant::now();
+        }
+        match action {
+            Action::RunStart {
+                run_name,
+                instrument_name,
+                offset,
+            } => {
+                info!("Starting run {run_name}");
+                let fbb = &mut simulation.fbb;
+                fbb.reset();
+                let start_time = Simulation::timestamp(cli, offset);
+                create_run_start_command(fbb, start_time, &run_name, &instrument_name);
+                output
+                    .send(
+                        args.control_topic.as_deref(),
+                        RUN_KEY,
+                        fbb.finished_data(),
+                        1,
+                    )
+                    .await?;
+            }
+            Action::RunStop { run_name, offset } => {
+                info!("Stopping run {run_name}");
+                let fbb = &mut simulation.fbb;
+                fbb.reset();
+                create_run_stop_command(fbb, Simulation::timestamp(cli, offset), &run_name);
+                output
+                    .send(
+                        args.control_topic.as_deref(),
+                        RUN_KEY,
+                        fbb.finished_data(),
+                        1,
+                    )
+                    .await?;
+            }
+            Action::Frame(frame, interval) => {
+                send(output, cli, simulation, &frame).await?;
+                next += interval;
+            }
+            Action::Wait(duration) => next += duration,
+        }
+    }
+    send_delayed(output, cli, simulation).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+digitisers: [1, 2, 3]
+start_frame: 10
+timeline:
+  - run_start:
+      run_name: Test
+      instrument_name: SuperMuSR
+  - frames: { count: 2, rate: 50 }
+  - period: 4
+  - veto_flags: [0, 8, 8]
+  - dropout: [2]
+  - frames: { count: 4, rate: 100, events: 3, physics: { asymmetry: 0.3, pulse_shape: gaussian } }
+  - restore: [2]
+  - wait: 500
+  - frames: { count: 1, rate: 50 }
+  - run_stop:
+      run_name: Test
+  - frames: { count: 1, rate: 50 }
+"#;
+
+    fn frames(actions: &[Action]) -> Vec<&Frame> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Frame(frame, _) => Some(frame),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plan() {
+        let scenario = Scenario::parse(SCENARIO).unwrap();
+        assert!(scenario.has_run_control());
+        let actions = scenario.plan(&[0], 20.0);
+        assert_eq!(actions.len(), 11);
+        assert_eq!(
+            actions[0],
+            Action::RunStart {
+                run_name: "Test".to_owned(),
+                instrument_name: "SuperMuSR".to_owned(),
+                offset: Duration::ZERO
+            }
+        );
+        assert_eq!(actions[7], Action::Wait(Duration::from_millis(500)));
+        assert_eq!(
+            actions[9],
+            Action::RunStop {
+                run_name: "Test".to_owned(),
+                offset: Duration::from_millis(2 * 20 + 4 * 10 + 500 + 20)
+            }
+        );
+
+        let frames = frames(&actions);
+        let field = |f: fn(&Frame) -> u64| frames.iter().map(|frame| f(frame)).collect::<Vec<_>>();
+        assert_eq!(
+            field(|frame| frame.number as u64),
+            (10..18).collect::<Vec<_>>()
+        );
+        assert_eq!(field(|frame| frame.period_number), [0, 0, 4, 4, 4, 4, 4, 4]);
+        assert_eq!(
+            field(|frame| frame.veto_flags as u64),
+            [0, 0, 0, 8, 8, 0, 8, 8]
+        );
+        assert_eq!(
+            field(|frame| frame.running as u64),
+            [1, 1, 1, 1, 1, 1, 1, 0]
+        );
+        assert_eq!(frames[0].digitizer_ids, [1, 2, 3]);
+        assert_eq!(frames[2].digitizer_ids, [1, 3]);
+        assert_eq!(frames[2].mean_events, 3.0);
+        assert_eq!(frames[6].digitizer_ids, [1, 2, 3]);
+        assert_eq!(frames[6].mean_events, 20.0);
+        assert_eq!(frames[6].physics, PhysicsOverrides::default());
+        assert_eq!(frames[2].offset, Duration::from_millis(40));
+        assert_eq!(frames[6].offset, Duration::from_millis(580));
+        assert_eq!(
+            actions[3],
+            Action::Frame(frames[2].clone(), Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn physics() {
+        let physics = PhysicsOptions::parse_from(["simulator"]);
+        let scenario = Scenario::parse(SCENARIO).unwrap();
+        assert!(scenario.validate_physics(&physics).is_ok());
+        let actions = scenario.plan(&[0], 20.0);
+        let frames = frames(&actions);
+        assert_ne!(frames[2].physics, PhysicsOverrides::default());
+
+        let scenario =
+            Scenario::parse("timeline: [{frames: {count: 3, rate: 10, physics: {asymmetry: 2}}}]")
+                .unwrap();
+        assert!(scenario.validate_physics(&physics).is_err());
+        assert!(
+            Scenario::parse("timeline: [{frames: {count: 3, rate: 10, physics: {spin: 2}}}]")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn json() {
+        let scenario = Scenario::parse(
+            r#"{"timeline": [{"frames": {"count": 3, "rate": 10}}, {"dropout": [0]}, {"frames": {"count": 1, "rate": 10}}]}"#,
+        )
+        .unwrap();
+        assert!(!scenario.has_run_control());
+        let actions = scenario.plan(&[0, 5], 1.0);
+        let frames = frames(&actions);
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|frame| frame.running));
+        assert_eq!(frames[3].digitizer_ids, [5]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Scenario::parse("timeline: [{frames: {count: 3, rate: 0}}]").is_err());
+        assert!(Scenario::parse("timeline: [{frames: {count: 3, rate: 1e-300}}]").is_err());
+        assert!(Scenario::parse("timeline: [{frames: {count: 3, rate: .nan}}]").is_err());
+        assert!(Scenario::parse("timeline: [{veto_flags: []}]").is_err());
+        assert!(Scenario::parse("timeline: [{frames: {count: 3}}]").is_err());
+        assert!(Scenario::parse("timeline: [{rewind: 3}]").is_err());
+        assert!(Scenario::parse("digitisers: [0]").is_err());
+    }
+}
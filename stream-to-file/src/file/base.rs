@@ -1,8 +1,8 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use hdf5::{Dataset, File};
+use hdf5::{types::VarLenUnicode, Dataset, File};
 use ndarray::{s, Array};
-use std::path::Path;
+use std::{path::Path, str::FromStr};
 use supermusr_common::FrameNumber;
 
 pub(super) struct BaseFile {
@@ -53,6 +53,33 @@ impl BaseFile {
         })
     }
 
+    /// Records the name and start time of the run this file contains.
+    pub(super) fn set_run_start(&self, run_name: &str, start_time: DateTime<Utc>) -> Result<()> {
+        self.file
+            .new_dataset::<VarLenUnicode>()
+            .create("run/name")?
+            .write_scalar(&VarLenUnicode::from_str(run_name)?)?;
+
+        self.file
+            .new_dataset::<VarLenUnicode>()
+            .create("run/start_time")?
+            .write_scalar(&VarLenUnicode::from_str(&start_time.to_rfc3339())?)?;
+
+        Ok(())
+    }
+
+    /// Records the stop time of the run this file contains.
+    pub(super) fn set_run_stop(&self, stop_time: DateTime<Utc>) -> Result<()> {
+        self.file
+            .new_dataset::<VarLenUnicode>()
+            .create("run/stop_time")?
+            .write_scalar(&VarLenUnicode::from_str(&stop_time.to_rfc3339())?)?;
+
+        self.file.flush()?;
+
+        Ok(())
+    }
+
     pub(super) fn find_frame_metadata_index(
         &self,
         frame_number: FrameNumber,
@@ -94,14 +121,15 @@ impl BaseFile {
         }
     }
 
+    /// Records the metadata of a frame, returning `false` if the frame has already been recorded.
     pub(super) fn new_frame(
         &mut self,
         frame_number: FrameNumber,
         frame_time: DateTime<Utc>,
         frame_start: usize,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         if frame_number < self.next_frame_number {
-            return Ok(());
+            return Ok(false);
         }
         self.next_frame_number = frame_number + 1;
 
@@ -133,7 +161,7 @@ impl BaseFile {
         self.frame_start_index
             .write_slice(&frame_start, s![num_frames..num_frames + 1])?;
 
-        Ok(())
+        Ok(true)
     }
 }
 
@@ -1,5 +1,7 @@
 use super::base::BaseFile;
+use crate::run::RunParameters;
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use hdf5::Dataset;
 use ndarray::{s, Array};
 use std::path::Path;
@@ -42,6 +44,15 @@ impl EventFile {
         })
     }
 
+    pub(crate) fn set_run_start(&self, parameters: &RunParameters) -> Result<()> {
+        self.base
+            .set_run_start(&parameters.name, parameters.start_time)
+    }
+
+    pub(crate) fn set_run_stop(&self, stop_time: DateTime<Utc>) -> Result<()> {
+        self.base.set_run_stop(stop_time)
+    }
+
     pub(crate) fn push(&mut self, data: &FrameAssembledEventListMessage) -> Result<()> {
         let time = data.time().unwrap();
         let voltage = data.voltage().unwrap();
@@ -136,6 +147,7 @@ mod tests {
             time,
             voltage,
             channel,
+            complete: true,
         };
         let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
         finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
@@ -0,0 +1,53 @@
+use super::{EventFile, NexusEventFile};
+use crate::run::RunParameters;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use std::path::Path;
+use supermusr_streaming_types::aev1_frame_assembled_event_v1_generated::FrameAssembledEventListMessage;
+
+/// Layouts in which event files can be written.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub(crate) enum EventFileLayout {
+    /// Flat event and frame metadata datasets
+    #[default]
+    Simple,
+    /// NeXus file with an NXentry containing the events as NXevent_data
+    Nexus,
+}
+
+/// An event file written in one of the [EventFileLayout]s.
+pub(crate) enum AnyEventFile {
+    Simple(EventFile),
+    Nexus(NexusEventFile),
+}
+
+impl AnyEventFile {
+    pub(crate) fn create(filename: &Path, layout: EventFileLayout) -> Result<Self> {
+        Ok(match layout {
+            EventFileLayout::Simple => Self::Simple(EventFile::create(filename)?),
+            EventFileLayout::Nexus => Self::Nexus(NexusEventFile::create(filename)?),
+        })
+    }
+
+    pub(crate) fn set_run_start(&mut self, parameters: &RunParameters) -> Result<()> {
+        match self {
+            Self::Simple(file) => file.set_run_start(parameters),
+            Self::Nexus(file) => file.set_run_start(parameters),
+        }
+    }
+
+    pub(crate) fn set_run_stop(&mut self, stop_time: DateTime<Utc>) -> Result<()> {
+        match self {
+            Self::Simple(file) => file.set_run_stop(stop_time),
+            Self::Nexus(file) => file.set_run_stop(stop_time),
+        }
+    }
+
+    pub(crate) fn push(&mut self, data: &FrameAssembledEventListMessage) -> Result<()> {
+        match self {
+            Self::Simple(file) => file.push(data),
+            Self::Nexus(file) => file.push(data),
+        }
+    }
+}
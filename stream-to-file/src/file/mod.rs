@@ -1,6 +1,10 @@
 mod base;
 mod event;
+mod layout;
+mod nexus;
 mod trace;
 
-pub(crate) use event::EventFile;
+use event::EventFile;
+pub(crate) use layout::{AnyEventFile, EventFileLayout};
+use nexus::NexusEventFile;
 pub(crate) use trace::TraceFile;
@@ -0,0 +1,196 @@
+//! Writes the JSON descriptions carried by `RunStart` messages into the NeXus file.
+
+use super::create_nx_group;
+use anyhow::{anyhow, Result};
+use hdf5::{types::VarLenUnicode, Dataset, Group, H5Type, Location};
+use serde_json::{Map, Value};
+use std::{slice, str::FromStr};
+use tracing::debug;
+
+/// The values of a JSON scalar or array, converted to a type that can be stored in HDF5.
+enum Values {
+    Integer(Vec<i64>),
+    Float(Vec<f64>),
+    Text(Vec<VarLenUnicode>),
+}
+
+/// Converts a JSON value to the values to be stored, and whether they are a scalar.
+fn to_values(value: &Value) -> Result<(Values, bool)> {
+    let (elements, scalar) = match value {
+        Value::Array(elements) => (elements.as_slice(), false),
+        value => (slice::from_ref(value), true),
+    };
+
+    let values = if elements.iter().all(Value::is_i64) {
+        Values::Integer(elements.iter().filter_map(Value::as_i64).collect())
+    } else if elements.iter().all(Value::is_number) {
+        Values::Float(elements.iter().filter_map(Value::as_f64).collect())
+    } else if elements.iter().all(Value::is_string) {
+        Values::Text(
+            elements
+                .iter()
+                .filter_map(Value::as_str)
+                .map(VarLenUnicode::from_str)
+                .collect::<Result<_, _>>()?,
+        )
+    } else {
+        return Err(anyhow!("unsupported value: {value}"));
+    };
+
+    Ok((values, scalar))
+}
+
+fn create_dataset<T: H5Type>(
+    parent: &Group,
+    name: &str,
+    values: &[T],
+    scalar: bool,
+) -> Result<Dataset> {
+    Ok(match values {
+        [value] if scalar => {
+            let dataset = parent.new_dataset::<T>().create(name)?;
+            dataset.write_scalar(value)?;
+            dataset
+        }
+        values => parent
+            .new_dataset_builder()
+            .with_data(values)
+            .create(name)?,
+    })
+}
+
+fn create_attr<T: H5Type>(
+    location: &Location,
+    name: &str,
+    values: &[T],
+    scalar: bool,
+) -> Result<()> {
+    if location.attr_names()?.iter().any(|attr| attr == name) {
+        debug!("Keeping existing attribute {name} of {}", location.name());
+        return Ok(());
+    }
+    match values {
+        [value] if scalar => location.new_attr::<T>().create(name)?.write_scalar(value)?,
+        values => {
+            location.new_attr_builder().with_data(values).create(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a dataset, unless one of the same name already exists, in which case that is returned.
+fn write_dataset(parent: &Group, name: &str, value: &Value) -> Result<Dataset> {
+    if parent.link_exists(name) {
+        debug!("Keeping existing dataset {name} of {}", parent.name());
+        return Ok(parent.dataset(name)?);
+    }
+    match to_values(value)? {
+        (Values::Integer(values), scalar) => create_dataset(parent, name, &values, scalar),
+        (Values::Float(values), scalar) => create_dataset(parent, name, &values, scalar),
+        (Values::Text(values), scalar) => create_dataset(parent, name, &values, scalar),
+    }
+}
+
+fn write_attr(location: &Location, name: &str, value: &Value) -> Result<()> {
+    match to_values(value)? {
+        (Values::Integer(values), scalar) => create_attr(location, name, &values, scalar),
+        (Values::Float(values), scalar) => create_attr(location, name, &values, scalar),
+        (Values::Text(values), scalar) => create_attr(location, name, &values, scalar),
+    }
+}
+
+/// Writes each entry of the run's `metadata` JSON object as a dataset of an NXcollection.
+pub(super) fn write_metadata(entry: &Group, metadata: &str) -> Result<()> {
+    let metadata: Map<String, Value> = serde_json::from_str(metadata)?;
+
+    let collection = create_nx_group(entry, "metadata", "NXcollection")?;
+    for (name, value) in &metadata {
+        write_dataset(&collection, name, value)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the static groups, datasets and attributes of a kafka-to-nexus
+/// `nexus_structure`, merging with any groups that already exist.
+/// Datasets and attributes already written from the `RunStart` message are kept.
+/// Streamed modules are skipped, as their data is not available here.
+pub(super) fn write_nexus_structure(root: &Group, structure: &str) -> Result<()> {
+    let structure: Value = serde_json::from_str(structure)?;
+    write_children(root, &structure)
+}
+
+fn name_of(node: &Value) -> Result<&str> {
+    node.get("name")
+        .and_then(Value::as_str)
+        .ok_or(anyhow!("no name given for {node}"))
+}
+
+fn values_of(node: &Value) -> Result<&Value> {
+    node.get("values")
+        .ok_or(anyhow!("no values given for {node}"))
+}
+
+fn write_children(parent: &Group, node: &Value) -> Result<()> {
+    match node.get("children") {
+        None => Ok(()),
+        Some(Value::Array(children)) => children
+            .iter()
+            .try_for_each(|child| write_node(parent, child)),
+        Some(children) => Err(anyhow!("children should be an array: {children}")),
+    }
+}
+
+fn write_attrs(location: &Location, node: &Value) -> Result<()> {
+    match node.get("attributes") {
+        None => Ok(()),
+        Some(Value::Array(attributes)) => attributes.iter().try_for_each(|attribute| {
+            write_attr(location, name_of(attribute)?, values_of(attribute)?)
+        }),
+        Some(Value::Object(attributes)) => attributes
+            .iter()
+            .try_for_each(|(name, value)| write_attr(location, name, value)),
+        Some(attributes) => Err(anyhow!("unsupported attributes: {attributes}")),
+    }
+}
+
+fn write_node(parent: &Group, node: &Value) -> Result<()> {
+    if let Some(module) = node.get("module").and_then(Value::as_str) {
+        let config = node
+            .get("config")
+            .ok_or(anyhow!("no config given for {module} module"))?;
+
+        return match module {
+            "dataset" => {
+                let dataset = write_dataset(parent, name_of(config)?, values_of(config)?)?;
+                write_attrs(&dataset, node)
+            }
+            module => {
+                debug!("Skipping {module} module in {}", parent.name());
+                Ok(())
+            }
+        };
+    }
+
+    match node.get("type").and_then(Value::as_str) {
+        Some("group") => {
+            let name = name_of(node)?;
+            let group = if parent.link_exists(name) {
+                parent.group(name)?
+            } else {
+                parent.create_group(name)?
+            };
+            write_attrs(&group, node)?;
+            write_children(&group, node)
+        }
+        Some("dataset") => {
+            let dataset = write_dataset(parent, name_of(node)?, values_of(node)?)?;
+            write_attrs(&dataset, node)
+        }
+        Some("stream") => {
+            debug!("Skipping stream in {}", parent.name());
+            Ok(())
+        }
+        _ => Err(anyhow!("unrecognised node: {node}")),
+    }
+}
@@ -0,0 +1,465 @@
+mod json;
+
+use super::base::BaseFile;
+use crate::run::RunParameters;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use hdf5::{types::VarLenUnicode, Dataset, Group, H5Type, Location};
+use ndarray::{s, Array};
+use std::{path::Path, str::FromStr};
+use supermusr_streaming_types::aev1_frame_assembled_event_v1_generated::FrameAssembledEventListMessage;
+use tracing::warn;
+
+/// Writes events to an `NXevent_data` group of the `raw_data_1` NXentry, as
+/// read by Mantid, alongside the frame metadata recorded by [BaseFile].
+pub(crate) struct NexusEventFile {
+    base: BaseFile,
+    entry: Group,
+    event_id: Dataset,
+    event_time_offset: Dataset,
+    event_pulse_height: Dataset,
+    event_time_zero: Dataset,
+    event_index: Dataset,
+
+    /// Time that `event_time_zero` is relative to, taken from the run start or the first frame
+    offset: Option<DateTime<Utc>>,
+}
+
+impl NexusEventFile {
+    pub(crate) fn create(filename: &Path) -> Result<Self> {
+        let base = BaseFile::create(filename)?;
+
+        let entry = create_nx_group(&base.file, "raw_data_1", "NXentry")?;
+        let detector = create_nx_group(&entry, "detector_1", "NXevent_data")?;
+
+        let event_id = detector
+            .new_dataset::<u32>()
+            .shape((0..,))
+            .create("event_id")?;
+
+        let event_time_offset = detector
+            .new_dataset::<u32>()
+            .shape((0..,))
+            .create("event_time_offset")?;
+        set_string_attr(&event_time_offset, "units", "ns")?;
+
+        let event_pulse_height = detector
+            .new_dataset::<u16>()
+            .shape((0..,))
+            .create("pulse_height")?;
+
+        let event_time_zero = detector
+            .new_dataset::<f64>()
+            .shape((0..,))
+            .create("event_time_zero")?;
+        set_string_attr(&event_time_zero, "units", "second")?;
+
+        let event_index = detector
+            .new_dataset::<u64>()
+            .shape((0..,))
+            .create("event_index")?;
+
+        Ok(NexusEventFile {
+            base,
+            entry,
+            event_id,
+            event_time_offset,
+            event_pulse_height,
+            event_time_zero,
+            event_index,
+            offset: None,
+        })
+    }
+
+    pub(crate) fn set_run_start(&mut self, parameters: &RunParameters) -> Result<()> {
+        write_string(&self.entry, "name", &parameters.name)?;
+        write_string(
+            &self.entry,
+            "start_time",
+            &parameters.start_time.to_rfc3339(),
+        )?;
+
+        if self.offset.is_none() {
+            self.set_offset(parameters.start_time)?;
+        }
+
+        let instrument = create_nx_group(&self.entry, "instrument", "NXinstrument")?;
+        if let Some(ref name) = parameters.instrument_name {
+            write_string(&instrument, "name", name)?;
+        }
+
+        if let Some(ref map) = parameters.detector_spectrum_map {
+            let detector = create_nx_group(&instrument, "detector_1", "NXdetector")?;
+            detector
+                .new_dataset_builder()
+                .with_data(map.detector_id.as_slice())
+                .create("detector_number")?;
+            detector
+                .new_dataset_builder()
+                .with_data(map.spectrum.as_slice())
+                .create("spectrum_index")?;
+        }
+
+        if let Some(ref metadata) = parameters.metadata {
+            json::write_metadata(&self.entry, metadata)
+                .map_err(|e| anyhow!("failed to write run metadata: {e}"))?;
+        }
+
+        if let Some(ref structure) = parameters.nexus_structure {
+            json::write_nexus_structure(&self.base.file, structure)
+                .map_err(|e| anyhow!("failed to write NeXus structure: {e}"))?;
+        }
+
+        self.base.file.flush()?;
+
+        Ok(())
+    }
+
+    pub(crate) fn set_run_stop(&mut self, stop_time: DateTime<Utc>) -> Result<()> {
+        write_string(&self.entry, "end_time", &stop_time.to_rfc3339())?;
+
+        self.base.file.flush()?;
+
+        Ok(())
+    }
+
+    pub(crate) fn push(&mut self, data: &FrameAssembledEventListMessage) -> Result<()> {
+        let time = data.time().ok_or(anyhow!("no event times in message"))?;
+        let voltage = data
+            .voltage()
+            .ok_or(anyhow!("no event voltages in message"))?;
+        let channel = data
+            .channel()
+            .ok_or(anyhow!("no event channels in message"))?;
+
+        if time.len() != voltage.len() || time.len() != channel.len() {
+            return Err(anyhow!(
+                "Event dataset sizes do not match (|time|={}, |voltage|={}, |channel|={})",
+                time.len(),
+                voltage.len(),
+                channel.len()
+            ));
+        }
+
+        let frame_time: DateTime<Utc> = (*data
+            .metadata()
+            .timestamp()
+            .ok_or(anyhow!("no timestamp in message"))?)
+        .into();
+
+        let frame_idx = self.event_id.shape()[0];
+
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => self.set_offset(frame_time)?,
+        };
+        let time_zero = (frame_time - offset)
+            .num_nanoseconds()
+            .ok_or(anyhow!("frame time {frame_time} is too far from {offset}"))?
+            as f64
+            / 1e9;
+
+        // Events are only written for new frames, so that every event belongs to a frame
+        let frame_number = data.metadata().frame_number();
+        if !self.base.new_frame(frame_number, frame_time, frame_idx)? {
+            warn!("Frame {frame_number} is not after the last frame written, discarding it");
+            return Ok(());
+        }
+
+        append(&self.event_id, channel.iter().collect())?;
+        append(&self.event_time_offset, time.iter().collect())?;
+        append(&self.event_pulse_height, voltage.iter().collect())?;
+        append(&self.event_time_zero, vec![time_zero])?;
+        append(&self.event_index, vec![frame_idx as u64])?;
+
+        self.base.file.flush()?;
+
+        Ok(())
+    }
+
+    fn set_offset(&mut self, offset: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        set_string_attr(&self.event_time_zero, "offset", &offset.to_rfc3339())?;
+        self.offset = Some(offset);
+        Ok(offset)
+    }
+}
+
+fn create_nx_group(parent: &Group, name: &str, class: &str) -> Result<Group> {
+    let group = parent.create_group(name)?;
+    set_string_attr(&group, "NX_class", class)?;
+    Ok(group)
+}
+
+fn set_string_attr(location: &Location, name: &str, value: &str) -> Result<()> {
+    location
+        .new_attr::<VarLenUnicode>()
+        .create(name)?
+        .write_scalar(&VarLenUnicode::from_str(value)?)?;
+    Ok(())
+}
+
+fn write_string(group: &Group, name: &str, value: &str) -> Result<()> {
+    group
+        .new_dataset::<VarLenUnicode>()
+        .create(name)?
+        .write_scalar(&VarLenUnicode::from_str(value)?)?;
+    Ok(())
+}
+
+/// Appends values to the end of a one dimensional resizable dataset.
+fn append<T: H5Type>(dataset: &Dataset, values: Vec<T>) -> Result<()> {
+    let idx = dataset.shape()[0];
+    dataset.resize((idx + values.len(),))?;
+    dataset.write_slice(&Array::from_vec(values), s![idx..])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::DetectorSpectrumMap;
+    use chrono::Duration;
+    use std::{env, fs, path::PathBuf};
+    use supermusr_streaming_types::{
+        aev1_frame_assembled_event_v1_generated::{
+            finish_frame_assembled_event_list_message_buffer,
+            root_as_frame_assembled_event_list_message, FrameAssembledEventListMessageArgs,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v1_generated::{FrameMetadataV1, FrameMetadataV1Args, GpsTime},
+    };
+
+    fn create_test_filename(name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("{name}.h5"));
+        path
+    }
+
+    fn push_frame(
+        file: &mut NexusEventFile,
+        num_events: usize,
+        frame_number: u32,
+        time: DateTime<Utc>,
+    ) {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = time.into();
+        let metadata = FrameMetadataV1Args {
+            frame_number,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            timestamp: Some(&time),
+            veto_flags: 0,
+        };
+        let metadata = FrameMetadataV1::create(&mut fbb, &metadata);
+
+        let time = Some(fbb.create_vector(&vec![frame_number; num_events]));
+        let voltage = Some(fbb.create_vector(&vec![frame_number as u16; num_events]));
+        let channel = Some(fbb.create_vector(&vec![frame_number; num_events]));
+
+        let message = FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            time,
+            voltage,
+            channel,
+            complete: true,
+        };
+        let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+
+        let message = root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        file.push(&message).unwrap();
+    }
+
+    fn read_string(location: &Location, name: &str) -> String {
+        location
+            .attr(name)
+            .unwrap()
+            .read_scalar::<VarLenUnicode>()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_event_data() {
+        let filepath = create_test_filename("NexusEventFile_test_event_data");
+        let mut file = NexusEventFile::create(&filepath).unwrap();
+        let _ = fs::remove_file(filepath);
+
+        let start = Utc::now();
+        push_frame(&mut file, 20, 0, start);
+        push_frame(&mut file, 50, 1, start + Duration::milliseconds(20));
+        push_frame(&mut file, 42, 2, start + Duration::milliseconds(40));
+
+        let file = file.base.file;
+
+        let entry = file.group("raw_data_1").unwrap();
+        assert_eq!(read_string(&entry, "NX_class"), "NXentry");
+
+        let detector = entry.group("detector_1").unwrap();
+        assert_eq!(read_string(&detector, "NX_class"), "NXevent_data");
+
+        let expected_shape = vec![20 + 50 + 42];
+
+        let event_id = detector.dataset("event_id").unwrap();
+        assert_eq!(event_id.shape(), expected_shape);
+
+        let event_time_offset = detector.dataset("event_time_offset").unwrap();
+        assert_eq!(event_time_offset.shape(), expected_shape);
+        assert_eq!(read_string(&event_time_offset, "units"), "ns");
+
+        let pulse_height = detector.dataset("pulse_height").unwrap();
+        assert_eq!(pulse_height.shape(), expected_shape);
+
+        let event_index = detector.dataset("event_index").unwrap();
+        assert_eq!(event_index.read_raw::<u64>().unwrap(), vec![0, 20, 70]);
+
+        let event_time_zero = detector.dataset("event_time_zero").unwrap();
+        assert_eq!(
+            event_time_zero.read_raw::<f64>().unwrap(),
+            vec![0.0, 0.02, 0.04]
+        );
+        assert_eq!(read_string(&event_time_zero, "units"), "second");
+        assert_eq!(
+            read_string(&event_time_zero, "offset"),
+            DateTime::<Utc>::from(GpsTime::from(start)).to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn test_repeated_frame_is_discarded() {
+        let filepath = create_test_filename("NexusEventFile_test_repeated_frame_is_discarded");
+        let mut file = NexusEventFile::create(&filepath).unwrap();
+        let _ = fs::remove_file(filepath);
+
+        let start = Utc::now();
+        push_frame(&mut file, 20, 0, start);
+        push_frame(&mut file, 50, 1, start + Duration::milliseconds(20));
+        push_frame(&mut file, 30, 1, start + Duration::milliseconds(20));
+
+        let detector = file.base.file.group("raw_data_1/detector_1").unwrap();
+
+        for name in ["event_id", "event_time_offset", "pulse_height"] {
+            assert_eq!(detector.dataset(name).unwrap().shape(), vec![20 + 50]);
+        }
+        let event_index = detector.dataset("event_index").unwrap();
+        assert_eq!(event_index.read_raw::<u64>().unwrap(), vec![0, 20]);
+        let event_time_zero = detector.dataset("event_time_zero").unwrap();
+        assert_eq!(event_time_zero.shape(), vec![2]);
+    }
+
+    #[test]
+    fn test_invalid_structure_is_an_error() {
+        let filepath = create_test_filename("NexusEventFile_test_invalid_structure_is_an_error");
+        let mut file = NexusEventFile::create(&filepath).unwrap();
+        let _ = fs::remove_file(filepath);
+
+        let parameters = RunParameters {
+            name: "run_1234".to_owned(),
+            start_time: Utc::now(),
+            stop_time: None,
+            filename: None,
+            instrument_name: None,
+            nexus_structure: Some(r#"{"children": [{"type": "mystery"}]}"#.to_owned()),
+            metadata: None,
+            detector_spectrum_map: None,
+        };
+        assert!(file.set_run_start(&parameters).is_err());
+    }
+
+    #[test]
+    fn test_run_metadata() {
+        let filepath = create_test_filename("NexusEventFile_test_run_metadata");
+        let mut file = NexusEventFile::create(&filepath).unwrap();
+        let _ = fs::remove_file(filepath);
+
+        let start = Utc::now();
+        let parameters = RunParameters {
+            name: "run_1234".to_owned(),
+            start_time: start,
+            stop_time: None,
+            filename: None,
+            instrument_name: Some("MUSR".to_owned()),
+            nexus_structure: Some(
+                r#"{"children": [{"type": "group", "name": "raw_data_1",
+                    "attributes": [{"name": "NX_class", "values": "NXentry"}],
+                    "children": [
+                    {"module": "dataset", "config": {"name": "title", "values": "Sample in field"}},
+                    {"module": "dataset", "config": {"name": "name", "values": "overwritten"}},
+                    {"type": "group", "name": "sample",
+                        "attributes": {"NX_class": "NXsample"},
+                        "children": [{"module": "dataset", "config": {"name": "name", "values": "Ag"}}]},
+                    {"module": "ev42", "config": {"topic": "events", "source": "musr"}}
+                ]}]}"#
+                    .to_owned(),
+            ),
+            metadata: Some(r#"{"proposal_id": 42, "temperatures": [1.5, 2.5]}"#.to_owned()),
+            detector_spectrum_map: Some(DetectorSpectrumMap {
+                detector_id: vec![1, 2, 3],
+                spectrum: vec![10, 20, 30],
+            }),
+        };
+        file.set_run_start(&parameters).unwrap();
+        push_frame(&mut file, 20, 0, start + Duration::milliseconds(20));
+        file.set_run_stop(start + Duration::seconds(1)).unwrap();
+
+        let file = file.base.file;
+
+        let entry = file.group("raw_data_1").unwrap();
+
+        let name = entry.dataset("name").unwrap();
+        assert_eq!(
+            name.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            "run_1234"
+        );
+        assert!(entry.dataset("end_time").is_ok());
+
+        let nx_class = entry.attr("NX_class").unwrap();
+        assert_eq!(
+            nx_class.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            "NXentry"
+        );
+
+        // The rest of the structure is written after the attribute which already exists
+        let sample_name = entry.dataset("sample/name").unwrap();
+        assert_eq!(
+            sample_name.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            "Ag"
+        );
+
+        let title = entry.dataset("title").unwrap();
+        assert_eq!(
+            title.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            "Sample in field"
+        );
+
+        let instrument_name = entry.dataset("instrument/name").unwrap();
+        assert_eq!(
+            instrument_name
+                .read_scalar::<VarLenUnicode>()
+                .unwrap()
+                .as_str(),
+            "MUSR"
+        );
+
+        let detector_number = entry
+            .dataset("instrument/detector_1/detector_number")
+            .unwrap();
+        assert_eq!(detector_number.read_raw::<i32>().unwrap(), vec![1, 2, 3]);
+
+        let spectrum_index = entry
+            .dataset("instrument/detector_1/spectrum_index")
+            .unwrap();
+        assert_eq!(spectrum_index.read_raw::<i32>().unwrap(), vec![10, 20, 30]);
+
+        let proposal_id = entry.dataset("metadata/proposal_id").unwrap();
+        assert_eq!(proposal_id.read_scalar::<i64>().unwrap(), 42);
+
+        let temperatures = entry.dataset("metadata/temperatures").unwrap();
+        assert_eq!(temperatures.read_raw::<f64>().unwrap(), vec![1.5, 2.5]);
+
+        let event_time_zero = entry.dataset("detector_1/event_time_zero").unwrap();
+        assert_eq!(event_time_zero.read_raw::<f64>().unwrap(), vec![0.02]);
+    }
+}
@@ -1,15 +1,20 @@
 mod file;
 mod metrics;
+mod run;
 
-use crate::file::{EventFile, TraceFile};
+use crate::{
+    file::{AnyEventFile, EventFileLayout, TraceFile},
+    run::RunManager,
+};
 use anyhow::{anyhow, Result};
+use chrono::Utc;
 use clap::Parser;
 use kagiyama::{prometheus::metrics::info::Info, AlwaysReady, Watcher};
 use rdkafka::{
     consumer::{stream_consumer::StreamConsumer, CommitMode, Consumer},
     message::Message,
 };
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 use supermusr_streaming_types::{
     aev1_frame_assembled_event_v1_generated::{
         frame_assembled_event_list_message_buffer_has_identifier,
@@ -19,9 +24,15 @@ use supermusr_streaming_types::{
         digitizer_analog_trace_message_buffer_has_identifier,
         root_as_digitizer_analog_trace_message,
     },
+    ecs_6s4t_run_stop_generated::{root_as_run_stop, run_stop_buffer_has_identifier},
+    ecs_pl72_run_start_generated::{root_as_run_start, run_start_buffer_has_identifier},
 };
 use tracing::{debug, info, warn};
 
+/// Interval at which runs are checked for having passed their stop time and grace period,
+/// so that the last run is closed even if no later frames arrive.
+const RUN_CLOSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 struct Cli {
@@ -43,6 +54,21 @@ struct Cli {
     #[clap(long)]
     event_file: Option<PathBuf>,
 
+    /// Layout in which event files are written
+    #[clap(long, value_enum, default_value_t = EventFileLayout::Simple)]
+    event_file_layout: EventFileLayout,
+
+    #[clap(long, conflicts_with = "event_file", requires = "event_topic")]
+    control_topic: Option<String>,
+
+    #[clap(long, default_value = ".")]
+    run_output_dir: PathBuf,
+
+    /// Time in milliseconds past the stop time of a run for which the run is kept open, so that
+    /// frames of the run which arrive after its RunStop are still written
+    #[clap(long, default_value = "1000")]
+    run_stop_grace_period: i64,
+
     #[clap(long)]
     trace_topic: Option<String>,
 
@@ -74,6 +100,13 @@ async fn main() -> Result<()> {
                     None => "none".into(),
                 },
             ),
+            (
+                "event_runs".to_string(),
+                match args.control_topic {
+                    Some(_) => args.run_output_dir.display().to_string(),
+                    None => "none".into(),
+                },
+            ),
             (
                 "trace".to_string(),
                 match args.trace_file {
@@ -99,10 +132,14 @@ async fn main() -> Result<()> {
     .set("enable.auto.commit", "false")
     .create()?;
 
-    let topics_to_subscribe: Vec<String> = vec![args.event_topic, args.trace_topic]
-        .into_iter()
-        .flatten()
-        .collect();
+    let topics_to_subscribe: Vec<String> = vec![
+        args.event_topic,
+        args.trace_topic,
+        args.control_topic.clone(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
     let topics_to_subscribe: Vec<&str> = topics_to_subscribe.iter().map(|i| i.as_ref()).collect();
     if topics_to_subscribe.is_empty() {
         return Err(anyhow!(
@@ -112,10 +149,18 @@ async fn main() -> Result<()> {
     consumer.subscribe(&topics_to_subscribe)?;
 
     let mut event_file = match args.event_file {
-        Some(filename) => Some(EventFile::create(&filename)?),
+        Some(filename) => Some(AnyEventFile::create(&filename, args.event_file_layout)?),
         None => None,
     };
 
+    let mut run_manager = args.control_topic.map(|_| {
+        RunManager::new(
+            args.run_output_dir,
+            args.event_file_layout,
+            chrono::Duration::milliseconds(args.run_stop_grace_period),
+        )
+    });
+
     let mut trace_file = match args.trace_file {
         Some(filename) => Some(TraceFile::create(
             &filename,
@@ -125,95 +170,176 @@ async fn main() -> Result<()> {
         None => None,
     };
 
+    let mut run_close_check = tokio::time::interval(RUN_CLOSE_CHECK_INTERVAL);
+
     loop {
-        match consumer.recv().await {
-            Err(e) => warn!("Kafka error: {}", e),
-            Ok(msg) => {
-                debug!(
-                    "key: '{:?}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
-                    msg.key(),
-                    msg.topic(),
-                    msg.partition(),
-                    msg.offset(),
-                    msg.timestamp()
-                );
-
-                if let Some(payload) = msg.payload() {
-                    if event_file.is_some()
-                        && frame_assembled_event_list_message_buffer_has_identifier(payload)
-                    {
-                        match root_as_frame_assembled_event_list_message(payload) {
-                            Ok(data) => {
-                                info!("Event packet: metadata: {:?}", data.metadata());
-                                metrics::MESSAGES_RECEIVED
-                                    .get_or_create(&metrics::MessagesReceivedLabels::new(
-                                        metrics::MessageKind::Event,
-                                    ))
-                                    .inc();
-                                if let Err(e) = event_file.as_mut().unwrap().push(&data) {
-                                    warn!("Failed to save events to file: {}", e);
+        tokio::select! {
+            _ = run_close_check.tick() => {
+                if let Some(run_manager) = run_manager.as_mut() {
+                    if let Err(e) = run_manager.close_expired(Utc::now()) {
+                        warn!("Failed to stop run: {}", e);
+                        metrics::FAILURES
+                            .get_or_create(&metrics::FailureLabels::new(
+                                metrics::FailureKind::FileWriteFailed,
+                            ))
+                            .inc();
+                    }
+                }
+            }
+            event = consumer.recv() => match event {
+                Err(e) => warn!("Kafka error: {}", e),
+                Ok(msg) => {
+                    debug!(
+                        "key: '{:?}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
+                        msg.key(),
+                        msg.topic(),
+                        msg.partition(),
+                        msg.offset(),
+                        msg.timestamp()
+                    );
+
+                    if let Some(payload) = msg.payload() {
+                        if (event_file.is_some() || run_manager.is_some())
+                            && frame_assembled_event_list_message_buffer_has_identifier(payload)
+                        {
+                            match root_as_frame_assembled_event_list_message(payload) {
+                                Ok(data) => {
+                                    info!("Event packet: metadata: {:?}", data.metadata());
+                                    metrics::MESSAGES_RECEIVED
+                                        .get_or_create(&metrics::MessagesReceivedLabels::new(
+                                            metrics::MessageKind::Event,
+                                        ))
+                                        .inc();
+                                    let result = match (event_file.as_mut(), run_manager.as_mut()) {
+                                        (Some(event_file), _) => event_file.push(&data),
+                                        (None, Some(run_manager)) => run_manager.push(&data),
+                                        (None, None) => unreachable!(),
+                                    };
+                                    if let Err(e) = result {
+                                        warn!("Failed to save events to file: {}", e);
+                                        metrics::FAILURES
+                                            .get_or_create(&metrics::FailureLabels::new(
+                                                metrics::FailureKind::FileWriteFailed,
+                                            ))
+                                            .inc();
+                                    }
+                                }
+                                Err(e) => {
+                                    warn!("Failed to parse message: {}", e);
                                     metrics::FAILURES
                                         .get_or_create(&metrics::FailureLabels::new(
-                                            metrics::FailureKind::FileWriteFailed,
+                                            metrics::FailureKind::UnableToDecodeMessage,
                                         ))
                                         .inc();
                                 }
                             }
-                            Err(e) => {
-                                warn!("Failed to parse message: {}", e);
-                                metrics::FAILURES
-                                    .get_or_create(&metrics::FailureLabels::new(
-                                        metrics::FailureKind::UnableToDecodeMessage,
-                                    ))
-                                    .inc();
+                            consumer.commit_message(&msg, CommitMode::Async).unwrap();
+                        } else if trace_file.is_some()
+                            && digitizer_analog_trace_message_buffer_has_identifier(payload)
+                        {
+                            match root_as_digitizer_analog_trace_message(payload) {
+                                Ok(data) => {
+                                    info!(
+                                        "Trace packet: dig. ID: {}, metadata: {:?}",
+                                        data.digitizer_id(),
+                                        data.metadata()
+                                    );
+                                    metrics::MESSAGES_RECEIVED
+                                        .get_or_create(&metrics::MessagesReceivedLabels::new(
+                                            metrics::MessageKind::Trace,
+                                        ))
+                                        .inc();
+                                    if let Err(e) = trace_file.as_mut().unwrap().push(&data) {
+                                        warn!("Failed to save traces to file: {}", e);
+                                        metrics::FAILURES
+                                            .get_or_create(&metrics::FailureLabels::new(
+                                                metrics::FailureKind::FileWriteFailed,
+                                            ))
+                                            .inc();
+                                    }
+                                }
+                                Err(e) => {
+                                    warn!("Failed to parse message: {}", e);
+                                    metrics::FAILURES
+                                        .get_or_create(&metrics::FailureLabels::new(
+                                            metrics::FailureKind::UnableToDecodeMessage,
+                                        ))
+                                        .inc();
+                                }
                             }
-                        }
-                        consumer.commit_message(&msg, CommitMode::Async).unwrap();
-                    } else if trace_file.is_some()
-                        && digitizer_analog_trace_message_buffer_has_identifier(payload)
-                    {
-                        match root_as_digitizer_analog_trace_message(payload) {
-                            Ok(data) => {
-                                info!(
-                                    "Trace packet: dig. ID: {}, metadata: {:?}",
-                                    data.digitizer_id(),
-                                    data.metadata()
-                                );
-                                metrics::MESSAGES_RECEIVED
-                                    .get_or_create(&metrics::MessagesReceivedLabels::new(
-                                        metrics::MessageKind::Trace,
-                                    ))
-                                    .inc();
-                                if let Err(e) = trace_file.as_mut().unwrap().push(&data) {
-                                    warn!("Failed to save traces to file: {}", e);
+                        } else if let Some(run_manager) = run_manager
+                            .as_mut()
+                            .filter(|_| run_start_buffer_has_identifier(payload))
+                        {
+                            match root_as_run_start(payload) {
+                                Ok(data) => {
+                                    info!("Run start: {:?}", data.run_name());
+                                    metrics::MESSAGES_RECEIVED
+                                        .get_or_create(&metrics::MessagesReceivedLabels::new(
+                                            metrics::MessageKind::RunStart,
+                                        ))
+                                        .inc();
+                                    if let Err(e) = run_manager.start_run(&data) {
+                                        warn!("Failed to start run: {}", e);
+                                        metrics::FAILURES
+                                            .get_or_create(&metrics::FailureLabels::new(
+                                                metrics::FailureKind::FileWriteFailed,
+                                            ))
+                                            .inc();
+                                    }
+                                }
+                                Err(e) => {
+                                    warn!("Failed to parse message: {}", e);
                                     metrics::FAILURES
                                         .get_or_create(&metrics::FailureLabels::new(
-                                            metrics::FailureKind::FileWriteFailed,
+                                            metrics::FailureKind::UnableToDecodeMessage,
                                         ))
                                         .inc();
                                 }
                             }
-                            Err(e) => {
-                                warn!("Failed to parse message: {}", e);
-                                metrics::FAILURES
-                                    .get_or_create(&metrics::FailureLabels::new(
-                                        metrics::FailureKind::UnableToDecodeMessage,
-                                    ))
-                                    .inc();
+                        } else if let Some(run_manager) = run_manager
+                            .as_mut()
+                            .filter(|_| run_stop_buffer_has_identifier(payload))
+                        {
+                            match root_as_run_stop(payload) {
+                                Ok(data) => {
+                                    info!("Run stop: {:?}", data.run_name());
+                                    metrics::MESSAGES_RECEIVED
+                                        .get_or_create(&metrics::MessagesReceivedLabels::new(
+                                            metrics::MessageKind::RunStop,
+                                        ))
+                                        .inc();
+                                    if let Err(e) = run_manager.stop_run(&data) {
+                                        warn!("Failed to stop run: {}", e);
+                                        metrics::FAILURES
+                                            .get_or_create(&metrics::FailureLabels::new(
+                                                metrics::FailureKind::FileWriteFailed,
+                                            ))
+                                            .inc();
+                                    }
+                                }
+                                Err(e) => {
+                                    warn!("Failed to parse message: {}", e);
+                                    metrics::FAILURES
+                                        .get_or_create(&metrics::FailureLabels::new(
+                                            metrics::FailureKind::UnableToDecodeMessage,
+                                        ))
+                                        .inc();
+                                }
                             }
+                        } else {
+                            warn!("Unexpected message type on topic \"{}\"", msg.topic());
+                            metrics::MESSAGES_RECEIVED
+                                .get_or_create(&metrics::MessagesReceivedLabels::new(
+                                    metrics::MessageKind::Unknown,
+                                ))
+                                .inc();
                         }
-                    } else {
-                        warn!("Unexpected message type on topic \"{}\"", msg.topic());
-                        metrics::MESSAGES_RECEIVED
-                            .get_or_create(&metrics::MessagesReceivedLabels::new(
-                                metrics::MessageKind::Unknown,
-                            ))
-                            .inc();
                     }
-                }
 
-                consumer.commit_message(&msg, CommitMode::Async).unwrap();
+                    consumer.commit_message(&msg, CommitMode::Async).unwrap();
+                }
             }
-        };
+        }
     }
 }
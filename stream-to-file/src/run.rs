@@ -0,0 +1,556 @@
+use crate::file::{AnyEventFile, EventFileLayout};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::path::{Component, Path, PathBuf};
+use supermusr_streaming_types::{
+    aev1_frame_assembled_event_v1_generated::FrameAssembledEventListMessage,
+    ecs_6s4t_run_stop_generated::RunStop, ecs_pl72_run_start_generated::RunStart,
+};
+use tracing::{debug, info, warn};
+
+/// Converts a run control timestamp (milliseconds since the Unix epoch) to a `DateTime`.
+fn datetime_from_millis(millis: u64) -> Result<DateTime<Utc>> {
+    DateTime::<Utc>::from_timestamp_millis(millis as i64)
+        .ok_or(anyhow!("timestamp out of range: {millis} ms"))
+}
+
+/// Mapping of detector IDs to spectrum numbers, as given in a `RunStart` message.
+pub(crate) struct DetectorSpectrumMap {
+    pub(crate) detector_id: Vec<i32>,
+    pub(crate) spectrum: Vec<i32>,
+}
+
+/// Description of a run, as given in its `RunStart` message.
+pub(crate) struct RunParameters {
+    pub(crate) name: String,
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) stop_time: Option<DateTime<Utc>>,
+    pub(crate) filename: Option<String>,
+    pub(crate) instrument_name: Option<String>,
+    /// JSON description of the NeXus file, in the format used by kafka-to-nexus
+    pub(crate) nexus_structure: Option<String>,
+    /// JSON object of static metadata about the measurement
+    pub(crate) metadata: Option<String>,
+    pub(crate) detector_spectrum_map: Option<DetectorSpectrumMap>,
+}
+
+impl TryFrom<&RunStart<'_>> for RunParameters {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &RunStart<'_>) -> Result<Self> {
+        let name = data
+            .run_name()
+            .ok_or(anyhow!("no run name in RunStart message"))?
+            .to_owned();
+
+        let start_time = datetime_from_millis(data.start_time())?;
+
+        // A stop time of zero indicates that the run is stopped by a later RunStop message
+        let stop_time = match data.stop_time() {
+            0 => None,
+            stop_time => Some(datetime_from_millis(stop_time)?),
+        };
+
+        let detector_spectrum_map = match data.detector_spectrum_map() {
+            Some(map) => {
+                let detector_id: Vec<i32> = map
+                    .detector_id()
+                    .map(|v| v.iter().collect())
+                    .unwrap_or_default();
+                let spectrum: Vec<i32> = map
+                    .spectrum()
+                    .map(|v| v.iter().collect())
+                    .unwrap_or_default();
+                if detector_id.len() != spectrum.len() {
+                    return Err(anyhow!(
+                        "Detector spectrum map sizes do not match (|detector_id|={}, |spectrum|={})",
+                        detector_id.len(),
+                        spectrum.len()
+                    ));
+                }
+                Some(DetectorSpectrumMap {
+                    detector_id,
+                    spectrum,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            name,
+            start_time,
+            stop_time,
+            filename: data.filename().map(ToOwned::to_owned),
+            instrument_name: data.instrument_name().map(ToOwned::to_owned),
+            nexus_structure: data.nexus_structure().map(ToOwned::to_owned),
+            metadata: data.metadata().map(ToOwned::to_owned),
+            detector_spectrum_map,
+        })
+    }
+}
+
+/// Checks that the filename of a run is a single normal path component,
+/// so that the file is written in the output directory.
+fn validate_filename(filename: &str) -> Result<()> {
+    let mut components = Path::new(filename).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(anyhow!(
+            "run filename \"{filename}\" is not a file name within the output directory"
+        )),
+    }
+}
+
+struct Run {
+    parameters: RunParameters,
+    file: AnyEventFile,
+    /// Whether the RunStop of the run has been received
+    stopped: bool,
+}
+
+impl Run {
+    fn new(output_dir: &Path, layout: EventFileLayout, data: &RunStart<'_>) -> Result<Self> {
+        let parameters = RunParameters::try_from(data)?;
+
+        let filename = match parameters.filename {
+            Some(ref filename) => filename.to_owned(),
+            None => format!("{}.h5", parameters.name),
+        };
+        validate_filename(&filename)?;
+        let filename = output_dir.join(filename);
+
+        let mut file = AnyEventFile::create(&filename, layout)?;
+        file.set_run_start(&parameters)?;
+
+        info!(
+            "Started run \"{}\", writing to {}",
+            parameters.name,
+            filename.display()
+        );
+
+        Ok(Self {
+            parameters,
+            file,
+            stopped: false,
+        })
+    }
+
+    fn contains(&self, timestamp: DateTime<Utc>) -> bool {
+        timestamp >= self.parameters.start_time
+            && self
+                .parameters
+                .stop_time
+                .map(|stop_time| timestamp < stop_time)
+                .unwrap_or(true)
+    }
+
+    fn finish(mut self, stop_time: DateTime<Utc>) -> Result<()> {
+        self.file.set_run_stop(stop_time)?;
+        info!("Stopped run \"{}\"", self.parameters.name);
+        Ok(())
+    }
+}
+
+/// Writes assembled event frames to a new file for each run, as delimited by
+/// `RunStart` and `RunStop` messages.
+///
+/// As run control messages and frames arrive on different topics, frames are given to the run
+/// containing their timestamp, and a run is kept open until the grace period past its stop time
+/// has passed, as told by the timestamp of a later frame or by [RunManager::close_expired], or
+/// the next run starts.
+pub(crate) struct RunManager {
+    output_dir: PathBuf,
+    layout: EventFileLayout,
+    grace_period: Duration,
+    run: Option<Run>,
+}
+
+impl RunManager {
+    pub(crate) fn new(
+        output_dir: PathBuf,
+        layout: EventFileLayout,
+        grace_period: Duration,
+    ) -> Self {
+        Self {
+            output_dir,
+            layout,
+            grace_period,
+            run: None,
+        }
+    }
+
+    pub(crate) fn start_run(&mut self, data: &RunStart<'_>) -> Result<()> {
+        let run = Run::new(&self.output_dir, self.layout, data)?;
+        let start_time = run.parameters.start_time;
+
+        if let Some(previous) = self.run.replace(run) {
+            if !previous.stopped {
+                warn!(
+                    "Run \"{}\" was not stopped before the next run started",
+                    previous.parameters.name
+                );
+            }
+            let stop_time = previous
+                .parameters
+                .stop_time
+                .map_or(start_time, |stop_time| stop_time.min(start_time));
+            previous.finish(stop_time)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the stop time of the current run, which is kept open for frames
+    /// arriving late until the grace period has passed.
+    pub(crate) fn stop_run(&mut self, data: &RunStop<'_>) -> Result<()> {
+        let run = self
+            .run
+            .as_mut()
+            .filter(|run| !run.stopped)
+            .ok_or(anyhow!("RunStop received with no run in progress"))?;
+
+        if let Some(run_name) = data.run_name() {
+            if run_name != run.parameters.name {
+                return Err(anyhow!(
+                    "RunStop for run \"{run_name}\" does not match the current run \"{}\"",
+                    run.parameters.name
+                ));
+            }
+        }
+
+        // A stop time of zero indicates that the run should stop now
+        let stop_time = match data.stop_time() {
+            0 => Utc::now(),
+            stop_time => datetime_from_millis(stop_time)?,
+        };
+        run.parameters.stop_time = Some(stop_time);
+        run.stopped = true;
+
+        if self.grace_period <= Duration::zero() {
+            if let Some(run) = self.run.take() {
+                run.finish(stop_time)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn push(&mut self, data: &FrameAssembledEventListMessage<'_>) -> Result<()> {
+        let timestamp: DateTime<Utc> = (*data
+            .metadata()
+            .timestamp()
+            .ok_or(anyhow!("no timestamp in message"))?)
+        .into();
+
+        // Close the current run once a frame arrives beyond its stop time and grace period
+        self.close_expired(timestamp)?;
+
+        match self.run.as_mut() {
+            Some(run) if run.contains(timestamp) => run.file.push(data),
+            _ => {
+                debug!("Frame at {timestamp} is not part of a run, discarding");
+                Ok(())
+            }
+        }
+    }
+
+    /// Closes the current run if the grace period past its stop time has passed by `now`,
+    /// so that a run is closed even if no later frames arrive.
+    pub(crate) fn close_expired(&mut self, now: DateTime<Utc>) -> Result<()> {
+        if let Some(stop_time) = self
+            .run
+            .as_ref()
+            .and_then(|run| run.parameters.stop_time)
+            .filter(|stop_time| now >= *stop_time + self.grace_period)
+        {
+            if let Some(run) = self.run.take() {
+                run.finish(stop_time)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use hdf5::{types::VarLenUnicode, File};
+    use std::{env, fs};
+    use supermusr_streaming_types::{
+        aev1_frame_assembled_event_v1_generated::{
+            finish_frame_assembled_event_list_message_buffer,
+            root_as_frame_assembled_event_list_message, FrameAssembledEventListMessageArgs,
+        },
+        ecs_6s4t_run_stop_generated::{finish_run_stop_buffer, root_as_run_stop, RunStopArgs},
+        ecs_pl72_run_start_generated::{finish_run_start_buffer, root_as_run_start, RunStartArgs},
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v1_generated::{FrameMetadataV1, FrameMetadataV1Args, GpsTime},
+    };
+
+    fn millis(time: DateTime<Utc>) -> u64 {
+        time.timestamp_millis() as u64
+    }
+
+    fn start_run_with_filename(
+        manager: &mut RunManager,
+        run_name: &str,
+        filename: &str,
+        start_time: DateTime<Utc>,
+        stop_time: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let mut fbb = FlatBufferBuilder::new();
+        let run_start = RunStartArgs {
+            start_time: millis(start_time),
+            stop_time: stop_time.map(millis).unwrap_or_default(),
+            run_name: Some(fbb.create_string(run_name)),
+            filename: Some(fbb.create_string(filename)),
+            ..Default::default()
+        };
+        let message = RunStart::create(&mut fbb, &run_start);
+        finish_run_start_buffer(&mut fbb, message);
+
+        let message = root_as_run_start(fbb.finished_data()).unwrap();
+        manager.start_run(&message)
+    }
+
+    fn start_run(
+        manager: &mut RunManager,
+        run_name: &str,
+        start_time: DateTime<Utc>,
+        stop_time: Option<DateTime<Utc>>,
+    ) {
+        let filename = format!("{run_name}.h5");
+        start_run_with_filename(manager, run_name, &filename, start_time, stop_time).unwrap();
+    }
+
+    fn stop_run(manager: &mut RunManager, run_name: &str, stop_time: DateTime<Utc>) -> Result<()> {
+        let mut fbb = FlatBufferBuilder::new();
+        let run_stop = RunStopArgs {
+            stop_time: millis(stop_time),
+            run_name: Some(fbb.create_string(run_name)),
+            ..Default::default()
+        };
+        let message = RunStop::create(&mut fbb, &run_stop);
+        finish_run_stop_buffer(&mut fbb, message);
+
+        let message = root_as_run_stop(fbb.finished_data()).unwrap();
+        manager.stop_run(&message)
+    }
+
+    fn push_frame(
+        manager: &mut RunManager,
+        num_events: usize,
+        frame_number: u32,
+        time: DateTime<Utc>,
+    ) {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = time.into();
+        let metadata = FrameMetadataV1Args {
+            frame_number,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            timestamp: Some(&time),
+            veto_flags: 0,
+        };
+        let metadata = FrameMetadataV1::create(&mut fbb, &metadata);
+
+        let time = Some(fbb.create_vector(&vec![frame_number; num_events]));
+        let voltage = Some(fbb.create_vector(&vec![frame_number as u16; num_events]));
+        let channel = Some(fbb.create_vector(&vec![frame_number; num_events]));
+
+        let message = FrameAssembledEventListMessageArgs {
+            metadata: Some(metadata),
+            time,
+            voltage,
+            channel,
+            complete: true,
+        };
+        let message = FrameAssembledEventListMessage::create(&mut fbb, &message);
+        finish_frame_assembled_event_list_message_buffer(&mut fbb, message);
+
+        let message = root_as_frame_assembled_event_list_message(fbb.finished_data()).unwrap();
+        manager.push(&message).unwrap();
+    }
+
+    fn read_run_file(run_name: &str) -> File {
+        let filepath = env::temp_dir().join(format!("{run_name}.h5"));
+        let file = File::open(&filepath).unwrap();
+        let _ = fs::remove_file(filepath);
+        file
+    }
+
+    #[test]
+    fn frames_outside_run_are_discarded() {
+        let run_name = "RunManager_test_frames_outside_run_are_discarded";
+        let start = Utc::now();
+        let stop = start + Duration::seconds(1);
+
+        let mut manager =
+            RunManager::new(env::temp_dir(), EventFileLayout::Simple, Duration::zero());
+
+        push_frame(&mut manager, 10, 0, start - Duration::milliseconds(20));
+        start_run(&mut manager, run_name, start, None);
+        push_frame(&mut manager, 20, 1, start - Duration::milliseconds(20));
+        push_frame(&mut manager, 30, 2, start);
+        push_frame(&mut manager, 40, 3, start + Duration::milliseconds(20));
+        stop_run(&mut manager, run_name, stop).unwrap();
+        push_frame(&mut manager, 50, 4, start + Duration::milliseconds(40));
+
+        let file = read_run_file(run_name);
+
+        let time = file.dataset("event_data/time").unwrap();
+        assert_eq!(time.shape(), vec![30 + 40]);
+
+        let frame_number = file.dataset("frame_number").unwrap();
+        assert_eq!(frame_number.read_raw::<u32>().unwrap(), vec![2, 3]);
+
+        let name = file.dataset("run/name").unwrap();
+        assert_eq!(
+            name.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            run_name
+        );
+
+        let stop_time = file.dataset("run/stop_time").unwrap();
+        assert_eq!(
+            stop_time.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            datetime_from_millis(millis(stop)).unwrap().to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn run_with_stop_time_is_closed_by_later_frame() {
+        let run_name = "RunManager_test_run_with_stop_time_is_closed_by_later_frame";
+        let start = Utc::now();
+        let stop = start + Duration::milliseconds(30);
+
+        let mut manager =
+            RunManager::new(env::temp_dir(), EventFileLayout::Simple, Duration::zero());
+
+        start_run(&mut manager, run_name, start, Some(stop));
+        push_frame(&mut manager, 20, 0, start);
+        push_frame(&mut manager, 30, 1, start + Duration::milliseconds(20));
+        push_frame(&mut manager, 40, 2, start + Duration::milliseconds(40));
+
+        assert!(manager.run.is_none());
+        assert!(stop_run(&mut manager, run_name, stop).is_err());
+
+        let file = read_run_file(run_name);
+
+        let time = file.dataset("event_data/time").unwrap();
+        assert_eq!(time.shape(), vec![20 + 30]);
+
+        assert!(file.dataset("run/stop_time").is_ok());
+    }
+
+    #[test]
+    fn mismatched_run_stop_is_rejected() {
+        let run_name = "RunManager_test_mismatched_run_stop_is_rejected";
+        let start = Utc::now();
+
+        let mut manager =
+            RunManager::new(env::temp_dir(), EventFileLayout::Simple, Duration::zero());
+
+        start_run(&mut manager, run_name, start, None);
+        assert!(stop_run(&mut manager, "some_other_run", start).is_err());
+        assert!(manager.run.is_some());
+
+        stop_run(&mut manager, run_name, start).unwrap();
+        assert!(manager.run.is_none());
+
+        let _ = read_run_file(run_name);
+    }
+
+    #[test]
+    fn late_frames_are_kept_within_grace_period() {
+        let run_name = "RunManager_test_late_frames_are_kept_within_grace_period";
+        let start = Utc::now();
+        let stop = start + Duration::milliseconds(50);
+
+        let mut manager = RunManager::new(
+            env::temp_dir(),
+            EventFileLayout::Simple,
+            Duration::milliseconds(100),
+        );
+
+        start_run(&mut manager, run_name, start, None);
+        push_frame(&mut manager, 20, 0, start);
+        stop_run(&mut manager, run_name, stop).unwrap();
+        assert!(stop_run(&mut manager, run_name, stop).is_err());
+
+        // Frames of the run which arrive after its RunStop are kept
+        push_frame(&mut manager, 30, 1, start + Duration::milliseconds(40));
+        push_frame(&mut manager, 40, 2, start + Duration::milliseconds(60));
+        push_frame(&mut manager, 50, 3, start + Duration::milliseconds(20));
+        assert!(manager.run.is_some());
+
+        push_frame(&mut manager, 60, 4, stop + Duration::milliseconds(100));
+        assert!(manager.run.is_none());
+
+        let file = read_run_file(run_name);
+
+        let frame_number = file.dataset("frame_number").unwrap();
+        assert_eq!(frame_number.read_raw::<u32>().unwrap(), vec![0, 1, 3]);
+
+        let stop_time = file.dataset("run/stop_time").unwrap();
+        assert_eq!(
+            stop_time.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            datetime_from_millis(millis(stop)).unwrap().to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn stopped_run_is_closed_after_grace_period() {
+        let run_name = "RunManager_test_stopped_run_is_closed_after_grace_period";
+        let start = Utc::now();
+        let stop = start + Duration::milliseconds(50);
+
+        let mut manager = RunManager::new(
+            env::temp_dir(),
+            EventFileLayout::Simple,
+            Duration::milliseconds(100),
+        );
+
+        start_run(&mut manager, run_name, start, None);
+        push_frame(&mut manager, 20, 0, start);
+        stop_run(&mut manager, run_name, stop).unwrap();
+
+        manager
+            .close_expired(stop + Duration::milliseconds(99))
+            .unwrap();
+        assert!(manager.run.is_some());
+
+        // No further frames arrive, but the run is still closed
+        manager
+            .close_expired(stop + Duration::milliseconds(100))
+            .unwrap();
+        assert!(manager.run.is_none());
+
+        let file = read_run_file(run_name);
+
+        let frame_number = file.dataset("frame_number").unwrap();
+        assert_eq!(frame_number.read_raw::<u32>().unwrap(), vec![0]);
+
+        let stop_time = file.dataset("run/stop_time").unwrap();
+        assert_eq!(
+            stop_time.read_scalar::<VarLenUnicode>().unwrap().as_str(),
+            datetime_from_millis(millis(stop)).unwrap().to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn filenames_outside_output_dir_are_rejected() {
+        let start = Utc::now();
+        let mut manager =
+            RunManager::new(env::temp_dir(), EventFileLayout::Simple, Duration::zero());
+
+        for filename in ["../run.h5", "/tmp/run.h5", "runs/run.h5", "..", ""] {
+            assert!(
+                start_run_with_filename(&mut manager, "run", filename, start, None).is_err(),
+                "{filename}"
+            );
+        }
+        assert!(manager.run.is_none());
+    }
+}
@@ -19,6 +19,10 @@ fn main() {
             schema_dir.join("hst1_histogram_v1.fbs").as_path(),
             schema_dir.join("ecs_6s4t_run_stop.fbs").as_path(),
             schema_dir.join("ecs_df12_det_spec_map.fbs").as_path(),
+            schema_dir.join("ecs_dtdb_adc_pulse_debug.fbs").as_path(),
+            schema_dir.join("ecs_ev42_events.fbs").as_path(),
+            schema_dir.join("ecs_ev44_events.fbs").as_path(),
+            schema_dir.join("ecs_is84_isis_events.fbs").as_path(),
             schema_dir.join("ecs_pl72_run_start.fbs").as_path(),
         ],
         out_dir: target_dir,
@@ -29,3 +29,19 @@ pub mod ecs_df12_det_spec_map_generated;
 #[rustfmt::skip]
 #[allow(unused_imports, clippy::derivable_impls, clippy::derive_partial_eq_without_eq, clippy::extra_unused_lifetimes, clippy::missing_safety_doc, clippy::size_of_in_element_count, clippy::unnecessary_cast, clippy::needless_lifetimes)]
 pub mod ecs_pl72_run_start_generated;
+
+#[rustfmt::skip]
+#[allow(unused_imports, clippy::derivable_impls, clippy::derive_partial_eq_without_eq, clippy::extra_unused_lifetimes, clippy::missing_safety_doc, clippy::size_of_in_element_count, clippy::unnecessary_cast, clippy::needless_lifetimes)]
+pub mod ecs_dtdb_adc_pulse_debug_generated;
+
+#[rustfmt::skip]
+#[allow(unused_imports, clippy::derivable_impls, clippy::derive_partial_eq_without_eq, clippy::extra_unused_lifetimes, clippy::missing_safety_doc, clippy::size_of_in_element_count, clippy::unnecessary_cast, clippy::needless_lifetimes)]
+pub mod ecs_ev42_events_generated;
+
+#[rustfmt::skip]
+#[allow(unused_imports, clippy::derivable_impls, clippy::derive_partial_eq_without_eq, clippy::extra_unused_lifetimes, clippy::missing_safety_doc, clippy::size_of_in_element_count, clippy::unnecessary_cast, clippy::needless_lifetimes)]
+pub mod ecs_ev44_events_generated;
+
+#[rustfmt::skip]
+#[allow(unused_imports, clippy::derivable_impls, clippy::derive_partial_eq_without_eq, clippy::extra_unused_lifetimes, clippy::missing_safety_doc, clippy::size_of_in_element_count, clippy::unnecessary_cast, clippy::needless_lifetimes)]
+pub mod ecs_is84_isis_events_generated;
@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
 use clap::Parser;
-use rand::{seq::IteratorRandom, thread_rng};
+use rand::{seq::IteratorRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rdkafka::producer::FutureProducer;
 use std::path::PathBuf;
 use supermusr_common::{DigitizerId, FrameNumber};
@@ -51,6 +53,14 @@ struct Cli {
     /// If set, then trace events are sampled randomly with replacement, if not set then trace events are read in order
     #[clap(long, default_value = "false")]
     random_sample: bool,
+
+    /// Seed of the random sampling of trace events. If not given, the sample differs each time
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Timestamp given to every message. If not given, messages are timestamped when sent
+    #[clap(long)]
+    start_time: Option<DateTime<Utc>>,
 }
 
 #[tokio::main]
@@ -78,12 +88,12 @@ async fn main() {
     };
 
     let trace_event_indices: Vec<_> = if args.random_sample {
+        let mut rng = match args.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
         (0..num_trace_events)
-            .map(|_| {
-                (0..num_trace_events)
-                    .choose(&mut thread_rng())
-                    .unwrap_or_default()
-            })
+            .map(|_| (0..num_trace_events).choose(&mut rng).unwrap_or_default())
             .collect()
     } else {
         (0..num_trace_events)
@@ -97,6 +107,7 @@ async fn main() {
         trace_event_indices,
         args.frame_number,
         args.digitizer_id,
+        args.start_time,
         &producer,
         &args.trace_topic,
         6000,
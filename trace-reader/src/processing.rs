@@ -3,7 +3,7 @@
 
 use super::loader::{TraceFile, TraceFileEvent};
 use anyhow::{Error, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rdkafka::{
     producer::{FutureProducer, FutureRecord},
     util::Timeout,
@@ -11,7 +11,7 @@ use rdkafka::{
 use std::time::Duration;
 use tracing::{debug, error};
 
-use supermusr_common::{Channel, DigitizerId, FrameNumber, Intensity};
+use supermusr_common::{kafka_key::digitiser_key, Channel, DigitizerId, FrameNumber, Intensity};
 use supermusr_streaming_types::{
     dat1_digitizer_analog_trace_v1_generated::{
         finish_digitizer_analog_trace_message_buffer, ChannelTrace, ChannelTraceArgs,
@@ -22,21 +22,25 @@ use supermusr_streaming_types::{
 };
 
 /// Reads the contents of trace_file and dispatches messages to the given Kafka topic.
+/// Messages are given `timestamp` if given, otherwise the time they are sent.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn dispatch_trace_file(
     mut trace_file: TraceFile,
     trace_event_indices: Vec<usize>,
     frame_number: FrameNumber,
     digitizer_id: DigitizerId,
+    timestamp: Option<DateTime<Utc>>,
     producer: &FutureProducer,
     topic: &str,
     timeout_ms: u64,
 ) -> Result<()> {
+    let key = digitiser_key(digitizer_id);
     let mut fbb = FlatBufferBuilder::new();
     for index in trace_event_indices {
         let event = trace_file.get_trace_event(index)?;
         create_message(
             &mut fbb,
-            Utc::now().into(),
+            timestamp.unwrap_or_else(Utc::now).into(),
             frame_number,
             digitizer_id,
             trace_file.get_num_channels(),
@@ -44,7 +48,9 @@ pub(crate) async fn dispatch_trace_file(
             &event,
         )?;
 
-        let future_record = FutureRecord::to(topic).payload(fbb.finished_data()).key("");
+        let future_record = FutureRecord::to(topic)
+            .payload(fbb.finished_data())
+            .key(&key);
         let timeout = Timeout::After(Duration::from_millis(timeout_ms));
         match producer.send(future_record, timeout).await {
             Ok(r) => debug!("Delivery: {:?}", r),
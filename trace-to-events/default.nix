@@ -5,6 +5,7 @@
   git_revision,
   nativeBuildInputs,
   buildInputs,
+  hdf5-joined,
 }: rec {
   trace-to-events = naersk'.buildPackage {
     name = "trace-to-events";
@@ -19,6 +20,8 @@
     overrideMain = p: {
       GIT_REVISION = git_revision;
     };
+
+    HDF5_DIR = "${hdf5-joined}";
   };
 
   trace-to-events-container-image = pkgs.dockerTools.buildImage {
@@ -0,0 +1,574 @@
+use crate::pulse_detection::{Pulse, Real};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use hdf5::{types::VarLenUnicode, File, Group, H5Type};
+use serde::Deserialize;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+use supermusr_common::{Channel, DigitizerId};
+use supermusr_streaming_types::dat1_digitizer_analog_trace_v1_generated::DigitizerAnalogTraceMessage;
+use tracing::{info, warn};
+
+/// Options for capturing the signals of the detectors to HDF5 files, for debugging.
+#[derive(Debug, Clone, Default, Args)]
+pub(crate) struct CaptureOptions {
+    /// Directory to which debug captures of the signals of the detectors are written, one HDF5 file per capture. If not given, nothing is captured. See README.md.
+    #[clap(long)]
+    pub(crate) capture_dir: Option<PathBuf>,
+
+    /// Capture every Nth trace message of the captured digitisers. If not given, messages are only captured when requested on the control topic.
+    #[clap(long)]
+    pub(crate) capture_every: Option<u64>,
+
+    /// Digitiser of which trace messages are captured, which may be given more than once. If not given, all digitisers are captured.
+    #[clap(long = "capture-digitiser")]
+    pub(crate) capture_digitisers: Vec<DigitizerId>,
+
+    /// Channel which is captured, which may be given more than once. If not given, all channels are captured.
+    #[clap(long = "capture-channel")]
+    pub(crate) capture_channels: Vec<Channel>,
+}
+
+/// A request, received on the control topic, to capture the next `capture` trace messages
+/// of the captured digitisers.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaptureRequest {
+    capture: u64,
+}
+
+/// Parses a capture request given as either JSON or TOML, returning the number of messages to capture.
+pub(crate) fn parse_request(text: &str) -> Result<u64> {
+    let request: CaptureRequest = if text.trim_start().starts_with('{') {
+        serde_json::from_str(text)?
+    } else {
+        toml::from_str(text)?
+    };
+    Ok(request.capture)
+}
+
+/// Decides which trace messages are captured, and owns the thread writing the captures.
+pub(crate) struct CaptureTrigger {
+    options: CaptureOptions,
+    /// Number of messages of the captured digitisers since the last periodic capture
+    seen: u64,
+    /// The capture being made on request, and the number of its messages which remain
+    requested: Option<(Capture, u64)>,
+    /// Number of captures started, which distinguishes captures started at the same time
+    started: u64,
+    sender: Sender<CapturedMessage>,
+    #[cfg(test)]
+    writer: thread::JoinHandle<()>,
+}
+
+impl CaptureTrigger {
+    /// Returns `None` if capturing is not enabled.
+    pub(crate) fn new(options: CaptureOptions) -> Option<Self> {
+        let mut writer = CaptureWriter::new(options.capture_dir.clone()?);
+        let (sender, receiver) = mpsc::channel::<CapturedMessage>();
+        let _writer = thread::Builder::new()
+            .name("capture-writer".to_owned())
+            .spawn(move || {
+                for message in receiver {
+                    writer.write(message);
+                }
+            })
+            .expect("capture writer thread should be spawned");
+        Some(Self {
+            options,
+            seen: 0,
+            requested: None,
+            started: 0,
+            sender,
+            #[cfg(test)]
+            writer: _writer,
+        })
+    }
+
+    pub(crate) fn request(&mut self, messages: u64) {
+        if messages == 0 {
+            return;
+        }
+        match &mut self.requested {
+            Some((_, remaining)) => *remaining += messages,
+            None => self.requested = Some((self.start(), messages)),
+        }
+    }
+
+    /// Returns the capture to be made of a trace message from the given digitiser, if any.
+    pub(crate) fn trigger(&mut self, digitiser_id: DigitizerId) -> Option<Capture> {
+        let digitisers = &self.options.capture_digitisers;
+        if !digitisers.is_empty() && !digitisers.contains(&digitiser_id) {
+            return None;
+        }
+        self.seen += 1;
+
+        let periodic = match self.options.capture_every {
+            Some(every) if every > 0 && self.seen >= every => {
+                self.seen = 0;
+                true
+            }
+            _ => false,
+        };
+
+        if let Some((capture, remaining)) = &mut self.requested {
+            *remaining -= 1;
+            if *remaining > 0 {
+                return Some(capture.next(false));
+            }
+            let capture = capture.next(true);
+            self.requested = None;
+            Some(capture)
+        } else if periodic {
+            Some(self.start().next(true))
+        } else {
+            None
+        }
+    }
+
+    /// Waits for all captures to be written.
+    #[cfg(test)]
+    pub(crate) fn finish(self) {
+        drop(self.requested);
+        drop(self.sender);
+        self.writer
+            .join()
+            .expect("capture writer thread should not panic");
+    }
+
+    fn start(&mut self) -> Capture {
+        self.started += 1;
+        let file_name = format!(
+            "capture_{}_{}.h5",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            self.started
+        );
+        Capture {
+            file_name,
+            message: 0,
+            last: false,
+            channels: self.options.capture_channels.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+/// A trace message of a capture, which is written to the capture file
+/// after processing, so that the file is not written by the processing thread.
+#[derive(Debug, Clone)]
+pub(crate) struct Capture {
+    file_name: String,
+    /// Index of the message within the capture
+    message: u64,
+    /// Set for the last message of the capture
+    last: bool,
+    channels: Vec<Channel>,
+    sender: Sender<CapturedMessage>,
+}
+
+/// The signals of a channel, with times in samples, and the pulses found in them.
+#[derive(Default)]
+pub(crate) struct ChannelCapture {
+    signals: Vec<(&'static str, Vec<(Real, Real)>)>,
+    pulses: Vec<Pulse>,
+}
+
+impl ChannelCapture {
+    pub(crate) fn signal(
+        &mut self,
+        name: &'static str,
+        signal: impl Iterator<Item = (Real, Real)>,
+    ) {
+        self.signals.push((name, signal.collect()));
+    }
+
+    pub(crate) fn pulses(&mut self, pulses: impl Iterator<Item = Pulse>) {
+        self.pulses.extend(pulses);
+    }
+
+    fn write(&self, group: &Group) -> Result<()> {
+        for (name, signal) in &self.signals {
+            let (time, value): (Vec<Real>, Vec<Real>) = signal.iter().copied().unzip();
+            write_dataset(group, &format!("{name}/time"), &time)?;
+            write_dataset(group, &format!("{name}/value"), &value)?;
+        }
+
+        // Properties the detector did not find are NaN
+        type Field = fn(&Pulse) -> Option<Real>;
+        let fields: [(&str, Field); 9] = [
+            ("start_time", |pulse| pulse.start.time),
+            ("start_value", |pulse| pulse.start.value),
+            ("peak_time", |pulse| pulse.peak.time),
+            ("peak_value", |pulse| pulse.peak.value),
+            ("end_time", |pulse| pulse.end.time),
+            ("end_value", |pulse| pulse.end.value),
+            ("steepest_rise_time", |pulse| pulse.steepest_rise.time),
+            ("steepest_rise_gradient", |pulse| {
+                pulse.steepest_rise.value.map(|value| value[1])
+            }),
+            ("sharpest_fall_time", |pulse| pulse.sharpest_fall.time),
+        ];
+        for (name, field) in fields {
+            let values: Vec<Real> = self
+                .pulses
+                .iter()
+                .map(|pulse| field(pulse).unwrap_or(Real::NAN))
+                .collect();
+            write_dataset(group, &format!("pulses/{name}"), &values)?;
+        }
+        Ok(())
+    }
+}
+
+impl Capture {
+    /// Returns the capture of the next message, advancing this one.
+    fn next(&mut self, last: bool) -> Self {
+        let capture = Self {
+            last,
+            ..self.clone()
+        };
+        self.message += 1;
+        capture
+    }
+
+    /// If no channels are given, all channels are captured.
+    pub(crate) fn includes(&self, channel: Channel) -> bool {
+        self.channels.is_empty() || self.channels.contains(&channel)
+    }
+
+    /// Queues the captured channels of a trace message to be written.
+    pub(crate) fn send(
+        &self,
+        trace: &DigitizerAnalogTraceMessage,
+        channels: Vec<(Channel, ChannelCapture)>,
+    ) {
+        let message = CapturedMessage {
+            file_name: self.file_name.clone(),
+            message: self.message,
+            last: self.last,
+            metadata: CapturedMetadata::new(trace),
+            channels,
+        };
+        if self.sender.send(message).is_err() {
+            warn!("Capture writer has stopped, trace message not captured");
+        }
+    }
+}
+
+/// The metadata of a captured trace message.
+#[derive(Debug)]
+struct CapturedMetadata {
+    digitiser_id: DigitizerId,
+    sample_rate: u64,
+    frame_number: u32,
+    period_number: u64,
+    protons_per_pulse: u8,
+    running: bool,
+    veto_flags: u16,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl CapturedMetadata {
+    fn new(trace: &DigitizerAnalogTraceMessage) -> Self {
+        let metadata = trace.metadata();
+        Self {
+            digitiser_id: trace.digitizer_id(),
+            sample_rate: trace.sample_rate(),
+            frame_number: metadata.frame_number(),
+            period_number: metadata.period_number(),
+            protons_per_pulse: metadata.protons_per_pulse(),
+            running: metadata.running(),
+            veto_flags: metadata.veto_flags(),
+            timestamp: metadata.timestamp().map(|time| (*time).into()),
+        }
+    }
+
+    fn write(&self, group: &Group) -> Result<()> {
+        write_scalar(group, "metadata/digitiser_id", &self.digitiser_id)?;
+        write_scalar(group, "metadata/sample_rate", &self.sample_rate)?;
+        write_scalar(group, "metadata/frame_number", &self.frame_number)?;
+        write_scalar(group, "metadata/period_number", &self.period_number)?;
+        write_scalar(group, "metadata/protons_per_pulse", &self.protons_per_pulse)?;
+        write_scalar(group, "metadata/running", &self.running)?;
+        write_scalar(group, "metadata/veto_flags", &self.veto_flags)?;
+        if let Some(timestamp) = self.timestamp {
+            write_scalar(
+                group,
+                "metadata/timestamp",
+                &VarLenUnicode::from_str(&timestamp.to_rfc3339())?,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A processed trace message to be written to its capture file.
+pub(crate) struct CapturedMessage {
+    file_name: String,
+    message: u64,
+    last: bool,
+    metadata: CapturedMetadata,
+    channels: Vec<(Channel, ChannelCapture)>,
+}
+
+/// A capture file, which is closed once all its messages are written.
+struct CaptureFile {
+    file: File,
+    written: u64,
+    /// Number of messages of the capture, known once its last message is written
+    messages: Option<u64>,
+}
+
+/// Writes captured messages, each capture to a new file in the capture directory.
+struct CaptureWriter {
+    dir: PathBuf,
+    files: HashMap<String, CaptureFile>,
+}
+
+impl CaptureWriter {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            files: Default::default(),
+        }
+    }
+
+    fn write(&mut self, message: CapturedMessage) {
+        if let Err(e) = self.try_write(&message) {
+            warn!(
+                "Failed to capture trace message to {}: {e}",
+                message.file_name
+            );
+        }
+    }
+
+    fn try_write(&mut self, message: &CapturedMessage) -> Result<()> {
+        let capture = match self.files.entry(message.file_name.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = self.dir.join(entry.key());
+                // Never overwrite an earlier capture
+                let file = File::create_excl(&path)?;
+                info!("Capturing trace messages to {}", path.display());
+                entry.insert(CaptureFile {
+                    file,
+                    written: 0,
+                    messages: None,
+                })
+            }
+        };
+
+        let group = capture
+            .file
+            .create_group(&format!("message_{}", message.message))?;
+        message.metadata.write(&group)?;
+        for (channel, channel_capture) in &message.channels {
+            channel_capture.write(&group.create_group(&format!("channel_{channel}"))?)?;
+        }
+        capture.file.flush()?;
+
+        capture.written += 1;
+        if message.last {
+            capture.messages = Some(message.message + 1);
+        }
+        if capture.messages == Some(capture.written) {
+            self.files.remove(&message.file_name);
+        }
+        Ok(())
+    }
+}
+
+fn write_scalar<T: H5Type>(group: &Group, name: &str, value: &T) -> Result<()> {
+    group.new_dataset::<T>().create(name)?.write_scalar(value)?;
+    Ok(())
+}
+
+fn write_dataset(group: &Group, name: &str, values: &[Real]) -> Result<()> {
+    group
+        .new_dataset_builder()
+        .with_data(values)
+        .create(name)
+        .map_err(|e| anyhow!("{name}: {e}"))?;
+    Ok(())
+}
+
+/// Checks that the capture directory exists, so that a misconfigured capture is found at startup.
+pub(crate) fn check_dir(options: &CaptureOptions) -> Result<()> {
+    match &options.capture_dir {
+        Some(dir) if !dir.is_dir() => Err(anyhow!(
+            "capture directory {} does not exist",
+            dir.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::pulse::TimeValueOptional;
+    use supermusr_streaming_types::{
+        dat1_digitizer_analog_trace_v1_generated::{
+            finish_digitizer_analog_trace_message_buffer, root_as_digitizer_analog_trace_message,
+            DigitizerAnalogTraceMessageArgs,
+        },
+        flatbuffers::FlatBufferBuilder,
+        frame_metadata_v1_generated::{FrameMetadataV1, FrameMetadataV1Args, GpsTime},
+    };
+
+    fn options(every: Option<u64>, digitisers: Vec<DigitizerId>) -> CaptureOptions {
+        CaptureOptions {
+            capture_dir: Some(std::env::temp_dir()),
+            capture_every: every,
+            capture_digitisers: digitisers,
+            capture_channels: vec![],
+        }
+    }
+
+    #[test]
+    fn disabled_without_dir() {
+        assert!(CaptureTrigger::new(CaptureOptions::default()).is_none());
+    }
+
+    #[test]
+    fn every_nth_message() {
+        let mut trigger = CaptureTrigger::new(options(Some(3), vec![2, 4])).unwrap();
+        let captured: Vec<_> = [2, 1, 4, 2, 1, 1, 4, 2, 4]
+            .into_iter()
+            .map(|digitiser_id| trigger.trigger(digitiser_id).is_some())
+            .collect();
+        // Only messages of digitisers 2 and 4 are counted
+        assert_eq!(
+            captured,
+            [false, false, false, true, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn requested_messages() {
+        let mut trigger = CaptureTrigger::new(options(None, vec![])).unwrap();
+        assert!(trigger.trigger(0).is_none());
+
+        trigger.request(parse_request(r#"{"capture": 2}"#).unwrap());
+        let first = trigger.trigger(0).unwrap();
+        let second = trigger.trigger(1).unwrap();
+        assert!(trigger.trigger(0).is_none());
+        // Requested messages are captured to the same file
+        assert_eq!(first.file_name, second.file_name);
+        assert_eq!((first.message, first.last), (0, false));
+        assert_eq!((second.message, second.last), (1, true));
+
+        trigger.request(parse_request("capture = 1").unwrap());
+        let third = trigger.trigger(3).unwrap();
+        assert!(trigger.trigger(3).is_none());
+        assert_ne!(first.file_name, third.file_name);
+
+        assert!(parse_request(r#"{"capture": 1, "mode": "template-fit"}"#).is_err());
+        assert!(parse_request(r#"mode = "template-fit""#).is_err());
+    }
+
+    #[test]
+    fn channel_selection() {
+        let mut trigger = CaptureTrigger::new(CaptureOptions {
+            capture_channels: vec![1, 5],
+            ..options(Some(1), vec![])
+        })
+        .unwrap();
+        let capture = trigger.trigger(0).unwrap();
+        assert!(capture.includes(5));
+        assert!(!capture.includes(2));
+    }
+
+    fn trace_message(fbb: &mut FlatBufferBuilder, frame_number: u32) {
+        let metadata = FrameMetadataV1Args {
+            frame_number,
+            period_number: 3,
+            protons_per_pulse: 8,
+            running: true,
+            veto_flags: 0,
+            timestamp: Some(&GpsTime::new(24, 100, 10, 30, 12, 1, 2, 3)),
+        };
+        let metadata = FrameMetadataV1::create(fbb, &metadata);
+        let message = DigitizerAnalogTraceMessageArgs {
+            digitizer_id: 7,
+            metadata: Some(metadata),
+            sample_rate: 1_000_000_000,
+            channels: None,
+        };
+        let message = DigitizerAnalogTraceMessage::create(fbb, &message);
+        finish_digitizer_analog_trace_message_buffer(fbb, message);
+    }
+
+    #[test]
+    fn write_capture() {
+        let dir =
+            std::env::temp_dir().join(format!("trace-to-events-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut trigger = CaptureTrigger::new(CaptureOptions {
+            capture_dir: Some(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Two captures of messages with the same frame number
+        for _ in 0..2 {
+            trigger.request(2);
+            for frame_number in [12, 13] {
+                let mut fbb = FlatBufferBuilder::new();
+                trace_message(&mut fbb, frame_number);
+                let trace = root_as_digitizer_analog_trace_message(fbb.finished_data()).unwrap();
+
+                let mut channel = ChannelCapture::default();
+                channel.signal("raw", [(0.0, 1.0), (1.0, 5.0), (2.0, 2.0)].into_iter());
+                channel.pulses(
+                    [Pulse {
+                        peak: TimeValueOptional {
+                            time: Some(1.0),
+                            value: Some(5.0),
+                        },
+                        ..Default::default()
+                    }]
+                    .into_iter(),
+                );
+                trigger.trigger(7).unwrap().send(&trace, vec![(3, channel)]);
+            }
+        }
+        trigger.finish();
+
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[1]
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .ends_with("_2.h5"));
+
+        let file = File::open(&paths[0]).unwrap();
+        let read = |name: &str| file.dataset(name).unwrap().read_raw::<Real>().unwrap();
+        assert_eq!(file.member_names().unwrap(), ["message_0", "message_1"]);
+        assert_eq!(
+            file.dataset("message_1/metadata/frame_number")
+                .unwrap()
+                .read_scalar::<u32>()
+                .unwrap(),
+            13
+        );
+        assert_eq!(read("message_0/channel_3/raw/value"), vec![1.0, 5.0, 2.0]);
+        assert_eq!(read("message_0/channel_3/raw/time"), vec![0.0, 1.0, 2.0]);
+        assert_eq!(read("message_0/channel_3/pulses/peak_value"), vec![5.0]);
+        assert!(read("message_0/channel_3/pulses/start_time")[0].is_nan());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,473 @@
+use crate::parameters::{DetectorConfig, Mode};
+use anyhow::{anyhow, Result};
+use kagiyama::prometheus::{self as prometheus_client, encoding::EncodeLabelValue};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use supermusr_common::{Channel, DigitizerId};
+
+/// Where the active detector configuration was loaded from.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, EncodeLabelValue)]
+pub(crate) enum ConfigSource {
+    CommandLine,
+    File,
+    ControlTopic,
+}
+
+/// Parameters of a single channel, which replace those of the default parameters.
+#[derive(Deserialize)]
+struct ChannelParameters {
+    digitiser_id: DigitizerId,
+    channel: Channel,
+    #[serde(flatten)]
+    parameters: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    channels: Vec<ChannelParameters>,
+    #[serde(flatten)]
+    default: Map<String, Value>,
+}
+
+/// Parses a table given as either JSON or TOML.
+/// JSON is assumed if the text is a JSON object.
+pub(crate) fn parse_table(text: &str) -> Result<Map<String, Value>> {
+    Ok(if text.trim_start().starts_with('{') {
+        serde_json::from_str(text)?
+    } else {
+        toml::from_str(text)?
+    })
+}
+
+/// Parses and validates a detector configuration given as either JSON or TOML.
+/// JSON is assumed if the text is a JSON object.
+pub(crate) fn parse_config(text: &str) -> Result<DetectorConfig> {
+    config_from_table(parse_table(text)?)
+}
+
+/// Validates a detector configuration given as a parsed table.
+pub(crate) fn config_from_table(table: Map<String, Value>) -> Result<DetectorConfig> {
+    let file: ConfigFile = serde_json::from_value(Value::Object(table))?;
+
+    let default: Mode = serde_json::from_value(Value::Object(file.default.clone()))?;
+
+    let mut channels = BTreeMap::new();
+    for ChannelParameters {
+        digitiser_id,
+        channel,
+        parameters,
+    } in file.channels
+    {
+        // Parameters are only inherited from the default if the channel uses the same detector
+        let mut merged = match parameters.get("mode") {
+            Some(mode) if Some(mode) != file.default.get("mode") => Map::new(),
+            _ => file.default.clone(),
+        };
+        merged.extend(parameters);
+
+        let mode: Mode = serde_json::from_value(Value::Object(merged))
+            .map_err(|e| anyhow!("digitiser {digitiser_id} channel {channel}: {e}"))?;
+        if channels.insert((digitiser_id, channel), mode).is_some() {
+            return Err(anyhow!(
+                "digitiser {digitiser_id} channel {channel} given more than once"
+            ));
+        }
+    }
+
+    let config = DetectorConfig { default, channels };
+    config.validate()?;
+    Ok(config)
+}
+
+pub(crate) fn load_config_file(path: &Path) -> Result<DetectorConfig> {
+    let text = fs::read_to_string(path)?;
+    parse_config(&text).map_err(|e| anyhow!("{}: {e}", path.display()))
+}
+
+/// Detects changes to a detector configuration file by its modification time.
+pub(crate) struct ConfigFileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigFileWatcher {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let modified = modified_time(&path);
+        Self { path, modified }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the configuration in the file if it has changed since the last call.
+    pub(crate) fn poll(&mut self) -> Option<Result<DetectorConfig>> {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(load_config_file(&self.path))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::BaselineTracking;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_toml() {
+        let mode = parse_config(
+            r#"
+            mode = "advanced-muon-detector"
+            muon_onset = 1.0
+            muon_fall = -0.1
+            muon_termination = 0.01
+            duration = 2.0
+            smoothing_window_size = 10
+            baseline_tracking = "gated"
+            baseline_gate = 5.0
+            filters = ["pole-zero:20", "trapezoidal:4,2", "low-pass:0.1"]
+            "#,
+        )
+        .unwrap()
+        .default;
+
+        let Mode::AdvancedMuonDetector(parameters) = mode else {
+            panic!("expected advanced muon detector");
+        };
+        assert_eq!(parameters.muon_onset, 1.0);
+        assert_eq!(parameters.muon_fall, -0.1);
+        assert_eq!(parameters.duration, 2.0);
+        assert_eq!(parameters.smoothing_window_size, Some(10));
+        assert_eq!(parameters.baseline_length, None);
+        assert_eq!(parameters.baseline_tracking, BaselineTracking::Gated);
+        assert_eq!(parameters.baseline_gate, Some(5.0));
+        assert_eq!(
+            format!("{:?}", parameters.filters),
+            "[pole-zero:20, trapezoidal:4,2, low-pass:0.1]"
+        );
+    }
+
+    #[test]
+    fn parse_json() {
+        let mode = parse_config(
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,2,1", "filters": ["low-pass:0.25"]}"#,
+        )
+        .unwrap()
+        .default;
+
+        let Mode::ConstantPhaseDiscriminator(parameters) = mode else {
+            panic!("expected constant phase discriminator");
+        };
+        assert_eq!(parameters.threshold_trigger.0.threshold, -5.0);
+        assert_eq!(parameters.threshold_trigger.0.duration, 2);
+        assert_eq!(parameters.threshold_trigger.0.cool_off, 1);
+        assert_eq!(format!("{:?}", parameters.filters), "[low-pass:0.25]");
+    }
+
+    #[test]
+    fn constant_phase_rejects_baseline_filters() {
+        for filter in ["pole-zero:20", "trapezoidal:4,2"] {
+            let config = format!(
+                r#"{{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,2,1", "filters": ["{filter}"]}}"#
+            );
+            assert!(parse_config(&config).is_err(), "{filter}");
+        }
+    }
+
+    #[test]
+    fn hash_identifies_parameters() {
+        let json = parse_config(
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,2,1"}"#,
+        )
+        .unwrap();
+        let toml = parse_config(
+            r#"
+            mode = "constant-phase-discriminator"
+            threshold_trigger = "-5,2,1"
+            "#,
+        )
+        .unwrap();
+        let other = parse_config(
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-6,2,1"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(json.hash(), toml.hash());
+        assert_ne!(json.hash(), other.hash());
+        assert_eq!(json.hash().len(), 16);
+    }
+
+    #[test]
+    fn parse_channel_parameters() {
+        let config = parse_config(
+            r#"
+            mode = "advanced-muon-detector"
+            muon_onset = 1.0
+            muon_fall = -0.1
+            muon_termination = 0.01
+            duration = 2.0
+            baseline_length = 1000
+
+            [[channels]]
+            digitiser_id = 2
+            channel = 5
+            muon_onset = 3.0
+            min_amplitude = 20.0
+
+            [[channels]]
+            digitiser_id = 4
+            channel = 0
+            mode = "constant-phase-discriminator"
+            threshold_trigger = "-5,1,0"
+            "#,
+        )
+        .unwrap();
+
+        let Mode::AdvancedMuonDetector(default) = config.mode(2, 4) else {
+            panic!("expected advanced muon detector");
+        };
+        assert_eq!(default.muon_onset, 1.0);
+        assert_eq!(default.min_amplitude, None);
+        assert_eq!(default.baseline_tracking, BaselineTracking::Fixed);
+
+        let Mode::AdvancedMuonDetector(parameters) = config.mode(2, 5) else {
+            panic!("expected advanced muon detector");
+        };
+        assert_eq!(parameters.muon_onset, 3.0);
+        assert_eq!(parameters.min_amplitude, Some(20.0));
+        assert_eq!(parameters.muon_fall, -0.1);
+        assert_eq!(parameters.baseline_length, Some(1000));
+
+        assert!(matches!(
+            config.mode(4, 0),
+            Mode::ConstantPhaseDiscriminator(_)
+        ));
+    }
+
+    #[test]
+    fn parse_template_fit() {
+        let config = parse_config(
+            r#"
+            mode = "template-fit"
+            rise_time = 2.0
+            decay_time = 12.0
+            threshold = 10.0
+            "#,
+        )
+        .unwrap();
+        let Mode::TemplateFit(parameters) = config.default else {
+            panic!("expected template fit");
+        };
+        assert_eq!(parameters.template().unwrap().len(), 73);
+
+        let path = std::env::temp_dir().join(format!(
+            "trace-to-events-template-{}.csv",
+            std::process::id()
+        ));
+        fs::write(&path, "0.0\n0.5\n1.0\n0.5\n").unwrap();
+        let config = parse_config(&format!(
+            r#"{{"mode": "template-fit", "template_file": "{}", "threshold": 10.0}}"#,
+            path.display()
+        ))
+        .unwrap();
+        let Mode::TemplateFit(parameters) = config.default else {
+            panic!("expected template fit");
+        };
+        assert_eq!(parameters.template().unwrap().len(), 4);
+
+        fs::remove_file(&path).unwrap();
+        // The template is only read once
+        assert!(parameters.template().is_ok());
+    }
+
+    #[test]
+    fn invalid_config_rejected() {
+        // Unknown detector
+        assert!(parse_config(r#"mode = "magic""#).is_err());
+        // Unknown parameter
+        assert!(parse_config(
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,1,0", "threshold": 1}"#
+        )
+        .is_err());
+        // Malformed threshold
+        assert!(parse_config(
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,1"}"#
+        )
+        .is_err());
+        // Zero duration
+        assert!(parse_config(
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,0,0"}"#
+        )
+        .is_err());
+        // Minimum amplitude above maximum amplitude
+        assert!(parse_config(
+            r#"
+            mode = "advanced-muon-detector"
+            muon_onset = 1.0
+            muon_fall = -0.1
+            muon_termination = 0.01
+            duration = 2.0
+            min_amplitude = 10.0
+            max_amplitude = 5.0
+            "#
+        )
+        .is_err());
+        // Pile-up split without being detected
+        assert!(parse_config(
+            r#"
+            mode = "advanced-muon-detector"
+            muon_onset = 1.0
+            muon_fall = -0.1
+            muon_termination = 0.01
+            duration = 2.0
+            split_pile_up = true
+            "#
+        )
+        .is_err());
+        // Gated baseline without a gate
+        assert!(parse_config(
+            r#"
+            mode = "advanced-muon-detector"
+            muon_onset = 1.0
+            muon_fall = -0.1
+            muon_termination = 0.01
+            duration = 2.0
+            baseline_tracking = "gated"
+            "#
+        )
+        .is_err());
+        // Rolling median baseline of no samples
+        assert!(parse_config(
+            r#"
+            mode = "advanced-muon-detector"
+            muon_onset = 1.0
+            muon_fall = -0.1
+            muon_termination = 0.01
+            duration = 2.0
+            baseline_tracking = "rolling-median"
+            baseline_window_size = 0
+            "#
+        )
+        .is_err());
+        // Unknown filter
+        assert!(parse_config(
+            r#"{"mode": "constant-fraction-discriminator", "delay": 2, "fraction": 0.5, "arming_threshold": 10.0, "filters": ["band-pass:0.1"]}"#
+        )
+        .is_err());
+        // Savitzky-Golay filter of even size
+        assert!(parse_config(
+            r#"{"mode": "constant-fraction-discriminator", "delay": 2, "fraction": 0.5, "arming_threshold": 10.0, "filters": ["savitzky-golay:4,2"]}"#
+        )
+        .is_err());
+        // Template given both by file and time constants
+        assert!(parse_config(
+            r#"
+            mode = "template-fit"
+            template_file = "template.csv"
+            rise_time = 2.0
+            decay_time = 12.0
+            threshold = 10.0
+            "#
+        )
+        .is_err());
+        // Missing template file
+        assert!(parse_config(
+            r#"
+            mode = "template-fit"
+            template_file = "/nonexistent/template.csv"
+            threshold = 10.0
+            "#
+        )
+        .is_err());
+        // Rise slower than decay
+        assert!(parse_config(
+            r#"
+            mode = "template-fit"
+            rise_time = 12.0
+            decay_time = 2.0
+            threshold = 10.0
+            "#
+        )
+        .is_err());
+        // Invalid channel parameters
+        assert!(parse_config(
+            r#"
+            mode = "constant-phase-discriminator"
+            threshold_trigger = "-5,1,0"
+
+            [[channels]]
+            digitiser_id = 2
+            channel = 5
+            threshold_trigger = "-5,0,0"
+            "#
+        )
+        .is_err());
+        // Channel given twice
+        assert!(parse_config(
+            r#"
+            mode = "constant-phase-discriminator"
+            threshold_trigger = "-5,1,0"
+
+            [[channels]]
+            digitiser_id = 2
+            channel = 5
+
+            [[channels]]
+            digitiser_id = 2
+            channel = 5
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn file_changes_detected() {
+        let path = std::env::temp_dir().join(format!(
+            "trace-to-events-config-{}.json",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-5,1,0"}"#,
+        )
+        .unwrap();
+
+        let mut watcher = ConfigFileWatcher::new(path.clone());
+        assert!(watcher.poll().is_none());
+
+        // Ensure the modification time changes on file systems with coarse timestamps
+        std::thread::sleep(Duration::from_millis(1100));
+        fs::write(
+            &path,
+            r#"{"mode": "constant-phase-discriminator", "threshold_trigger": "-7,1,0"}"#,
+        )
+        .unwrap();
+
+        let Some(Ok(DetectorConfig {
+            default: Mode::ConstantPhaseDiscriminator(parameters),
+            ..
+        })) = watcher.poll()
+        else {
+            panic!("expected updated constant phase discriminator");
+        };
+        assert_eq!(parameters.threshold_trigger.0.threshold, -7.0);
+        assert!(watcher.poll().is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
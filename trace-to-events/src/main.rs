@@ -1,27 +1,41 @@
+mod capture;
+mod config;
 mod metrics;
+mod offline;
 mod parameters;
 mod processing;
 mod pulse_detection;
+mod scan;
 
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use capture::{Capture, CaptureOptions, CaptureTrigger};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use config::{ConfigFileWatcher, ConfigSource};
 use kagiyama::{AlwaysReady, Watcher};
-use parameters::Mode;
+use offline::OfflineArgs;
+use parameters::{DetectorConfig, Mode};
 use rdkafka::{
     consumer::{stream_consumer::StreamConsumer, CommitMode, Consumer},
-    message::Message,
-    producer::{FutureProducer, FutureRecord},
+    error::KafkaResult,
+    message::{BorrowedMessage, Message},
+    producer::{future_producer::DeliveryFuture, FutureProducer, FutureRecord},
+    Offset, TopicPartitionList,
 };
-use std::{net::SocketAddr, path::PathBuf, time::Duration};
+use scan::ScanArgs;
+use std::{collections::VecDeque, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use supermusr_common::{kafka_key::digitiser_key, DigitizerId};
 use supermusr_streaming_types::dat1_digitizer_analog_trace_v1_generated::{
     digitizer_analog_trace_message_buffer_has_identifier, root_as_digitizer_analog_trace_message,
 };
-use tracing::{debug, error, trace, warn};
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, trace, warn};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 struct Cli {
+    /// Required unless running offline.
     #[clap(long)]
-    broker: String,
+    broker: Option<String>,
 
     #[clap(long)]
     username: Option<String>,
@@ -29,37 +43,163 @@ struct Cli {
     #[clap(long)]
     password: Option<String>,
 
+    /// Required unless running offline.
     #[clap(long = "group")]
-    consumer_group: String,
+    consumer_group: Option<String>,
 
+    /// Required unless running offline.
     #[clap(long)]
-    trace_topic: String,
+    trace_topic: Option<String>,
 
+    /// Required unless running offline.
     #[clap(long)]
-    event_topic: String,
+    event_topic: Option<String>,
 
     #[clap(long, default_value = "127.0.0.1:9090")]
     observability_address: SocketAddr,
 
+    /// Include the peak time, rise time, width and area of the pulse of each event in event messages.
+    #[clap(long)]
+    pulse_features: bool,
+
+    /// Maximum number of trace messages being processed at any one time.
+    /// Event messages are always published in the order the traces were received.
+    #[clap(long, default_value = "16")]
+    max_messages_in_flight: usize,
+
+    /// File giving the detector and its parameters as TOML or JSON, used instead of a subcommand.
+    /// Changes to the file are applied while running.
+    #[clap(long)]
+    config_file: Option<PathBuf>,
+
+    /// Interval in milliseconds at which the config file is checked for changes.
+    #[clap(long, default_value = "1000")]
+    config_poll_interval_ms: u64,
+
+    /// Topic from which updates to the detector and its parameters, as TOML or JSON, are consumed.
+    /// Requests for debug captures are also consumed from this topic.
     #[clap(long)]
-    save_file: Option<PathBuf>,
+    control_topic: Option<String>,
+
+    #[clap(flatten)]
+    capture: CaptureOptions,
 
     #[command(subcommand)]
-    pub(crate) mode: Mode,
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(flatten)]
+    Detector(Mode),
+    /// Finds the events in trace files written by trace-archiver or stream-to-file,
+    /// writing them to an event file rather than consuming from and publishing to Kafka.
+    Offline(OfflineArgs),
+    /// Evaluates a detector against the true events in trace files, over a grid of parameters.
+    Scan(ScanArgs),
+}
+
+/// Arguments required when consuming from and publishing to Kafka.
+struct KafkaArgs {
+    broker: String,
+    consumer_group: String,
+    trace_topic: String,
+    event_topic: String,
+}
+
+impl KafkaArgs {
+    fn new(args: &Cli) -> Self {
+        let required = |value: &Option<String>, name: &str| match value {
+            Some(value) => value.clone(),
+            None => Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!("--{name} is required unless running offline"),
+                )
+                .exit(),
+        };
+        Self {
+            broker: required(&args.broker, "broker"),
+            consumer_group: required(&args.consumer_group, "group"),
+            trace_topic: required(&args.trace_topic, "trace-topic"),
+            event_topic: required(&args.event_topic, "event-topic"),
+        }
+    }
+}
+
+/// A trace message that is being processed on the rayon thread pool.
+struct InFlight<'a> {
+    message: BorrowedMessage<'a>,
+    /// Digitiser of the trace and the event message produced from it, none if the message
+    /// is not a valid trace message, so has nothing to publish
+    processing: Option<(DigitizerId, oneshot::Receiver<Vec<u8>>)>,
+}
+
+/// A trace message whose event message is being delivered, to be committed once it has been.
+struct Publishing<'a> {
+    message: BorrowedMessage<'a>,
+    /// Delivery of the event message, none if there is nothing to publish
+    delivery: Option<DeliveryFuture>,
 }
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let args = Cli::parse();
+    let mut args = Cli::parse();
+
+    let (mode, offline) = match args.command.take() {
+        Some(Command::Detector(mode)) => (Some(mode), None),
+        Some(Command::Offline(mut offline)) => (offline.mode.take(), Some(offline)),
+        Some(Command::Scan(scan)) => {
+            if let Err(e) = scan::run(&scan) {
+                error!("{e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => (None, None),
+    };
+    let (config, config_source) = match (mode, &args.config_file) {
+        (Some(mode), None) => (DetectorConfig::from(mode), ConfigSource::CommandLine),
+        (None, Some(path)) => match config::load_config_file(path) {
+            Ok(config) => (config, ConfigSource::File),
+            Err(e) => Cli::command().error(ErrorKind::Io, e).exit(),
+        },
+        _ => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "either a detector subcommand or --config-file should be given",
+            )
+            .exit(),
+    };
+    if let Err(e) = config.validate() {
+        Cli::command().error(ErrorKind::ValueValidation, e).exit();
+    }
+    if let Err(e) = capture::check_dir(&args.capture) {
+        Cli::command().error(ErrorKind::ValueValidation, e).exit();
+    }
+
+    if let Some(offline) = offline {
+        if let Err(e) = offline::run(&offline, &config, args.pulse_features) {
+            error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let kafka = KafkaArgs::new(&args);
 
     let mut watcher = Watcher::<AlwaysReady>::default();
     metrics::register(&watcher);
     watcher.start_server(args.observability_address).await;
+    info!(
+        "Detector configuration {} from {config_source:?}: {config:?}",
+        config.hash()
+    );
+    metrics::set_detector_config(config_source, &config);
 
     let mut client_config = supermusr_common::generate_kafka_client_config(
-        &args.broker,
+        &kafka.broker,
         &args.username,
         &args.password,
     );
@@ -69,7 +209,7 @@ async fn main() {
         .expect("Kafka Producer should be created");
 
     let consumer: StreamConsumer = client_config
-        .set("group.id", &args.consumer_group)
+        .set("group.id", &kafka.consumer_group)
         .set("enable.partition.eof", "false")
         .set("session.timeout.ms", "6000")
         .set("enable.auto.commit", "false")
@@ -77,79 +217,298 @@ async fn main() {
         .expect("Kafka Consumer should be created");
 
     consumer
-        .subscribe(&[&args.trace_topic])
+        .subscribe(&[&kafka.trace_topic])
         .expect("Kafka Consumer should subscribe to trace-topic");
 
+    // Every instance must see every control message, so rather than sharing
+    // the control topic between the consumer group, each instance is assigned
+    // all of its partitions
+    let control_consumer: Option<StreamConsumer> = args.control_topic.as_ref().map(|topic| {
+        let control_consumer: StreamConsumer = client_config
+            .create()
+            .expect("Kafka Consumer should be created");
+        control_consumer
+            .assign(&all_partitions(&control_consumer, topic))
+            .expect("Kafka Consumer should be assigned control-topic");
+        control_consumer
+    });
+
+    let mut config = Arc::new(config);
+    let mut config_watcher = args.config_file.clone().map(ConfigFileWatcher::new);
+    let mut config_poll =
+        tokio::time::interval(Duration::from_millis(args.config_poll_interval_ms));
+    let max_in_flight = args.max_messages_in_flight.max(1);
+    let mut in_flight = VecDeque::<InFlight>::with_capacity(max_in_flight);
+    let mut publishing = VecDeque::<Publishing>::new();
+    let mut capture_trigger = CaptureTrigger::new(args.capture.clone());
+
     loop {
-        match consumer.recv().await {
-            Ok(m) => {
-                debug!(
-                    "key: '{:?}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
-                    m.key(),
-                    m.topic(),
-                    m.partition(),
-                    m.offset(),
-                    m.timestamp()
-                );
-
-                if let Some(payload) = m.payload() {
-                    if digitizer_analog_trace_message_buffer_has_identifier(payload) {
-                        metrics::MESSAGES_RECEIVED
-                            .get_or_create(&metrics::MessagesReceivedLabels::new(
-                                metrics::MessageKind::Trace,
-                            ))
-                            .inc();
-                        match root_as_digitizer_analog_trace_message(payload) {
-                            Ok(thing) => {
-                                match producer
-                                    .send(
-                                        FutureRecord::to(&args.event_topic)
-                                            .payload(&processing::process(
-                                                &thing,
-                                                &args.mode,
-                                                args.save_file.as_deref(),
-                                            ))
-                                            .key("test"),
-                                        Duration::from_secs(0),
-                                    )
-                                    .await
-                                {
-                                    Ok(_) => {
-                                        trace!("Published event message");
-                                        metrics::MESSAGES_PROCESSED.inc();
-                                    }
-                                    Err(e) => {
-                                        error!("{:?}", e);
-                                        metrics::FAILURES
-                                            .get_or_create(&metrics::FailureLabels::new(
-                                                metrics::FailureKind::KafkaPublishFailed,
-                                            ))
-                                            .inc();
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                warn!("Failed to parse message: {}", e);
-                                metrics::FAILURES
-                                    .get_or_create(&metrics::FailureLabels::new(
-                                        metrics::FailureKind::UnableToDecodeMessage,
-                                    ))
-                                    .inc();
-                            }
+        tokio::select! {
+            processed = next_processed(&mut in_flight) => {
+                let message = in_flight
+                    .pop_front()
+                    .expect("a message should be in flight")
+                    .message;
+                match processed {
+                    Some((digitiser_id, events)) => {
+                        // A trace message whose events cannot be queued is not committed
+                        if let Some(delivery) =
+                            publish(&producer, &kafka.event_topic, digitiser_id, events)
+                        {
+                            publishing.push_back(Publishing {
+                                message,
+                                delivery: Some(delivery),
+                            });
                         }
-                    } else {
-                        warn!("Unexpected message type on topic \"{}\"", m.topic());
-                        metrics::MESSAGES_RECEIVED
-                            .get_or_create(&metrics::MessagesReceivedLabels::new(
-                                metrics::MessageKind::Unknown,
-                            ))
-                            .inc();
+                    }
+                    None => publishing.push_back(Publishing {
+                        message,
+                        delivery: None,
+                    }),
+                }
+            }
+            delivered = next_delivered(&mut publishing) => {
+                let published = publishing
+                    .pop_front()
+                    .expect("a message should be publishing");
+                // Messages are committed in the order received, once their events are delivered
+                if delivered {
+                    consumer
+                        .commit_message(&published.message, CommitMode::Async)
+                        .unwrap();
+                }
+            }
+            message = next_control(&control_consumer) => match message {
+                Ok(m) => {
+                    let text = m
+                        .payload()
+                        .ok_or(anyhow!("empty message"))
+                        .and_then(|payload| Ok(std::str::from_utf8(payload)?));
+                    match text.as_deref().map(capture::parse_request) {
+                        Ok(Ok(messages)) => request_capture(&mut capture_trigger, messages),
+                        _ => apply_config(
+                            &mut config,
+                            text.and_then(config::parse_config),
+                            ConfigSource::ControlTopic,
+                        ),
                     }
                 }
+                Err(e) => warn!("Kafka error: {}", e),
+            },
+            message = consumer.recv(), if in_flight.len() < max_in_flight => match message {
+                Ok(m) => {
+                    debug!(
+                        "key: '{:?}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
+                        m.key(),
+                        m.topic(),
+                        m.partition(),
+                        m.offset(),
+                        m.timestamp()
+                    );
 
-                consumer.commit_message(&m, CommitMode::Async).unwrap();
+                    // Messages with nothing to publish are still queued, so that they are
+                    // committed in the order received
+                    let processing = dispatch(&m, &config, &mut capture_trigger, args.pulse_features);
+                    in_flight.push_back(InFlight {
+                        message: m,
+                        processing,
+                    });
+                }
+                Err(e) => warn!("Kafka error: {}", e),
+            },
+            _ = config_poll.tick(), if config_watcher.is_some() => {
+                if let Some(watcher) = &mut config_watcher {
+                    if let Some(update) = watcher.poll() {
+                        info!("Detector config file {} changed", watcher.path().display());
+                        apply_config(&mut config, update, ConfigSource::File);
+                    }
+                }
             }
-            Err(e) => warn!("Kafka error: {}", e),
-        };
+        }
+    }
+}
+
+/// Replaces the detector configuration used for subsequent trace messages if the update is valid.
+/// Messages already in flight are processed with the configuration they were dispatched with.
+fn apply_config(
+    config: &mut Arc<DetectorConfig>,
+    update: Result<DetectorConfig>,
+    source: ConfigSource,
+) {
+    match update {
+        Ok(new_config) => {
+            info!(
+                "Applying detector configuration {} from {source:?}: {new_config:?}",
+                new_config.hash()
+            );
+            metrics::set_detector_config(source, &new_config);
+            *config = Arc::new(new_config);
+        }
+        Err(e) => {
+            warn!("Rejected detector configuration from {source:?}: {e}");
+            metrics::DETECTOR_CONFIG_REJECTED.inc();
+        }
+    }
+}
+
+fn request_capture(trigger: &mut Option<CaptureTrigger>, messages: u64) {
+    match trigger {
+        Some(trigger) => {
+            info!("Capturing the next {messages} trace messages");
+            trigger.request(messages);
+        }
+        None => warn!("Capture requested, but no capture directory is given"),
+    }
+}
+
+/// Returns every partition of the given topic, to be consumed from its end.
+fn all_partitions(consumer: &StreamConsumer, topic: &str) -> TopicPartitionList {
+    let metadata = consumer
+        .fetch_metadata(Some(topic), Duration::from_secs(10))
+        .expect("Kafka metadata should be fetched for control-topic");
+    let mut partitions = TopicPartitionList::new();
+    for partition in metadata
+        .topics()
+        .iter()
+        .flat_map(|topic| topic.partitions())
+    {
+        partitions
+            .add_partition_offset(topic, partition.id(), Offset::End)
+            .expect("partition offset should be valid");
+    }
+    partitions
+}
+
+/// Waits for the next control message, never completing if there is no control topic.
+async fn next_control(consumer: &Option<StreamConsumer>) -> KafkaResult<BorrowedMessage<'_>> {
+    match consumer {
+        Some(consumer) => consumer.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the oldest message in flight to be processed, never completing if there is none.
+async fn next_processed(in_flight: &mut VecDeque<InFlight<'_>>) -> Option<(DigitizerId, Vec<u8>)> {
+    match in_flight.front_mut() {
+        Some(front) => match &mut front.processing {
+            Some((digitiser_id, events)) => Some((
+                *digitiser_id,
+                events
+                    .await
+                    .expect("processing of a trace message should not panic"),
+            )),
+            None => None,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for the events of the oldest message being published to be delivered, returning
+/// whether they were, and never completing if there is no such message.
+async fn next_delivered(publishing: &mut VecDeque<Publishing<'_>>) -> bool {
+    let Some(front) = publishing.front_mut() else {
+        return std::future::pending().await;
+    };
+    let Some(delivery) = &mut front.delivery else {
+        return true;
+    };
+    match delivery.await {
+        Ok(Ok(_)) => {
+            trace!("Published event message");
+            metrics::MESSAGES_PROCESSED.inc();
+            true
+        }
+        Ok(Err((e, _))) => {
+            error!("{:?}", e);
+            metrics::FAILURES
+                .get_or_create(&metrics::FailureLabels::new(
+                    metrics::FailureKind::KafkaPublishFailed,
+                ))
+                .inc();
+            false
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            false
+        }
+    }
+}
+
+/// Validates a trace message and spawns its processing on the rayon thread pool.
+fn dispatch(
+    m: &BorrowedMessage,
+    config: &Arc<DetectorConfig>,
+    capture_trigger: &mut Option<CaptureTrigger>,
+    pulse_features: bool,
+) -> Option<(DigitizerId, oneshot::Receiver<Vec<u8>>)> {
+    let payload = m.payload()?;
+
+    if !digitizer_analog_trace_message_buffer_has_identifier(payload) {
+        warn!("Unexpected message type on topic \"{}\"", m.topic());
+        metrics::MESSAGES_RECEIVED
+            .get_or_create(&metrics::MessagesReceivedLabels::new(
+                metrics::MessageKind::Unknown,
+            ))
+            .inc();
+        return None;
+    }
+
+    metrics::MESSAGES_RECEIVED
+        .get_or_create(&metrics::MessagesReceivedLabels::new(
+            metrics::MessageKind::Trace,
+        ))
+        .inc();
+
+    let digitiser_id = match root_as_digitizer_analog_trace_message(payload) {
+        Ok(thing) => thing.digitizer_id(),
+        Err(e) => {
+            warn!("Failed to parse message: {}", e);
+            metrics::FAILURES
+                .get_or_create(&metrics::FailureLabels::new(
+                    metrics::FailureKind::UnableToDecodeMessage,
+                ))
+                .inc();
+            return None;
+        }
+    };
+
+    let payload = payload.to_vec();
+    let config = config.clone();
+    let capture: Option<Capture> = capture_trigger
+        .as_mut()
+        .and_then(|trigger| trigger.trigger(digitiser_id));
+    let (sender, receiver) = oneshot::channel();
+
+    rayon::spawn(move || {
+        let thing = root_as_digitizer_analog_trace_message(&payload)
+            .expect("trace message should have been verified");
+        let events = processing::process(&thing, &config, capture.as_ref(), pulse_features);
+        // The receiver is only dropped on shutdown, so the result can be ignored
+        let _ = sender.send(events);
+    });
+
+    Some((digitiser_id, receiver))
+}
+
+/// Queues an event message to be published, returning its delivery if it was queued.
+fn publish(
+    producer: &FutureProducer,
+    topic: &str,
+    digitiser_id: DigitizerId,
+    events: Vec<u8>,
+) -> Option<DeliveryFuture> {
+    let key = digitiser_key(digitiser_id);
+
+    match producer.send_result(FutureRecord::to(topic).payload(&events).key(&key)) {
+        Ok(delivery) => Some(delivery),
+        Err((e, _)) => {
+            error!("{:?}", e);
+            metrics::FAILURES
+                .get_or_create(&metrics::FailureLabels::new(
+                    metrics::FailureKind::KafkaPublishFailed,
+                ))
+                .inc();
+            None
+        }
     }
 }
@@ -1,5 +1,10 @@
+use crate::{config::ConfigSource, parameters::DetectorConfig};
 use kagiyama::{
-    prometheus::metrics::{counter::Counter, family::Family},
+    prometheus::{
+        self as prometheus_client,
+        encoding::EncodeLabelSet,
+        metrics::{counter::Counter, family::Family, gauge::Gauge},
+    },
     AlwaysReady, Watcher,
 };
 use lazy_static::lazy_static;
@@ -7,6 +12,7 @@ pub(crate) use supermusr_common::metrics::{
     failures::{FailureKind, FailureLabels},
     messages_received::{MessageKind, MessagesReceivedLabels},
 };
+use supermusr_common::{Channel, DigitizerId};
 
 pub(crate) fn register(watcher: &Watcher<AlwaysReady>) {
     let mut registry = watcher.metrics_registry();
@@ -26,6 +32,56 @@ pub(crate) fn register(watcher: &Watcher<AlwaysReady>) {
         "Messages received by type from incomming Kafka topic",
         MESSAGES_RECEIVED.clone(),
     );
+
+    registry.register(
+        "detector_config_info",
+        "Active detector parameters",
+        DETECTOR_CONFIG.clone(),
+    );
+
+    registry.register(
+        "detector_config_rejected",
+        "Detector configuration updates rejected as invalid",
+        DETECTOR_CONFIG_REJECTED.clone(),
+    );
+
+    registry.register(
+        "pile_up_pulses",
+        "Pulses found to contain more than one peak, by channel",
+        PILE_UP_PULSES.clone(),
+    );
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct ChannelLabels {
+    digitiser_id: DigitizerId,
+    channel: Channel,
+}
+
+impl ChannelLabels {
+    pub(crate) fn new(digitiser_id: DigitizerId, channel: Channel) -> Self {
+        Self {
+            digitiser_id,
+            channel,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct DetectorConfigLabels {
+    source: ConfigSource,
+    hash: String,
+}
+
+/// Records the given detector configuration as the only one active.
+pub(crate) fn set_detector_config(source: ConfigSource, config: &DetectorConfig) {
+    DETECTOR_CONFIG.clear();
+    DETECTOR_CONFIG
+        .get_or_create(&DetectorConfigLabels {
+            source,
+            hash: config.hash(),
+        })
+        .set(1);
 }
 
 lazy_static! {
@@ -34,4 +90,9 @@ lazy_static! {
         Family::<FailureLabels, Counter>::default();
     pub(crate) static ref MESSAGES_RECEIVED: Family::<MessagesReceivedLabels, Counter> =
         Family::<MessagesReceivedLabels, Counter>::default();
+    pub(crate) static ref DETECTOR_CONFIG: Family::<DetectorConfigLabels, Gauge> =
+        Family::<DetectorConfigLabels, Gauge>::default();
+    pub(crate) static ref DETECTOR_CONFIG_REJECTED: Counter = Counter::default();
+    pub(crate) static ref PILE_UP_PULSES: Family::<ChannelLabels, Counter> =
+        Family::<ChannelLabels, Counter>::default();
 }
@@ -0,0 +1,554 @@
+use crate::{parameters::DetectorConfig, parameters::Mode, processing};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use hdf5::{Dataset, File, H5Type};
+use ndarray::s;
+use std::path::{Path, PathBuf};
+use supermusr_common::{
+    channel_index, Channel, DigitizerId, FrameNumber, Intensity, SampleRate, Time,
+    CHANNELS_PER_DIGITIZER,
+};
+use supermusr_streaming_types::{
+    dat1_digitizer_analog_trace_v1_generated::{
+        finish_digitizer_analog_trace_message_buffer, root_as_digitizer_analog_trace_message,
+        ChannelTrace, ChannelTraceArgs, DigitizerAnalogTraceMessage,
+        DigitizerAnalogTraceMessageArgs,
+    },
+    dev1_digitizer_event_v1_generated::{
+        root_as_digitizer_event_list_message, DigitizerEventListMessage,
+    },
+    flatbuffers::{FlatBufferBuilder, Follow, Vector},
+    frame_metadata_v1_generated::{FrameMetadataV1, FrameMetadataV1Args, GpsTime},
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Parser)]
+pub(crate) struct OfflineArgs {
+    /// Trace file written by trace-archiver or stream-to-file, which may be given more than once. See README.md.
+    #[clap(long = "input", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// File to which the events found in the traces are written.
+    #[clap(long)]
+    output: PathBuf,
+
+    #[command(subcommand)]
+    pub(crate) mode: Option<Mode>,
+}
+
+/// The traces of a single digitiser in a single frame.
+pub(crate) struct TraceFrame {
+    pub(crate) digitiser_id: DigitizerId,
+    pub(crate) frame_number: FrameNumber,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) sample_rate: SampleRate,
+    pub(crate) channels: Vec<(Channel, Vec<Intensity>)>,
+}
+
+impl TraceFrame {
+    /// Creates the trace message the digitiser would have sent.
+    /// Metadata not recorded in trace files is left at its default.
+    pub(crate) fn to_message(&self) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let timestamp: GpsTime = self.timestamp.into();
+        let metadata = FrameMetadataV1Args {
+            frame_number: self.frame_number,
+            running: true,
+            timestamp: Some(&timestamp),
+            ..Default::default()
+        };
+        let metadata = FrameMetadataV1::create(&mut fbb, &metadata);
+
+        let channels: Vec<_> = self
+            .channels
+            .iter()
+            .map(|(channel, voltage)| {
+                let voltage = Some(fbb.create_vector(voltage));
+                ChannelTrace::create(
+                    &mut fbb,
+                    &ChannelTraceArgs {
+                        channel: *channel,
+                        voltage,
+                    },
+                )
+            })
+            .collect();
+
+        let message = DigitizerAnalogTraceMessageArgs {
+            digitizer_id: self.digitiser_id,
+            metadata: Some(metadata),
+            sample_rate: self.sample_rate,
+            channels: Some(fbb.create_vector(&channels)),
+        };
+        let message = DigitizerAnalogTraceMessage::create(&mut fbb, &message);
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+        fbb.finished_data().to_vec()
+    }
+}
+
+fn timestamp(seconds: u64, nanoseconds: u32) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds as i64, nanoseconds).ok_or(anyhow!(
+        "invalid frame timestamp {seconds}s {nanoseconds}ns"
+    ))
+}
+
+/// Reads a file written by trace-archiver, which contains a single frame of a single digitiser.
+fn read_archived_frame(file: &File) -> Result<TraceFrame> {
+    let scalar = |name: &str| file.dataset(&format!("metadata/{name}"));
+
+    let channel_numbers = scalar("channel_numbers")?.read_raw::<Channel>()?;
+    let channel_data = file.dataset("channel_data")?.read_2d::<Intensity>()?;
+    let channels = channel_numbers
+        .into_iter()
+        .zip(channel_data.outer_iter())
+        .map(|(channel, voltage)| (channel, voltage.to_vec()))
+        .collect();
+
+    Ok(TraceFrame {
+        digitiser_id: scalar("digitizer_id")?.read_scalar()?,
+        frame_number: scalar("frame_number")?.read_scalar()?,
+        timestamp: timestamp(
+            scalar("frame_timestamp/seconds")?.read_scalar()?,
+            scalar("frame_timestamp/nanoseconds")?.read_scalar()?,
+        )?,
+        sample_rate: scalar("sample_rate")?.read_scalar()?,
+        channels,
+    })
+}
+
+/// Reads each frame of each digitiser in a file written by stream-to-file.
+///
+/// The length of the traces of each frame is not recorded, so is taken to be the distance
+/// to the next larger start of a frame. The starts are not sorted, as stream-to-file places
+/// a new frame at the end of the data of the first digitiser to send it, so frames after one
+/// a digitiser missed can start at, or before, the start of earlier frames. Digitisers
+/// missing from a frame have samples of zero, so are skipped.
+fn read_stream_frames(file: &File, f: &mut impl FnMut(TraceFrame) -> Result<()>) -> Result<()> {
+    let sample_rate: SampleRate = file.dataset("sample_rate")?.read_scalar()?;
+    let seconds = file.dataset("frame_timestamp/seconds")?.read_raw::<u64>()?;
+    let nanoseconds = file
+        .dataset("frame_timestamp/nanoseconds")?
+        .read_raw::<u32>()?;
+    let frame_numbers = file.dataset("frame_number")?.read_raw::<FrameNumber>()?;
+    let starts = file.dataset("frame_start_index")?.read_raw::<u32>()?;
+
+    let detector_data = file.dataset("detector_data")?;
+    let [rows, width] = detector_data.shape()[..] else {
+        return Err(anyhow!("detector_data should be two dimensional"));
+    };
+    let digitisers = rows / CHANNELS_PER_DIGITIZER;
+
+    let mut sorted_starts = starts.clone();
+    sorted_starts.sort_unstable();
+    sorted_starts.dedup();
+
+    for (frame, &start) in starts.iter().enumerate() {
+        let next = sorted_starts.partition_point(|&other| other <= start);
+        let start = start as usize;
+        let end = sorted_starts.get(next).map_or(width, |&end| end as usize);
+
+        for digitiser in 0..digitisers {
+            let first = channel_index(digitiser, 0);
+            let data = detector_data.read_slice_2d::<Intensity, _>(s![
+                first..first + CHANNELS_PER_DIGITIZER,
+                start..end
+            ])?;
+            if data.iter().all(|&value| value == 0) {
+                continue;
+            }
+            f(TraceFrame {
+                digitiser_id: digitiser as DigitizerId,
+                frame_number: frame_numbers[frame],
+                timestamp: timestamp(seconds[frame], nanoseconds[frame])?,
+                sample_rate,
+                channels: data
+                    .outer_iter()
+                    .enumerate()
+                    .map(|(channel, voltage)| (channel as Channel, voltage.to_vec()))
+                    .collect(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads each frame of each digitiser in a trace file, of either format.
+pub(crate) fn read_trace_file(
+    path: &Path,
+    f: &mut impl FnMut(TraceFrame) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(path)?;
+    if file.link_exists("channel_data") {
+        f(read_archived_frame(&file)?)
+    } else if file.link_exists("detector_data") {
+        read_stream_frames(&file, f)
+    } else {
+        Err(anyhow!(
+            "not a trace file written by trace-archiver or stream-to-file"
+        ))
+    }
+}
+
+fn new_dataset<T: H5Type>(file: &File, name: &str) -> Result<Dataset> {
+    Ok(file.new_dataset::<T>().shape((0..,)).create(name)?)
+}
+
+fn vector<'a, T: Follow<'a> + 'a>(values: Option<Vector<'a, T>>) -> Vec<T::Inner> {
+    values.into_iter().flatten().collect()
+}
+
+fn append<T: H5Type>(dataset: &Dataset, values: &[T]) -> Result<()> {
+    let len = dataset.shape()[0];
+    dataset.resize((len + values.len(),))?;
+    dataset.write_slice(values, s![len..len + values.len()])?;
+    Ok(())
+}
+
+/// Writes the event list messages of each digitiser and frame to a file,
+/// in the layout of the event files of stream-to-file.
+struct EventFileWriter {
+    file: File,
+    frame_timestamp_seconds: Dataset,
+    frame_timestamp_nanoseconds: Dataset,
+    frame_number: Dataset,
+    frame_start_index: Dataset,
+    event_time: Dataset,
+    event_voltage: Dataset,
+    event_channel: Dataset,
+    /// Peak time, rise time, width and area of the pulse of each event
+    features: Option<[Dataset; 4]>,
+    /// Frame number and timestamp of the last frame written
+    last_frame: Option<(FrameNumber, DateTime<Utc>)>,
+}
+
+impl EventFileWriter {
+    fn create(path: &Path, with_features: bool) -> Result<Self> {
+        let file = File::create(path)?;
+        let features = if with_features {
+            Some([
+                new_dataset::<Time>(&file, "event_data/peak_time")?,
+                new_dataset::<f32>(&file, "event_data/rise_time")?,
+                new_dataset::<f32>(&file, "event_data/width")?,
+                new_dataset::<f32>(&file, "event_data/area")?,
+            ])
+        } else {
+            None
+        };
+        Ok(Self {
+            frame_timestamp_seconds: new_dataset::<u64>(&file, "frame_timestamp/seconds")?,
+            frame_timestamp_nanoseconds: new_dataset::<u32>(&file, "frame_timestamp/nanoseconds")?,
+            frame_number: new_dataset::<FrameNumber>(&file, "frame_number")?,
+            frame_start_index: new_dataset::<u32>(&file, "frame_start_index")?,
+            event_time: new_dataset::<u32>(&file, "event_data/time")?,
+            event_voltage: new_dataset::<u32>(&file, "event_data/voltage")?,
+            event_channel: new_dataset::<u32>(&file, "event_data/channel")?,
+            features,
+            file,
+            last_frame: None,
+        })
+    }
+
+    /// Appends the events of a digitiser frame, which are joined to the events of the
+    /// previous digitiser frame if it has the same frame number and timestamp.
+    fn push(&mut self, frame: &TraceFrame, events: &DigitizerEventListMessage) -> Result<()> {
+        let this_frame = (frame.frame_number, frame.timestamp);
+        if self.last_frame != Some(this_frame) {
+            let start = self.event_time.shape()[0] as u32;
+            append(
+                &self.frame_timestamp_seconds,
+                &[frame.timestamp.timestamp() as u64],
+            )?;
+            append(
+                &self.frame_timestamp_nanoseconds,
+                &[frame.timestamp.timestamp_subsec_nanos()],
+            )?;
+            append(&self.frame_number, &[frame.frame_number])?;
+            append(&self.frame_start_index, &[start])?;
+            self.last_frame = Some(this_frame);
+        }
+
+        let voltage: Vec<u32> = vector(events.voltage())
+            .into_iter()
+            .map(u32::from)
+            .collect();
+        append(&self.event_time, &vector(events.time()))?;
+        append(&self.event_voltage, &voltage)?;
+        append(&self.event_channel, &vector(events.channel()))?;
+
+        if let Some([peak_time, rise_time, width, area]) = &self.features {
+            append(peak_time, &vector(events.peak_time()))?;
+            append(rise_time, &vector(events.rise_time()))?;
+            append(width, &vector(events.width()))?;
+            append(area, &vector(events.area()))?;
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Finds the events in each frame of each trace file, writing them to the output file.
+pub(crate) fn run(args: &OfflineArgs, config: &DetectorConfig, with_features: bool) -> Result<()> {
+    let mut writer = EventFileWriter::create(&args.output, with_features)?;
+    let mut frames = 0;
+
+    for input in &args.inputs {
+        info!("Reading traces from {}", input.display());
+        read_trace_file(input, &mut |frame| {
+            let message = frame.to_message();
+            let trace = root_as_digitizer_analog_trace_message(&message)?;
+            let events = processing::process(&trace, config, None, with_features);
+            writer.push(&frame, &root_as_digitizer_event_list_message(&events)?)?;
+            frames += 1;
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}: {e}", input.display()))?;
+    }
+
+    if frames == 0 {
+        warn!("No frames found in the input files");
+    }
+    info!(
+        "Wrote events of {frames} digitiser frames to {}",
+        args.output.display()
+    );
+    writer.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::{ConstantPhaseDiscriminatorParameters, ThresholdDurationWrapper};
+    use ndarray::{Array1, Array2};
+    use std::{fs, str::FromStr};
+
+    /// A trace of baseline 1000, with a pulse to 900 at the given sample
+    fn trace(length: usize, pulse: usize) -> Vec<Intensity> {
+        (0..length)
+            .map(|i| if i == pulse { 900 } else { 1000 })
+            .collect()
+    }
+
+    fn config() -> DetectorConfig {
+        DetectorConfig::from(Mode::ConstantPhaseDiscriminator(
+            ConstantPhaseDiscriminatorParameters {
+                threshold_trigger: ThresholdDurationWrapper::from_str("-950,1,0").unwrap(),
+                filters: Vec::new(),
+            },
+        ))
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "trace-to-events-offline-{name}-{}.h5",
+            std::process::id()
+        ))
+    }
+
+    /// Writes a file in the layout of trace-archiver
+    fn write_archived_frame(path: &Path) {
+        let file = File::create(path).unwrap();
+        let scalar = |name: &str| format!("metadata/{name}");
+        file.new_dataset::<u64>()
+            .create(scalar("frame_timestamp/seconds").as_str())
+            .unwrap()
+            .write_scalar(&1_700_000_000u64)
+            .unwrap();
+        file.new_dataset::<u32>()
+            .create(scalar("frame_timestamp/nanoseconds").as_str())
+            .unwrap()
+            .write_scalar(&500u32)
+            .unwrap();
+        file.new_dataset::<DigitizerId>()
+            .create(scalar("digitizer_id").as_str())
+            .unwrap()
+            .write_scalar(&3u8)
+            .unwrap();
+        file.new_dataset::<FrameNumber>()
+            .create(scalar("frame_number").as_str())
+            .unwrap()
+            .write_scalar(&42u32)
+            .unwrap();
+        file.new_dataset::<FrameNumber>()
+            .create(scalar("sample_rate").as_str())
+            .unwrap()
+            .write_scalar(&1_000_000_000u32)
+            .unwrap();
+        file.new_dataset_builder()
+            .with_data(&[4 as Channel, 6])
+            .create(scalar("channel_numbers").as_str())
+            .unwrap();
+        let mut data = Array2::<Intensity>::zeros((2, 50));
+        data.row_mut(0).assign(&Array1::from_vec(trace(50, 10)));
+        data.row_mut(1).assign(&Array1::from_vec(trace(50, 20)));
+        file.new_dataset_builder()
+            .with_data(&data)
+            .create("channel_data")
+            .unwrap();
+    }
+
+    /// Writes a file in the layout of stream-to-file, with a frame of the given number and
+    /// start index in each second
+    fn write_stream_file(
+        path: &Path,
+        frame_numbers: &[FrameNumber],
+        starts: &[u32],
+        data: &Array2<Intensity>,
+    ) {
+        let file = File::create(path).unwrap();
+        file.new_dataset::<SampleRate>()
+            .create("sample_rate")
+            .unwrap()
+            .write_scalar(&1_000_000_000u64)
+            .unwrap();
+        let seconds: Vec<_> = (0..frame_numbers.len() as u64)
+            .map(|frame| 1_700_000_000 + frame)
+            .collect();
+        file.new_dataset_builder()
+            .with_data(&seconds)
+            .create("frame_timestamp/seconds")
+            .unwrap();
+        file.new_dataset_builder()
+            .with_data(&vec![0u32; frame_numbers.len()])
+            .create("frame_timestamp/nanoseconds")
+            .unwrap();
+        file.new_dataset_builder()
+            .with_data(frame_numbers)
+            .create("frame_number")
+            .unwrap();
+        file.new_dataset_builder()
+            .with_data(starts)
+            .create("frame_start_index")
+            .unwrap();
+        file.new_dataset_builder()
+            .with_data(data)
+            .create("detector_data")
+            .unwrap();
+    }
+
+    /// Writes a file in the layout of stream-to-file, of two digitisers and two frames,
+    /// the second digitiser missing from the second frame
+    fn write_stream_frames(path: &Path) {
+        let mut data = Array2::<Intensity>::zeros((2 * CHANNELS_PER_DIGITIZER, 60));
+        for row in 0..2 * CHANNELS_PER_DIGITIZER {
+            data.slice_mut(s![row, 0..30])
+                .assign(&Array1::from_vec(trace(30, 5 + row)));
+            if row < CHANNELS_PER_DIGITIZER {
+                data.slice_mut(s![row, 30..60])
+                    .assign(&Array1::from_vec(trace(30, 25)));
+            }
+        }
+        write_stream_file(path, &[7, 8], &[0, 30], &data);
+    }
+
+    #[test]
+    fn archived_and_streamed_traces() {
+        let archived = temp_path("archived");
+        let streamed = temp_path("streamed");
+        let output = temp_path("events");
+        write_archived_frame(&archived);
+        write_stream_frames(&streamed);
+
+        let args = OfflineArgs {
+            inputs: vec![archived.clone(), streamed.clone()],
+            output: output.clone(),
+            mode: None,
+        };
+        run(&args, &config(), false).unwrap();
+
+        let file = File::open(&output).unwrap();
+        let read = |name: &str| file.dataset(name).unwrap().read_raw::<u64>().unwrap();
+        // The digitisers of each frame of the stream-to-file traces are joined
+        assert_eq!(read("frame_number"), vec![42, 7, 8]);
+        assert_eq!(
+            read("frame_timestamp/seconds"),
+            vec![1_700_000_000, 1_700_000_000, 1_700_000_001]
+        );
+        assert_eq!(read("frame_start_index"), vec![0, 2, 18]);
+        assert!(!file.link_exists("digitiser_id"));
+        for name in [
+            "frame_start_index",
+            "event_data/time",
+            "event_data/voltage",
+            "event_data/channel",
+        ] {
+            assert_eq!(
+                file.dataset(name)
+                    .unwrap()
+                    .dtype()
+                    .unwrap()
+                    .to_descriptor()
+                    .unwrap(),
+                u32::type_descriptor()
+            );
+        }
+        assert_eq!(read("event_data/channel")[..2], [4, 6]);
+
+        // The threshold is crossed half way between samples
+        let time = read("event_data/time");
+        assert_eq!(time.len(), 2 + 8 + 8 + 8);
+        assert_eq!(time[..3], [10, 20, 5]);
+        assert_eq!(time[10], 5 + 8);
+        assert_eq!(time[18..], [25; 8]);
+        assert!(!file.link_exists("event_data/peak_time"));
+
+        for path in [archived, streamed, output] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn digitiser_missing_from_frames() {
+        let input = temp_path("missing");
+        let output = temp_path("missing-events");
+
+        // Both digitisers send frame 7, then the second misses frames 8 and 9, so
+        // stream-to-file places frame 10 at its end of data, before the start of frame 9
+        let mut data = Array2::<Intensity>::zeros((2 * CHANNELS_PER_DIGITIZER, 90));
+        for row in 0..2 * CHANNELS_PER_DIGITIZER {
+            let blocks = if row < CHANNELS_PER_DIGITIZER { 3 } else { 2 };
+            for block in 0..blocks {
+                data.slice_mut(s![row, 30 * block..30 * (block + 1)])
+                    .assign(&Array1::from_vec(trace(30, 10)));
+            }
+        }
+        write_stream_file(&input, &[7, 8, 9, 10], &[0, 30, 60, 30], &data);
+
+        let args = OfflineArgs {
+            inputs: vec![input.clone()],
+            output: output.clone(),
+            mode: None,
+        };
+        run(&args, &config(), false).unwrap();
+
+        let file = File::open(&output).unwrap();
+        let read = |name: &str| file.dataset(name).unwrap().read_raw::<u64>().unwrap();
+        // Frames sharing samples read the data of both digitisers there, none are dropped
+        assert_eq!(read("frame_number"), vec![7, 8, 9, 10]);
+        assert_eq!(read("frame_start_index"), vec![0, 16, 32, 40]);
+        assert_eq!(read("event_data/time"), vec![10; 56]);
+
+        for path in [input, output] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn unknown_file_rejected() {
+        let path = temp_path("unknown");
+        File::create(&path).unwrap();
+        let args = OfflineArgs {
+            inputs: vec![path.clone()],
+            output: temp_path("unknown-events"),
+            mode: None,
+        };
+        assert!(run(&args, &config(), false).is_err());
+        fs::remove_file(path).unwrap();
+        let _ = fs::remove_file(&args.output);
+    }
+}
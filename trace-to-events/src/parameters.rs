@@ -1,9 +1,23 @@
-use crate::pulse_detection::{detectors::threshold_detector::ThresholdDuration, Real};
-use anyhow::{anyhow, Error};
-use clap::{Parser, Subcommand};
-use std::str::FromStr;
+use crate::pulse_detection::{
+    detectors::{template_detector::Template, threshold_detector::ThresholdDuration},
+    window::{Filter, LowPass, PoleZeroCorrection, SavitzkyGolay, TrapezoidalFilter},
+    Real,
+};
+use anyhow::{anyhow, Error, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+use supermusr_common::{Channel, DigitizerId};
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
 pub(crate) struct ThresholdDurationWrapper(pub(crate) ThresholdDuration);
 
 impl FromStr for ThresholdDurationWrapper {
@@ -25,14 +39,109 @@ impl FromStr for ThresholdDurationWrapper {
     }
 }
 
-#[derive(Default, Debug, Clone, Parser)]
+impl TryFrom<String> for ThresholdDurationWrapper {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+/// A filter applied to the baselined signal, given as "name:parameters". See README.md.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct FilterWrapper(pub(crate) Filter, String);
+
+impl FromStr for FilterWrapper {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, vals) = s.split_once(':').unwrap_or((s, ""));
+        let vals: Vec<_> = vals.split(',').filter(|val| !val.is_empty()).collect();
+        let filter = match (name, vals.as_slice()) {
+            ("trapezoidal", [rise, flat_top]) => {
+                let rise = usize::from_str(rise)?;
+                if rise < 1 {
+                    return Err(anyhow!("trapezoidal filter rise must be positive"));
+                }
+                Filter::Trapezoidal(TrapezoidalFilter::new(rise, usize::from_str(flat_top)?))
+            }
+            ("pole-zero", [decay_time]) => {
+                let decay_time = Real::from_str(decay_time)?;
+                if !(decay_time.is_finite() && decay_time > 0.0) {
+                    return Err(anyhow!("pole-zero decay time must be positive"));
+                }
+                Filter::PoleZero(PoleZeroCorrection::new(decay_time))
+            }
+            ("savitzky-golay", [size, order]) => {
+                let size = usize::from_str(size)?;
+                let order = usize::from_str(order)?;
+                if size & 1 == 0 || order >= size {
+                    return Err(anyhow!(
+                        "savitzky-golay size must be odd and greater than the order"
+                    ));
+                }
+                Filter::SavitzkyGolay(SavitzkyGolay::new(size, order))
+            }
+            ("low-pass", [cutoff]) => {
+                let cutoff = Real::from_str(cutoff)?;
+                if !(cutoff > 0.0 && cutoff < 0.5) {
+                    return Err(anyhow!("low-pass cutoff must be between 0 and 0.5"));
+                }
+                Filter::LowPass(LowPass::new(cutoff))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unknown filter, expected one of 'trapezoidal:*,*', 'pole-zero:*', 'savitzky-golay:*,*' or 'low-pass:*', got '{s}'"
+                ))
+            }
+        };
+        Ok(FilterWrapper(filter, format!("{name}:{}", vals.join(","))))
+    }
+}
+
+impl TryFrom<String> for FilterWrapper {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl Debug for FilterWrapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+#[derive(Default, Debug, Clone, Parser, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct ConstantPhaseDiscriminatorParameters {
     /// Constant phase threshold for detecting muon events, use format "threshold,duration,cool_down". See README.md.
     #[clap(long)]
     pub(crate) threshold_trigger: ThresholdDurationWrapper,
+
+    /// Filters applied in turn to the signal, each given as "name:parameters", and which may be given more than once. See README.md.
+    #[clap(long = "filter")]
+    #[serde(default)]
+    pub(crate) filters: Vec<FilterWrapper>,
+}
+
+/// How the baseline is tracked after the initial portion of the trace. See README.md.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BaselineTracking {
+    /// The baseline found from the initial portion of the trace is used for the whole trace
+    #[default]
+    Fixed,
+    /// Exponential moving average of the samples close to the baseline
+    Gated,
+    /// Median of the most recent samples
+    RollingMedian,
 }
 
-#[derive(Default, Debug, Clone, Parser)]
+#[derive(Default, Debug, Clone, Parser, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct AdvancedMuonDetectorParameters {
     /// Differential threshold for detecting muon onset. See README.md.
     #[clap(long)]
@@ -54,10 +163,32 @@ pub(crate) struct AdvancedMuonDetectorParameters {
     #[clap(long)]
     pub(crate) baseline_length: Option<usize>,
 
+    /// Filters applied in turn to the baselined signal, each given as "name:parameters", and which may be given more than once. See README.md.
+    #[clap(long = "filter")]
+    #[serde(default)]
+    pub(crate) filters: Vec<FilterWrapper>,
+
     /// Size of the moving average window to use for the lopass filter.
     #[clap(long)]
     pub(crate) smoothing_window_size: Option<usize>,
 
+    /// Method by which the baseline follows drifts of the signal after the initial portion of the trace. See README.md.
+    #[clap(long, value_enum, default_value_t = BaselineTracking::Fixed)]
+    #[serde(default)]
+    pub(crate) baseline_tracking: BaselineTracking,
+
+    /// Distance from the baseline beyond which samples are treated as part of a pulse, required for gated baseline tracking.
+    #[clap(long)]
+    pub(crate) baseline_gate: Option<Real>,
+
+    /// Weight given to each new sample by gated baseline tracking, between 0 and 1. Defaults to 0.01.
+    #[clap(long)]
+    pub(crate) baseline_smoothing_factor: Option<Real>,
+
+    /// Number of samples of which the rolling median baseline is taken, required for rolling median baseline tracking.
+    #[clap(long)]
+    pub(crate) baseline_window_size: Option<usize>,
+
     /// Optional parameter which (if set) filters out events whose peak is greater than the given value.
     #[clap(long)]
     pub(crate) max_amplitude: Option<Real>,
@@ -65,12 +196,369 @@ pub(crate) struct AdvancedMuonDetectorParameters {
     /// Optional parameter which (if set) filters out events whose peak is less than the given value.
     #[clap(long)]
     pub(crate) min_amplitude: Option<Real>,
+
+    /// Optional parameter which (if set) detects pulses with more than one peak, where the signal falls by at least the given value between peaks. See README.md.
+    #[clap(long)]
+    pub(crate) pile_up_prominence: Option<Real>,
+
+    /// If set, pulses with more than one peak are split into an event for each peak, rather than only being counted.
+    #[clap(long)]
+    #[serde(default)]
+    pub(crate) split_pile_up: bool,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Default, Debug, Clone, Parser, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConstantFractionDiscriminatorParameters {
+    /// Number of samples by which the signal is delayed before the fraction of the signal is subtracted. See README.md.
+    #[clap(long)]
+    pub(crate) delay: usize,
+
+    /// Fraction of the height of each pulse at which it is timed, between 0 and 1. See README.md.
+    #[clap(long)]
+    pub(crate) fraction: Real,
+
+    /// Level above the baseline which the signal must pass for a pulse to be detected. See README.md.
+    #[clap(long)]
+    pub(crate) arming_threshold: Real,
+
+    /// Size of initial portion of the trace to use for determining the baseline. Initial portion should be event free.
+    #[clap(long)]
+    pub(crate) baseline_length: Option<usize>,
+
+    /// Filters applied in turn to the baselined signal, each given as "name:parameters", and which may be given more than once. See README.md.
+    #[clap(long = "filter")]
+    #[serde(default)]
+    pub(crate) filters: Vec<FilterWrapper>,
+
+    /// Optional parameter which (if set) filters out events whose peak is greater than the given value.
+    #[clap(long)]
+    pub(crate) max_amplitude: Option<Real>,
+
+    /// Optional parameter which (if set) filters out events whose peak is less than the given value.
+    #[clap(long)]
+    pub(crate) min_amplitude: Option<Real>,
+}
+
+/// The template used by the template fit detector, which is created when first used so that
+/// template files are only read once for each configuration.
+#[derive(Default, Clone)]
+pub(crate) struct TemplateCell(Arc<OnceLock<Result<Template, String>>>);
+
+impl Debug for TemplateCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("..")
+    }
+}
+
+#[derive(Default, Debug, Clone, Parser, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TemplateFitParameters {
+    /// File containing the template, as the values of its samples separated by commas or whitespace. See README.md.
+    #[clap(long)]
+    pub(crate) template_file: Option<PathBuf>,
+
+    /// Time constant in samples of the rise of a double exponential template, used if no template file is given.
+    #[clap(long)]
+    pub(crate) rise_time: Option<Real>,
+
+    /// Time constant in samples of the decay of a double exponential template, used if no template file is given.
+    #[clap(long)]
+    pub(crate) decay_time: Option<Real>,
+
+    /// Fitted amplitude of the template which the signal must reach for a pulse to be detected. See README.md.
+    #[clap(long)]
+    pub(crate) threshold: Real,
+
+    /// Optional parameter which (if set) discards pulses whose correlation with the template is less than the given value.
+    #[clap(long)]
+    pub(crate) min_correlation: Option<Real>,
+
+    /// Size of initial portion of the trace to use for determining the baseline. Initial portion should be event free.
+    #[clap(long)]
+    pub(crate) baseline_length: Option<usize>,
+
+    /// Filters applied in turn to the baselined signal, each given as "name:parameters", and which may be given more than once. See README.md.
+    #[clap(long = "filter")]
+    #[serde(default)]
+    pub(crate) filters: Vec<FilterWrapper>,
+
+    /// Size of the moving average window to use for the lopass filter.
+    #[clap(long)]
+    pub(crate) smoothing_window_size: Option<usize>,
+
+    /// Optional parameter which (if set) filters out events whose fitted amplitude is greater than the given value.
+    #[clap(long)]
+    pub(crate) max_amplitude: Option<Real>,
+
+    /// Optional parameter which (if set) filters out events whose fitted amplitude is less than the given value.
+    #[clap(long)]
+    pub(crate) min_amplitude: Option<Real>,
+
+    #[clap(skip)]
+    #[serde(skip)]
+    template: TemplateCell,
+}
+
+/// The detector used, given either on the command line or in a config file, in which
+/// the detector is selected by the `mode` field, e.g. `mode = "advanced-muon-detector"`.
+#[derive(Subcommand, Debug, Clone, Deserialize)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
 pub(crate) enum Mode {
     /// Detects events using a constant phase discriminator. Events consist only of a time value.
     ConstantPhaseDiscriminator(ConstantPhaseDiscriminatorParameters),
     /// Detects events using differential discriminators. Event lists consist of time and voltage values.
     AdvancedMuonDetector(AdvancedMuonDetectorParameters),
+    /// Detects events using a constant fraction discriminator. Event lists consist of time and voltage values.
+    ConstantFractionDiscriminator(ConstantFractionDiscriminatorParameters),
+    /// Detects events by fitting a pulse template. Event lists consist of time and fitted amplitude values.
+    TemplateFit(TemplateFitParameters),
+}
+
+impl ConstantPhaseDiscriminatorParameters {
+    fn validate(&self) -> Result<()> {
+        let ThresholdDuration {
+            threshold,
+            duration,
+            cool_off,
+        } = self.threshold_trigger.0;
+
+        if !threshold.is_finite() {
+            return Err(anyhow!("threshold_trigger threshold must be finite"));
+        }
+        if duration < 1 {
+            return Err(anyhow!("threshold_trigger duration must be positive"));
+        }
+        if cool_off < 0 {
+            return Err(anyhow!("threshold_trigger cool_off must be non-negative"));
+        }
+        // No baseline is removed, so any offset would accumulate in the pole-zero
+        // correction, and would be removed by the trapezoidal filter, changing the
+        // level of the threshold
+        if self
+            .filters
+            .iter()
+            .any(|filter| matches!(filter.0, Filter::PoleZero(_) | Filter::Trapezoidal(_)))
+        {
+            return Err(anyhow!(
+                "pole-zero and trapezoidal filters need the baseline to be removed, which the constant phase discriminator does not do"
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AdvancedMuonDetectorParameters {
+    fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("muon_onset", Some(self.muon_onset)),
+            ("muon_fall", Some(self.muon_fall)),
+            ("muon_termination", Some(self.muon_termination)),
+            ("duration", Some(self.duration)),
+            ("max_amplitude", self.max_amplitude),
+            ("min_amplitude", self.min_amplitude),
+            ("pile_up_prominence", self.pile_up_prominence),
+            ("baseline_gate", self.baseline_gate),
+            ("baseline_smoothing_factor", self.baseline_smoothing_factor),
+        ] {
+            if value.is_some_and(|value| !value.is_finite()) {
+                return Err(anyhow!("{name} must be finite"));
+            }
+        }
+        if self
+            .pile_up_prominence
+            .is_some_and(|prominence| prominence <= 0.0)
+        {
+            return Err(anyhow!("pile_up_prominence must be positive"));
+        }
+        if self.split_pile_up && self.pile_up_prominence.is_none() {
+            return Err(anyhow!("split_pile_up requires pile_up_prominence"));
+        }
+        match self.baseline_tracking {
+            BaselineTracking::Fixed => {}
+            BaselineTracking::Gated => {
+                if !matches!(self.baseline_gate, Some(gate) if gate > 0.0) {
+                    return Err(anyhow!(
+                        "gated baseline_tracking requires a positive baseline_gate"
+                    ));
+                }
+                if self
+                    .baseline_smoothing_factor
+                    .is_some_and(|factor| factor <= 0.0 || factor > 1.0)
+                {
+                    return Err(anyhow!("baseline_smoothing_factor must be in (0, 1]"));
+                }
+            }
+            BaselineTracking::RollingMedian => {
+                if !matches!(self.baseline_window_size, Some(size) if size > 0) {
+                    return Err(anyhow!(
+                        "rolling-median baseline_tracking requires a positive baseline_window_size"
+                    ));
+                }
+            }
+        }
+        if self.duration < 0.0 {
+            return Err(anyhow!("duration must be non-negative"));
+        }
+        if self.smoothing_window_size == Some(0) {
+            return Err(anyhow!("smoothing_window_size must be positive"));
+        }
+        if let (Some(min), Some(max)) = (self.min_amplitude, self.max_amplitude) {
+            if min > max {
+                return Err(anyhow!(
+                    "min_amplitude ({min}) must not be greater than max_amplitude ({max})"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConstantFractionDiscriminatorParameters {
+    fn validate(&self) -> Result<()> {
+        if self.delay < 1 {
+            return Err(anyhow!("delay must be positive"));
+        }
+        if !(self.fraction > 0.0 && self.fraction < 1.0) {
+            return Err(anyhow!("fraction must be between 0 and 1"));
+        }
+        for (name, value) in [
+            ("arming_threshold", Some(self.arming_threshold)),
+            ("max_amplitude", self.max_amplitude),
+            ("min_amplitude", self.min_amplitude),
+        ] {
+            if value.is_some_and(|value| !value.is_finite()) {
+                return Err(anyhow!("{name} must be finite"));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_amplitude, self.max_amplitude) {
+            if min > max {
+                return Err(anyhow!(
+                    "min_amplitude ({min}) must not be greater than max_amplitude ({max})"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TemplateFitParameters {
+    /// Returns the template, loading it from the template file on first use.
+    pub(crate) fn template(&self) -> Result<&Template> {
+        self.template
+            .0
+            .get_or_init(|| self.load_template().map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(|e| anyhow!("{e}"))
+    }
+
+    fn load_template(&self) -> Result<Template> {
+        match (&self.template_file, self.rise_time, self.decay_time) {
+            (Some(path), None, None) => Template::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| anyhow!("{}: {e}", path.display())),
+            (None, Some(rise_time), Some(decay_time)) => {
+                if !(rise_time > 0.0 && rise_time < decay_time && decay_time.is_finite()) {
+                    return Err(anyhow!(
+                        "rise_time must be positive and less than decay_time"
+                    ));
+                }
+                Template::double_exponential(rise_time, decay_time)
+            }
+            _ => Err(anyhow!(
+                "either template_file, or both rise_time and decay_time, should be given"
+            )),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("threshold", Some(self.threshold)),
+            ("max_amplitude", self.max_amplitude),
+            ("min_amplitude", self.min_amplitude),
+        ] {
+            if value.is_some_and(|value| !value.is_finite()) {
+                return Err(anyhow!("{name} must be finite"));
+            }
+        }
+        if self.threshold <= 0.0 {
+            return Err(anyhow!("threshold must be positive"));
+        }
+        if self
+            .min_correlation
+            .is_some_and(|correlation| !(correlation > 0.0 && correlation <= 1.0))
+        {
+            return Err(anyhow!("min_correlation must be between 0 and 1"));
+        }
+        if self.smoothing_window_size == Some(0) {
+            return Err(anyhow!("smoothing_window_size must be positive"));
+        }
+        if let (Some(min), Some(max)) = (self.min_amplitude, self.max_amplitude) {
+            if min > max {
+                return Err(anyhow!(
+                    "min_amplitude ({min}) must not be greater than max_amplitude ({max})"
+                ));
+            }
+        }
+        self.template()?;
+        Ok(())
+    }
+}
+
+impl Mode {
+    /// Checks that the parameters can be used by the detector.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match self {
+            Mode::ConstantPhaseDiscriminator(parameters) => parameters.validate(),
+            Mode::AdvancedMuonDetector(parameters) => parameters.validate(),
+            Mode::ConstantFractionDiscriminator(parameters) => parameters.validate(),
+            Mode::TemplateFit(parameters) => parameters.validate(),
+        }
+    }
+}
+
+/// The detector parameters of each channel of each digitiser,
+/// channels without their own parameters use the default parameters.
+#[derive(Debug, Clone)]
+pub(crate) struct DetectorConfig {
+    pub(crate) default: Mode,
+    pub(crate) channels: BTreeMap<(DigitizerId, Channel), Mode>,
+}
+
+impl DetectorConfig {
+    /// A short identifier of the parameters, which is the same for identical
+    /// parameters across runs and instances (FNV-1a of the debug representation).
+    pub(crate) fn hash(&self) -> String {
+        let hash = format!("{self:?}")
+            .bytes()
+            .fold(0xcbf29ce484222325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        format!("{hash:016x}")
+    }
+}
+
+impl From<Mode> for DetectorConfig {
+    fn from(default: Mode) -> Self {
+        Self {
+            default,
+            channels: Default::default(),
+        }
+    }
+}
+
+impl DetectorConfig {
+    pub(crate) fn mode(&self, digitiser_id: DigitizerId, channel: Channel) -> &Mode {
+        self.channels
+            .get(&(digitiser_id, channel))
+            .unwrap_or(&self.default)
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        self.default.validate()?;
+        for ((digitiser_id, channel), mode) in &self.channels {
+            mode.validate()
+                .map_err(|e| anyhow!("digitiser {digitiser_id} channel {channel}: {e}"))?;
+        }
+        Ok(())
+    }
 }
@@ -1,14 +1,24 @@
 use crate::{
-    parameters::{AdvancedMuonDetectorParameters, ConstantPhaseDiscriminatorParameters, Mode},
+    capture::{Capture, ChannelCapture},
+    metrics,
+    parameters::{
+        AdvancedMuonDetectorParameters, BaselineTracking, ConstantFractionDiscriminatorParameters,
+        ConstantPhaseDiscriminatorParameters, DetectorConfig, FilterWrapper, Mode,
+        TemplateFitParameters,
+    },
     pulse_detection::{
         advanced_muon_detector::{AdvancedMuonDetector, BasicMuonAssembler},
+        constant_fraction_detector::{ConstantFractionAssembler, ConstantFractionDetector},
+        features::PulseFeatures,
+        pile_up::PileUpSplitter,
+        template_detector::{TemplateAssembler, TemplateDetector},
         threshold_detector::{ThresholdAssembler, ThresholdDetector, UpperThreshold},
-        window::{Baseline, FiniteDifferences, SmoothingWindow, WindowFilter},
-        AssembleFilter, EventFilter, Real, SaveToFileFilter,
+        window::{Baseline, FilterChain, FiniteDifferences, SmoothingWindow, WindowFilter},
+        AssembleFilter, EventFilter, Pulse, Real,
     },
 };
-use std::path::{Path, PathBuf};
-use supermusr_common::{Channel, EventData, Intensity, Time};
+use rayon::prelude::*;
+use supermusr_common::{Channel, DigitizerId, EventData, Intensity, Time};
 use supermusr_streaming_types::{
     dat1_digitizer_analog_trace_v1_generated::{ChannelTrace, DigitizerAnalogTraceMessage},
     dev1_digitizer_event_v1_generated::{
@@ -20,48 +30,109 @@ use supermusr_streaming_types::{
 };
 use tracing::info;
 
+/// Pulse features of each event in nanoseconds, features which are not found being NaN.
+#[derive(Default)]
+struct PulseFeatureData {
+    peak_time: Vec<Time>,
+    rise_time: Vec<f32>,
+    width: Vec<f32>,
+    area: Vec<f32>,
+}
+
+impl PulseFeatureData {
+    fn push(&mut self, time: Time, features: &PulseFeatures, sample_time: Real) {
+        let in_ns =
+            |value: Option<Real>| value.map_or(f32::NAN, |value| (value * sample_time) as f32);
+        self.peak_time.push(
+            features
+                .peak_time
+                .map_or(time, |peak_time| (peak_time * sample_time).round() as Time),
+        );
+        self.rise_time.push(in_ns(features.rise_time));
+        self.width.push(in_ns(features.width));
+        self.area.push(in_ns(features.area));
+    }
+
+    fn append(&mut self, other: &mut Self) {
+        self.peak_time.append(&mut other.peak_time);
+        self.rise_time.append(&mut other.rise_time);
+        self.width.append(&mut other.width);
+        self.area.append(&mut other.area);
+    }
+}
+
 struct ChannnelEvents {
     channel_number: Channel,
 
     time: Vec<Time>,
     voltage: Vec<Intensity>,
+    features: PulseFeatureData,
+    capture: Option<ChannelCapture>,
 }
 
+/// An event found in a channel, with its time in samples,
+/// and the features of its pulse if they are needed.
+type ChannelEvent = (Real, Intensity, Option<PulseFeatures>);
+
 fn find_channel_events(
+    digitiser_id: DigitizerId,
     trace: &ChannelTrace,
     sample_time: Real,
     mode: &Mode,
-    save_options: Option<&Path>,
+    capture: Option<&Capture>,
+    with_features: bool,
 ) -> ChannnelEvents {
+    let mut capture = capture
+        .filter(|capture| capture.includes(trace.channel()))
+        .map(|_| ChannelCapture::default());
     let events = match &mode {
         Mode::ConstantPhaseDiscriminator(parameters) => {
-            find_constant_events(trace, parameters, save_options)
+            find_constant_events(trace, parameters, capture.as_mut(), with_features)
+        }
+        Mode::AdvancedMuonDetector(parameters) => find_advanced_events(
+            digitiser_id,
+            trace,
+            parameters,
+            capture.as_mut(),
+            with_features,
+        ),
+        Mode::ConstantFractionDiscriminator(parameters) => {
+            find_constant_fraction_events(trace, parameters, capture.as_mut(), with_features)
         }
-        Mode::AdvancedMuonDetector(parameters) => {
-            find_advanced_events(trace, parameters, save_options)
+        Mode::TemplateFit(parameters) => {
+            find_template_events(trace, parameters, capture.as_mut(), with_features)
         }
     };
 
     let mut time = Vec::new();
     let mut voltage = Vec::new();
+    let mut features = PulseFeatureData::default();
 
-    for event in events {
-        time.push(((event.0 as Real) * sample_time) as Time);
-        voltage.push(event.1);
+    for (event_time, event_voltage, event_features) in events {
+        // Event times are interpolated between samples, so are rounded to the nearest nanosecond
+        let event_time = (event_time * sample_time).round() as Time;
+        if let Some(event_features) = event_features {
+            features.push(event_time, &event_features, sample_time);
+        }
+        time.push(event_time);
+        voltage.push(event_voltage);
     }
 
     ChannnelEvents {
         channel_number: trace.channel(),
         time,
         voltage,
+        features,
+        capture,
     }
 }
 
 fn find_constant_events(
     trace: &ChannelTrace,
     parameters: &ConstantPhaseDiscriminatorParameters,
-    save_path: Option<&Path>,
-) -> Vec<(usize, Intensity)> {
+    capture: Option<&mut ChannelCapture>,
+    with_features: bool,
+) -> Vec<ChannelEvent> {
     let raw = trace
         .voltage()
         .unwrap()
@@ -69,39 +140,75 @@ fn find_constant_events(
         .enumerate()
         .map(|(i, v)| (i as Real, -(v as Real)));
 
-    let pulses = raw
+    let filtered = raw.clone().window(filter_chain(&parameters.filters));
+
+    let pulses = filtered
         .clone()
-        .events(ThresholdDetector::<UpperThreshold>::new(
-            &parameters.threshold_trigger.0,
-        ))
+        .events(
+            ThresholdDetector::<UpperThreshold>::new(&parameters.threshold_trigger.0)
+                .with_interpolation(),
+        )
         .assemble(ThresholdAssembler::<UpperThreshold>::default());
 
-    if let Some(save_path) = save_path {
-        raw.clone()
-            .save_to_file(&get_save_file_name(save_path, trace.channel(), "raw"))
-            .unwrap();
-
-        pulses
-            .clone()
-            .save_to_file(&get_save_file_name(save_path, trace.channel(), "pulses"))
-            .unwrap();
+    if let Some(capture) = capture {
+        capture.signal("raw", raw.clone());
+        if !parameters.filters.is_empty() {
+            capture.signal("filtered", filtered);
+        }
+        capture.pulses(pulses.clone());
     }
 
+    // Pulses only have a start, so the signal is not needed for their features
     pulses
         .map(|pulse| {
             (
-                pulse.start.time.unwrap() as usize,
+                pulse.start.time.unwrap(),
                 pulse.start.value.unwrap_or_default() as Intensity,
+                with_features.then(|| PulseFeatures::new(&pulse, &[])),
             )
         })
         .collect()
 }
 
+/// Whether the peak of a pulse is within the given limits, those not given being no limit.
+fn within_amplitudes(
+    pulse: &Pulse,
+    min_amplitude: Option<Real>,
+    max_amplitude: Option<Real>,
+) -> bool {
+    let Some(value) = pulse.peak.value else {
+        return true;
+    };
+    min_amplitude.into_iter().all(|min| min <= value)
+        && max_amplitude.into_iter().all(|max| max >= value)
+}
+
+fn filter_chain(filters: &[FilterWrapper]) -> FilterChain {
+    FilterChain::new(filters.iter().map(|filter| filter.0.clone()).collect())
+}
+
+/// Validation ensures the parameters required by the baseline tracking are given.
+fn advanced_baseline(parameters: &AdvancedMuonDetectorParameters) -> Baseline {
+    let baseline = Baseline::new(parameters.baseline_length.unwrap_or(0), 0.1);
+    match parameters.baseline_tracking {
+        BaselineTracking::Fixed => baseline,
+        BaselineTracking::Gated => baseline.with_gated_tracking(
+            parameters.baseline_gate.unwrap_or(Real::INFINITY),
+            parameters.baseline_smoothing_factor.unwrap_or(0.01),
+        ),
+        BaselineTracking::RollingMedian => {
+            baseline.with_rolling_median(parameters.baseline_window_size.unwrap_or(1))
+        }
+    }
+}
+
 fn find_advanced_events(
+    digitiser_id: DigitizerId,
     trace: &ChannelTrace,
     parameters: &AdvancedMuonDetectorParameters,
-    save_path: Option<&Path>,
-) -> Vec<(usize, Intensity)> {
+    capture: Option<&mut ChannelCapture>,
+    with_features: bool,
+) -> Vec<ChannelEvent> {
     let raw = trace
         .voltage()
         .unwrap()
@@ -111,7 +218,8 @@ fn find_advanced_events(
 
     let smoothed = raw
         .clone()
-        .window(Baseline::new(parameters.baseline_length.unwrap_or(0), 0.1))
+        .window(advanced_baseline(parameters))
+        .window(filter_chain(&parameters.filters))
         .window(SmoothingWindow::new(
             parameters.smoothing_window_size.unwrap_or(1),
         ))
@@ -120,70 +228,195 @@ fn find_advanced_events(
     let events = smoothed
         .clone()
         .window(FiniteDifferences::<2>::new())
-        .events(AdvancedMuonDetector::new(
-            parameters.muon_onset,
-            parameters.muon_fall,
-            parameters.muon_termination,
-            parameters.duration,
-        ));
+        .events(
+            AdvancedMuonDetector::new(
+                parameters.muon_onset,
+                parameters.muon_fall,
+                parameters.muon_termination,
+                parameters.duration,
+            )
+            .with_interpolation(),
+        );
+
+    // Pile-up and pulse features are found in the smoothed signal, so it is only kept if needed
+    let samples = if with_features || parameters.pile_up_prominence.is_some() {
+        smoothed.clone().collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+    let pile_up = parameters.pile_up_prominence.map(|prominence| {
+        (
+            PileUpSplitter::new(prominence),
+            metrics::PILE_UP_PULSES
+                .get_or_create(&metrics::ChannelLabels::new(digitiser_id, trace.channel()))
+                .clone(),
+        )
+    });
 
-    let pulses = events
+    // Collected so that piled-up pulses are only counted once
+    let pulses: Vec<Pulse> = events
         .clone()
         .assemble(BasicMuonAssembler::default())
-        .filter(|pulse| {
-            Option::zip(parameters.min_amplitude, pulse.peak.value)
-                .map(|(min, val)| min <= val)
-                .unwrap_or(true)
+        .flat_map(|pulse| match &pile_up {
+            Some((splitter, pile_up_pulses)) => {
+                let split = splitter.split(pulse.clone(), &samples);
+                if split.len() > 1 {
+                    pile_up_pulses.inc();
+                }
+                if parameters.split_pile_up {
+                    split
+                } else {
+                    vec![pulse]
+                }
+            }
+            None => vec![pulse],
         })
         .filter(|pulse| {
-            Option::zip(parameters.max_amplitude, pulse.peak.value)
-                .map(|(max, val)| max >= val)
-                .unwrap_or(true)
-        });
+            within_amplitudes(pulse, parameters.min_amplitude, parameters.max_amplitude)
+        })
+        .collect();
 
-    if let Some(save_path) = save_path {
-        raw.clone()
-            .save_to_file(&get_save_file_name(save_path, trace.channel(), "raw"))
-            .unwrap();
+    if let Some(capture) = capture {
+        capture.signal("raw", raw.clone());
+        capture.signal("smoothed", smoothed.clone());
+        capture.signal(
+            "derivative",
+            smoothed
+                .clone()
+                .window(FiniteDifferences::<2>::new())
+                .map(|(i, diffs)| (i, diffs[1])),
+        );
+        capture.pulses(pulses.iter().cloned());
+    }
 
-        smoothed
-            .clone()
-            .save_to_file(&get_save_file_name(save_path, trace.channel(), "smoothed"))
-            .unwrap();
+    pulses
+        .into_iter()
+        .map(|pulse| {
+            (
+                pulse.steepest_rise.time.unwrap_or_default(),
+                pulse.peak.value.unwrap_or_default() as Intensity,
+                with_features.then(|| PulseFeatures::new(&pulse, &samples)),
+            )
+        })
+        .collect()
+}
 
-        pulses
-            .clone()
-            .save_to_file(&get_save_file_name(save_path, trace.channel(), "pulses"))
-            .unwrap();
+fn find_constant_fraction_events(
+    trace: &ChannelTrace,
+    parameters: &ConstantFractionDiscriminatorParameters,
+    capture: Option<&mut ChannelCapture>,
+    with_features: bool,
+) -> Vec<ChannelEvent> {
+    let raw = trace
+        .voltage()
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (i as Real, -(v as Real)));
+
+    let baselined = raw
+        .clone()
+        .window(Baseline::new(parameters.baseline_length.unwrap_or(0), 0.1))
+        .window(filter_chain(&parameters.filters));
+
+    let signal = with_features.then(|| baselined.clone().collect::<Vec<_>>());
+
+    let pulses = baselined
+        .clone()
+        .events(ConstantFractionDetector::new(
+            parameters.delay,
+            parameters.fraction,
+            parameters.arming_threshold,
+        ))
+        .assemble(ConstantFractionAssembler::default())
+        .filter(|pulse| {
+            within_amplitudes(pulse, parameters.min_amplitude, parameters.max_amplitude)
+        });
+
+    if let Some(capture) = capture {
+        capture.signal("raw", raw.clone());
+        capture.signal("baselined", baselined.clone());
+        capture.pulses(pulses.clone());
     }
 
     pulses
         .map(|pulse| {
             (
-                pulse.steepest_rise.time.unwrap_or_default() as usize,
+                pulse.start.time.unwrap_or_default(),
                 pulse.peak.value.unwrap_or_default() as Intensity,
+                signal
+                    .as_deref()
+                    .map(|signal| PulseFeatures::new(&pulse, signal)),
             )
         })
         .collect()
 }
 
-fn get_save_file_name(path: &Path, channel: Channel, subscript: &str) -> PathBuf {
-    let file_name = format!(
-        "{0}{channel}_{subscript}",
-        path.file_stem()
-            .and_then(|os_str| os_str.to_str())
-            .expect("file-name should be a valid file name")
-    );
-    match path.parent() {
-        Some(parent) => parent.to_owned().join(file_name).with_extension("csv"),
-        None => PathBuf::from(file_name).with_extension("csv"),
+fn find_template_events(
+    trace: &ChannelTrace,
+    parameters: &TemplateFitParameters,
+    capture: Option<&mut ChannelCapture>,
+    with_features: bool,
+) -> Vec<ChannelEvent> {
+    let raw = trace
+        .voltage()
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (i as Real, -(v as Real)));
+
+    let smoothed = raw
+        .clone()
+        .window(Baseline::new(parameters.baseline_length.unwrap_or(0), 0.1))
+        .window(filter_chain(&parameters.filters))
+        .window(SmoothingWindow::new(
+            parameters.smoothing_window_size.unwrap_or(1),
+        ))
+        .map(|(i, stats)| (i, stats.mean));
+
+    let template = parameters
+        .template()
+        .expect("template should have been validated")
+        .clone();
+
+    let signal = with_features.then(|| smoothed.clone().collect::<Vec<_>>());
+
+    let pulses = smoothed
+        .clone()
+        .events(TemplateDetector::new(
+            template,
+            parameters.threshold,
+            parameters.min_correlation,
+        ))
+        .assemble(TemplateAssembler::default())
+        .filter(|pulse| {
+            within_amplitudes(pulse, parameters.min_amplitude, parameters.max_amplitude)
+        });
+
+    if let Some(capture) = capture {
+        capture.signal("raw", raw.clone());
+        capture.signal("smoothed", smoothed.clone());
+        capture.pulses(pulses.clone());
     }
+
+    pulses
+        .map(|pulse| {
+            (
+                pulse.start.time.unwrap_or_default(),
+                pulse.peak.value.unwrap_or_default() as Intensity,
+                signal
+                    .as_deref()
+                    .map(|signal| PulseFeatures::new(&pulse, signal)),
+            )
+        })
+        .collect()
 }
 
 pub(crate) fn process(
     trace: &DigitizerAnalogTraceMessage,
-    mode: &Mode,
-    save_options: Option<&Path>,
+    config: &DetectorConfig,
+    capture: Option<&Capture>,
+    with_features: bool,
 ) -> Vec<u8> {
     info!(
         "Dig ID: {}, Metadata: {:?}",
@@ -197,23 +430,44 @@ pub(crate) fn process(
 
     let sample_time_in_ns: Real = 1_000_000_000.0 / trace.sample_rate() as Real;
 
+    // Channels are independent, so are processed in parallel,
+    // collecting preserves the order of the channels in the message
     let channel_events = trace
         .channels()
         .unwrap()
         .iter()
         .collect::<Vec<ChannelTrace>>()
-        .iter()
-        .map(move |channel_trace| {
-            find_channel_events(channel_trace, sample_time_in_ns, mode, save_options)
+        .par_iter()
+        .map(|channel_trace| {
+            let mode = config.mode(trace.digitizer_id(), channel_trace.channel());
+            find_channel_events(
+                trace.digitizer_id(),
+                channel_trace,
+                sample_time_in_ns,
+                mode,
+                capture,
+                with_features,
+            )
         })
         .collect::<Vec<ChannnelEvents>>();
 
+    let mut features = PulseFeatureData::default();
+    let mut channel_captures = Vec::new();
+
     for mut channel in channel_events {
+        if let Some(channel_capture) = channel.capture.take() {
+            channel_captures.push((channel.channel_number, channel_capture));
+        }
         events
             .channel
             .append(&mut vec![channel.channel_number; channel.time.len()]);
         events.time.append(&mut channel.time);
         events.voltage.append(&mut channel.voltage);
+        features.append(&mut channel.features);
+    }
+
+    if let Some(capture) = capture {
+        capture.send(trace, channel_captures);
     }
 
     let metadata = FrameMetadataV1Args {
@@ -230,13 +484,20 @@ pub(crate) fn process(
     let voltage = Some(fbb.create_vector(&events.voltage));
     let channel = Some(fbb.create_vector(&events.channel));
 
-    let message = DigitizerEventListMessageArgs {
+    let mut message = DigitizerEventListMessageArgs {
         digitizer_id: trace.digitizer_id(),
         metadata: Some(metadata),
         time,
         voltage,
         channel,
+        ..Default::default()
     };
+    if with_features {
+        message.peak_time = Some(fbb.create_vector(&features.peak_time));
+        message.rise_time = Some(fbb.create_vector(&features.rise_time));
+        message.width = Some(fbb.create_vector(&features.width));
+        message.area = Some(fbb.create_vector(&features.area));
+    }
     let message = DigitizerEventListMessage::create(&mut fbb, &message);
     finish_digitizer_event_list_message_buffer(&mut fbb, message);
 
@@ -245,11 +506,17 @@ pub(crate) fn process(
 
 #[cfg(test)]
 mod tests {
-    use crate::parameters::ThresholdDurationWrapper;
+    use crate::{
+        capture::{CaptureOptions, CaptureTrigger},
+        parameters::ThresholdDurationWrapper,
+    };
     use chrono::Utc;
-    use std::str::FromStr;
+    use rand::Rng;
+    use std::{path::Path, str::FromStr, time::Instant};
+    use supermusr_common::DigitizerId;
     use supermusr_streaming_types::{
         dat1_digitizer_analog_trace_v1_generated::{
+            digitizer_analog_trace_message_buffer_has_identifier,
             finish_digitizer_analog_trace_message_buffer, root_as_digitizer_analog_trace_message,
             ChannelTraceArgs, DigitizerAnalogTraceMessage, DigitizerAnalogTraceMessageArgs,
         },
@@ -302,11 +569,13 @@ mod tests {
 
         let test_parameters = ConstantPhaseDiscriminatorParameters {
             threshold_trigger: ThresholdDurationWrapper::from_str("-5,1,0").unwrap(),
+            filters: Vec::new(),
         };
         let result = process(
             &message,
-            &Mode::ConstantPhaseDiscriminator(test_parameters),
+            &Mode::ConstantPhaseDiscriminator(test_parameters).into(),
             None,
+            false,
         );
 
         assert!(digitizer_event_list_message_buffer_has_identifier(&result));
@@ -327,4 +596,475 @@ mod tests {
             event_message.voltage().unwrap().iter().collect::<Vec<_>>()
         );
     }
+
+    fn trace_message(
+        digitizer_id: DigitizerId,
+        sample_rate: u64,
+        channels: &[Vec<Intensity>],
+    ) -> Vec<u8> {
+        let mut fbb = FlatBufferBuilder::new();
+
+        let time: GpsTime = Utc::now().into();
+
+        let metadata = FrameMetadataV1Args {
+            frame_number: 0,
+            period_number: 0,
+            protons_per_pulse: 0,
+            running: true,
+            timestamp: Some(&time),
+            veto_flags: 0,
+        };
+        let metadata = FrameMetadataV1::create(&mut fbb, &metadata);
+
+        let channels = channels
+            .iter()
+            .enumerate()
+            .map(|(channel, voltage)| {
+                let voltage = Some(fbb.create_vector(voltage));
+                ChannelTrace::create(
+                    &mut fbb,
+                    &ChannelTraceArgs {
+                        channel: channel as Channel,
+                        voltage,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let message = DigitizerAnalogTraceMessageArgs {
+            digitizer_id,
+            metadata: Some(metadata),
+            sample_rate,
+            channels: Some(fbb.create_vector(&channels)),
+        };
+        let message = DigitizerAnalogTraceMessage::create(&mut fbb, &message);
+        finish_digitizer_analog_trace_message_buffer(&mut fbb, message);
+
+        fbb.finished_data().to_vec()
+    }
+
+    #[test]
+    fn test_channel_order_preserved() {
+        let channels = (0..8)
+            .map(|channel| {
+                let mut voltage = vec![10; 100];
+                voltage[10 + 3 * channel] = 2;
+                voltage[50 + channel] = 2;
+                voltage
+            })
+            .collect::<Vec<_>>();
+
+        let message = trace_message(3, 1_000_000_000, &channels);
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = ConstantPhaseDiscriminatorParameters {
+            threshold_trigger: ThresholdDurationWrapper::from_str("-5,1,0").unwrap(),
+            filters: Vec::new(),
+        };
+        let result = process(
+            &message,
+            &Mode::ConstantPhaseDiscriminator(test_parameters).into(),
+            None,
+            false,
+        );
+        let event_message = root_as_digitizer_event_list_message(&result).unwrap();
+
+        assert_eq!(event_message.digitizer_id(), 3);
+        assert_eq!(
+            (0..8).flat_map(|c| [c, c]).collect::<Vec<_>>(),
+            event_message.channel().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (0..8)
+                .flat_map(|c| [10 + 3 * c, 50 + c])
+                .collect::<Vec<_>>(),
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_constant_phase_filters() {
+        let mut voltage = vec![10; 100];
+        voltage[20] = 2;
+        voltage[60..70].fill(2);
+
+        let message = trace_message(3, 1_000_000_000, &[voltage]);
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let event_times = |filters: &[&str]| {
+            let parameters = ConstantPhaseDiscriminatorParameters {
+                threshold_trigger: ThresholdDurationWrapper::from_str("-5,1,0").unwrap(),
+                filters: filters
+                    .iter()
+                    .map(|filter| FilterWrapper::from_str(filter).unwrap())
+                    .collect(),
+            };
+            let result = process(
+                &message,
+                &Mode::ConstantPhaseDiscriminator(parameters).into(),
+                None,
+                false,
+            );
+            root_as_digitizer_event_list_message(&result)
+                .unwrap()
+                .time()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(event_times(&[]), [20, 60]);
+        // The low-pass filter removes the single sample spike, but not the longer pulse
+        assert_eq!(event_times(&["low-pass:0.05"]).len(), 1);
+    }
+
+    #[test]
+    fn test_channel_parameters() {
+        let mut voltage = vec![10; 100];
+        voltage[20] = 2;
+        voltage[60] = 0;
+
+        let message = trace_message(
+            3,
+            1_000_000_000,
+            &[voltage.clone(), voltage.clone(), voltage],
+        );
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let threshold = |threshold| {
+            Mode::ConstantPhaseDiscriminator(ConstantPhaseDiscriminatorParameters {
+                threshold_trigger: ThresholdDurationWrapper::from_str(threshold).unwrap(),
+                filters: Vec::new(),
+            })
+        };
+        let mut config = DetectorConfig::from(threshold("-5,1,0"));
+        config.channels.insert((3, 1), threshold("-1,1,0"));
+        // Parameters of other digitisers are not used
+        config.channels.insert((4, 2), threshold("-1,1,0"));
+
+        let result = process(&message, &config, None, false);
+        let event_message = root_as_digitizer_event_list_message(&result).unwrap();
+
+        assert_eq!(
+            vec![0, 0, 1, 2, 2],
+            event_message.channel().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![20, 60, 60, 20, 60],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_event_times_interpolated() {
+        let mut voltage = vec![10; 100];
+        voltage[20] = 2;
+        voltage[60] = 0;
+
+        // 10 ns between samples
+        let message = trace_message(0, 100_000_000, &[voltage]);
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let test_parameters = ConstantPhaseDiscriminatorParameters {
+            threshold_trigger: ThresholdDurationWrapper::from_str("-5,1,0").unwrap(),
+            filters: Vec::new(),
+        };
+        let result = process(
+            &message,
+            &Mode::ConstantPhaseDiscriminator(test_parameters).into(),
+            None,
+            false,
+        );
+        let event_message = root_as_digitizer_event_list_message(&result).unwrap();
+
+        // The threshold is crossed 5/8 and 1/2 of the way from the samples before the pulses
+        assert_eq!(
+            vec![196, 595],
+            event_message.time().unwrap().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_pile_up_split() {
+        // Two muons arriving eight samples apart
+        let voltage = (0..200)
+            .map(|i| {
+                [20.0, 28.0]
+                    .into_iter()
+                    .map(|start| {
+                        let t = i as Real - start;
+                        if t < 0.0 {
+                            0.0
+                        } else {
+                            100.0 * ((-t / 20.0).exp() - (-t / 2.0).exp())
+                        }
+                    })
+                    .fold(1000.0, |v, pulse| v - pulse) as Intensity
+            })
+            .collect();
+
+        let message = trace_message(5, 1_000_000_000, &[voltage]);
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let parameters = AdvancedMuonDetectorParameters {
+            muon_onset: 1.0,
+            muon_fall: -1.0,
+            muon_termination: -0.01,
+            duration: 3.0,
+            pile_up_prominence: Some(2.0),
+            ..Default::default()
+        };
+        let num_events = |parameters: AdvancedMuonDetectorParameters| {
+            let result = process(
+                &message,
+                &Mode::AdvancedMuonDetector(parameters).into(),
+                None,
+                false,
+            );
+            let event_message = root_as_digitizer_event_list_message(&result).unwrap();
+            event_message.time().unwrap().len()
+        };
+
+        let pile_up_pulses = metrics::PILE_UP_PULSES
+            .get_or_create(&metrics::ChannelLabels::new(5, 0))
+            .clone();
+
+        assert_eq!(num_events(parameters.clone()), 1);
+        assert_eq!(pile_up_pulses.get(), 1);
+
+        assert_eq!(
+            num_events(AdvancedMuonDetectorParameters {
+                split_pile_up: true,
+                ..parameters
+            }),
+            2
+        );
+        assert_eq!(pile_up_pulses.get(), 2);
+    }
+
+    #[test]
+    fn test_capture() {
+        let voltage: Vec<Intensity> = (0..100)
+            .map(|i| if (40..45).contains(&i) { 900 } else { 1000 })
+            .collect();
+        let message = trace_message(2, 1_000_000_000, &[voltage.clone(), voltage]);
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let mut config =
+            DetectorConfig::from(Mode::AdvancedMuonDetector(AdvancedMuonDetectorParameters {
+                muon_onset: 1.0,
+                muon_fall: -1.0,
+                muon_termination: -0.01,
+                duration: 1.0,
+                ..Default::default()
+            }));
+        config.channels.insert(
+            (2, 1),
+            Mode::ConstantPhaseDiscriminator(ConstantPhaseDiscriminatorParameters {
+                threshold_trigger: ThresholdDurationWrapper::from_str("-950,1,0").unwrap(),
+                filters: Vec::new(),
+            }),
+        );
+
+        let dir = std::env::temp_dir().join(format!(
+            "trace-to-events-process-capture-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut trigger = CaptureTrigger::new(CaptureOptions {
+            capture_dir: Some(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        trigger.request(1);
+        let capture = trigger.trigger(2);
+        process(&message, &config, capture.as_ref(), false);
+        drop(capture);
+        trigger.finish();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let file = hdf5::File::open(files[0].as_ref().unwrap().path()).unwrap();
+        let file = file.group("message_0").unwrap();
+        assert_eq!(
+            file.member_names().unwrap(),
+            vec!["channel_0", "channel_1", "metadata"]
+        );
+        assert_eq!(
+            file.group("channel_0").unwrap().member_names().unwrap(),
+            vec!["derivative", "pulses", "raw", "smoothed"]
+        );
+        assert_eq!(
+            file.group("channel_1").unwrap().member_names().unwrap(),
+            vec!["pulses", "raw"]
+        );
+        let pulse_times = file
+            .dataset("channel_1/pulses/start_time")
+            .unwrap()
+            .read_raw::<Real>()
+            .unwrap();
+        // The threshold is crossed half way between samples
+        assert_eq!(pulse_times, vec![39.5]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pulse_features() {
+        let voltage: Vec<Intensity> = (0..200)
+            .map(|i| {
+                let t = i as Real - 20.0;
+                let pulse = if t < 0.0 {
+                    0.0
+                } else {
+                    100.0 * ((-t / 20.0).exp() - (-t / 2.0).exp())
+                };
+                (1000.0 - pulse) as Intensity
+            })
+            .collect();
+
+        // 10 ns between samples
+        let message = trace_message(6, 100_000_000, &[voltage.clone(), voltage]);
+        let message = root_as_digitizer_analog_trace_message(&message).unwrap();
+
+        let mut config =
+            DetectorConfig::from(Mode::AdvancedMuonDetector(AdvancedMuonDetectorParameters {
+                muon_onset: 1.0,
+                muon_fall: -1.0,
+                muon_termination: -0.01,
+                duration: 3.0,
+                baseline_length: Some(10),
+                ..Default::default()
+            }));
+        config.channels.insert(
+            (6, 1),
+            Mode::ConstantPhaseDiscriminator(ConstantPhaseDiscriminatorParameters {
+                threshold_trigger: ThresholdDurationWrapper::from_str("-990,1,0").unwrap(),
+                filters: Vec::new(),
+            }),
+        );
+
+        let result = process(&message, &config, None, false);
+        let event_message = root_as_digitizer_event_list_message(&result).unwrap();
+        assert!(event_message.peak_time().is_none());
+        assert!(event_message.area().is_none());
+
+        let result = process(&message, &config, None, true);
+        let event_message = root_as_digitizer_event_list_message(&result).unwrap();
+        assert_eq!(
+            vec![0, 1],
+            event_message.channel().unwrap().iter().collect::<Vec<_>>()
+        );
+        let time: Vec<_> = event_message.time().unwrap().iter().collect();
+        let peak_time: Vec<_> = event_message.peak_time().unwrap().iter().collect();
+        let rise_time: Vec<_> = event_message.rise_time().unwrap().iter().collect();
+        let width: Vec<_> = event_message.width().unwrap().iter().collect();
+        let area: Vec<_> = event_message.area().unwrap().iter().collect();
+
+        // The pulse peaks 5.1 samples after it starts, and is found once it has risen for three samples
+        assert!(peak_time[0] > time[0]);
+        assert!(rise_time[0] > 0.0 && rise_time[0] <= 30.0);
+        assert!(width[0] > 500.0);
+        // The pulse has an area of 1800 voltage samples, most of which is before its end
+        assert!(area[0] > 10_000.0 && area[0] < 18_000.0);
+
+        // The constant phase discriminator only finds the time of each pulse
+        assert_eq!(peak_time[1], time[1]);
+        assert!(rise_time[1].is_nan() && width[1].is_nan() && area[1].is_nan());
+    }
+
+    /// Reads `dat1` messages from a file in which each message is prefixed by its length as a little endian `u32`.
+    fn read_recorded_traces(path: &Path) -> Vec<Vec<u8>> {
+        let bytes = std::fs::read(path).expect("trace file should be readable");
+
+        let mut messages = Vec::new();
+        let mut remaining = bytes.as_slice();
+        while remaining.len() >= 4 {
+            let (length, rest) = remaining.split_at(4);
+            let length = u32::from_le_bytes(length.try_into().unwrap());
+            let (message, rest) = rest.split_at(length as usize);
+            if digitizer_analog_trace_message_buffer_has_identifier(message) {
+                messages.push(message.to_vec());
+            }
+            remaining = rest;
+        }
+        messages
+    }
+
+    fn synthetic_traces() -> Vec<Vec<u8>> {
+        let mut rng = rand::thread_rng();
+        (0..64)
+            .map(|_| {
+                let channels = (0..8)
+                    .map(|_| {
+                        let mut voltage: Vec<Intensity> =
+                            (0..30_000).map(|_| rng.gen_range(995..1005)).collect();
+                        for _ in 0..50 {
+                            let start = rng.gen_range(0..29_900);
+                            let amplitude = rng.gen_range(100.0..800.0);
+                            for (t, v) in voltage[start..start + 100].iter_mut().enumerate() {
+                                let t = t as Real;
+                                let pulse = amplitude * ((-t / 20.0).exp() - (-t / 2.0).exp());
+                                *v = v.saturating_sub(pulse as Intensity);
+                            }
+                        }
+                        voltage
+                    })
+                    .collect::<Vec<_>>();
+                trace_message(0, 1_000_000_000, &channels)
+            })
+            .collect()
+    }
+
+    /// Compares processing the channels of each message on one thread with processing them
+    /// on all available threads. Recorded traces are used if `TRACE_TO_EVENTS_BENCHMARK_TRACES`
+    /// gives a path to a file readable by [read_recorded_traces], otherwise synthetic traces are used.
+    ///
+    /// Run with `cargo test --release -p trace-to-events benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn benchmark_channel_processing() {
+        let messages = match std::env::var_os("TRACE_TO_EVENTS_BENCHMARK_TRACES") {
+            Some(path) => read_recorded_traces(Path::new(&path)),
+            None => synthetic_traces(),
+        };
+
+        let config: DetectorConfig = Mode::AdvancedMuonDetector(AdvancedMuonDetectorParameters {
+            muon_onset: 1.0,
+            muon_fall: -0.1,
+            muon_termination: 0.01,
+            duration: 1.0,
+            baseline_length: Some(1000),
+            smoothing_window_size: Some(10),
+            ..Default::default()
+        })
+        .into();
+
+        let time_with_threads = |num_threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            let start = Instant::now();
+            pool.install(|| {
+                for message in &messages {
+                    let message = root_as_digitizer_analog_trace_message(message).unwrap();
+                    process(&message, &config, None, false);
+                }
+            });
+            start.elapsed()
+        };
+
+        let num_threads = rayon::current_num_threads();
+        let sequential = time_with_threads(1);
+        let parallel = time_with_threads(num_threads);
+
+        println!(
+            "{} messages: 1 thread {:?}, {num_threads} threads {:?} ({:.2}x)",
+            messages.len(),
+            sequential,
+            parallel,
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
 }
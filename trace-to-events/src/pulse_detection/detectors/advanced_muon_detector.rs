@@ -60,12 +60,75 @@ enum Mode {
     Fall,
 }
 
+/// The values of the samples either side of a superlative, used to refine its time
+/// by fitting a parabola through the three samples. Samples are assumed to be one time unit apart.
+#[derive(Clone, Debug, Default)]
+struct Neighbours {
+    before: Option<Real>,
+    after: Option<Real>,
+    awaiting_after: bool,
+}
+
+impl Neighbours {
+    /// Called when the superlative is replaced by the current sample.
+    fn replace(&mut self, before: Option<Real>) {
+        self.before = before;
+        self.after = None;
+        self.awaiting_after = true;
+    }
+
+    /// Called with each sample before any replacement of the superlative.
+    fn push(&mut self, value: Real) {
+        if self.awaiting_after {
+            self.after = Some(value);
+            self.awaiting_after = false;
+        }
+    }
+
+    /// Returns the time of the vertex of the parabola through the superlative and its neighbours.
+    fn refine(&self, superlative: &TimeValue<Real>) -> Real {
+        match (self.before, self.after) {
+            (Some(before), Some(after)) => {
+                let curvature = before - 2.0 * superlative.value + after;
+                if curvature == 0.0 {
+                    superlative.time
+                } else {
+                    superlative.time + (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+                }
+            }
+            _ => superlative.time,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Refinement {
+    previous: Option<RealArray<2>>,
+    superlative_value: Neighbours,
+    superlative_diff: Neighbours,
+}
+
 #[derive(Clone, Debug)]
-struct State(Mode, SuperlativeValue, SuperlativeDiff);
+struct State(Mode, SuperlativeValue, SuperlativeDiff, Refinement);
 
 impl State {
-    fn from_mode(mode: Option<Mode>, time: Real, value: &RealArray<2>) -> Option<Self> {
+    fn from_mode(
+        mode: Option<Mode>,
+        time: Real,
+        value: &RealArray<2>,
+        previous: Option<RealArray<2>>,
+    ) -> Option<Self> {
         mode.map(|mode| {
+            let mut refinement = Refinement {
+                previous,
+                ..Default::default()
+            };
+            refinement
+                .superlative_value
+                .replace(previous.map(|previous| previous[0]));
+            refinement
+                .superlative_diff
+                .replace(previous.map(|previous| previous[1]));
             State(
                 mode,
                 SuperlativeValue {
@@ -76,40 +139,51 @@ impl State {
                     time,
                     value: *value,
                 },
+                refinement,
             )
         })
     }
 
     fn test_and_update_superlative(&mut self, time: Real, value: &RealArray<2>) {
-        match self {
-            State(Mode::Rise, peak, steepest_rise) => {
-                //  Update Steepest Rise
-                if value[1] >= steepest_rise.value[1] {
-                    steepest_rise.time = time;
-                    steepest_rise.value = *value;
-                }
-                //  Update Peak
-                if value[0] >= peak.value {
-                    peak.time = time;
-                    peak.value = value[0];
-                }
-            }
-            State(Mode::Fall, nadir, sharpest_fall) => {
-                if value[1] <= sharpest_fall.value[1] {
-                    sharpest_fall.time = time;
-                    sharpest_fall.value = *value;
-                }
-                //  Update Nadir
-                if value[0] <= nadir.value {
-                    nadir.time = time;
-                    nadir.value = value[0];
-                }
-            }
+        let State(mode, extreme, extreme_diff, refinement) = self;
+        refinement.superlative_value.push(value[0]);
+        refinement.superlative_diff.push(value[1]);
+        let previous = refinement.previous;
+
+        let (replace_diff, replace_value) = match mode {
+            //  Update Steepest Rise and Peak
+            Mode::Rise => (value[1] >= extreme_diff.value[1], value[0] >= extreme.value),
+            //  Update Sharpest Fall and Nadir
+            Mode::Fall => (value[1] <= extreme_diff.value[1], value[0] <= extreme.value),
+        };
+        if replace_diff {
+            extreme_diff.time = time;
+            extreme_diff.value = *value;
+            refinement
+                .superlative_diff
+                .replace(previous.map(|previous| previous[1]));
+        }
+        if replace_value {
+            extreme.time = time;
+            extreme.value = value[0];
+            refinement
+                .superlative_value
+                .replace(previous.map(|previous| previous[0]));
         }
+        refinement.previous = Some(*value);
     }
 
-    fn generate_event(&self) -> BasicMuonEvent {
-        let State(mode, extreme, extreme_diff) = self;
+    fn generate_event(&self, interpolate: bool) -> BasicMuonEvent {
+        let State(mode, extreme, extreme_diff, refinement) = self;
+        let mut extreme = extreme.clone();
+        let mut extreme_diff = extreme_diff.clone();
+        if interpolate {
+            extreme.time = refinement.superlative_value.refine(&extreme);
+            extreme_diff.time = refinement.superlative_diff.refine(&TimeValue {
+                time: extreme_diff.time,
+                value: extreme_diff.value[1],
+            });
+        }
         (
             extreme.time,
             Data {
@@ -135,6 +209,9 @@ pub(crate) struct AdvancedMuonDetector {
     //If the change lasts the requisite duration then the mode is changed.
     state: Option<State>,
     time_crossed: Option<Real>,
+
+    interpolate: bool,
+    previous: Option<RealArray<2>>,
 }
 
 impl AdvancedMuonDetector {
@@ -148,10 +225,20 @@ impl AdvancedMuonDetector {
         }
     }
 
+    /// The times of the peak, nadir, steepest rise and sharpest fall of each pulse
+    /// are refined by fitting a parabola through the samples either side of them,
+    /// rather than being the times of the samples.
+    pub(crate) fn with_interpolation(self) -> Self {
+        Self {
+            interpolate: true,
+            ..self
+        }
+    }
+
     fn test_threshold(&self, value: &RealArray<2>) -> bool {
         match &self.state {
-            Some(State(Mode::Rise, _, _)) => value[1] <= self.fall_threshold,
-            Some(State(Mode::Fall, _, _)) => value[1] >= self.termination_threshold,
+            Some(State(Mode::Rise, ..)) => value[1] <= self.fall_threshold,
+            Some(State(Mode::Fall, ..)) => value[1] >= self.termination_threshold,
             None => value[1] >= self.onset_threshold,
         }
     }
@@ -178,6 +265,14 @@ impl Detector for AdvancedMuonDetector {
     type EventPointType = (Real, Data);
 
     fn signal(&mut self, time: Real, value: RealArray<2>) -> Option<BasicMuonEvent> {
+        let event = self.detect(time, value);
+        self.previous = Some(value);
+        event
+    }
+}
+
+impl AdvancedMuonDetector {
+    fn detect(&mut self, time: Real, value: RealArray<2>) -> Option<BasicMuonEvent> {
         self.test_and_update_threshold(time, &value);
         if let Some(state) = &mut self.state {
             state.test_and_update_superlative(time, &value);
@@ -185,8 +280,8 @@ impl Detector for AdvancedMuonDetector {
         match &self.state {
             Some(state) => {
                 if self.test_threshold_duration(time) {
-                    let event = state.generate_event();
-                    let State(mode, _, _) = &state;
+                    let event = state.generate_event(self.interpolate);
+                    let State(mode, ..) = &state;
                     self.state = State::from_mode(
                         match mode {
                             Mode::Rise => Some(Mode::Fall),
@@ -194,6 +289,7 @@ impl Detector for AdvancedMuonDetector {
                         },
                         time,
                         &value,
+                        self.previous,
                     );
                     Some(event)
                 } else {
@@ -210,7 +306,7 @@ impl Detector for AdvancedMuonDetector {
                             ..Default::default()
                         },
                     );
-                    self.state = State::from_mode(Some(Mode::Rise), time, &value);
+                    self.state = State::from_mode(Some(Mode::Rise), time, &value, self.previous);
                     Some(event)
                 } else {
                     None
@@ -311,6 +407,7 @@ mod tests {
     use crate::pulse_detection::{
         datatype::tracevalue::TraceArray, window::FiniteDifferences, EventFilter, WindowFilter,
     };
+    use assert_approx_eq::assert_approx_eq;
 
     #[test]
     fn test_threshold() {
@@ -402,4 +499,32 @@ mod tests {
         );
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_interpolation() {
+        let data = [0, 0, 2, 8, 14, 16, 15, 10, 4, 1, 0, 0];
+        let detector = AdvancedMuonDetector::new(1.0, -0.5, -0.5, 0.0).with_interpolation();
+        let events: Vec<_> = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v as Real))
+            .window(FiniteDifferences::<2>::new())
+            .events(detector)
+            .collect();
+        assert_eq!(events.len(), 3);
+
+        // Onsets are not interpolated
+        assert_eq!(events[0].0, 2.0);
+
+        // The peak is nearer the sample after it than the one before it
+        let (time, data) = &events[1];
+        assert_eq!(data.get_class(), Class::Peak);
+        assert_approx_eq!(*time, 5.0 + 1.0 / 6.0);
+        // The rise is equally steep at samples 3 and 4
+        assert_approx_eq!(data.get_superlative().unwrap().time, 3.5);
+
+        let (_, data) = &events[2];
+        assert_eq!(data.get_class(), Class::End);
+        assert_approx_eq!(data.get_superlative().unwrap().time, 7.75);
+    }
 }
@@ -0,0 +1,242 @@
+use super::{Assembler, Detector, EventData, Pulse, Real, TimeValue, TimeValueOptional};
+use std::{collections::VecDeque, fmt::Display};
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Data {
+    peak: TimeValue<Real>,
+    end: TimeValue<Real>,
+}
+
+impl Data {
+    pub(crate) fn get_peak(&self) -> TimeValue<Real> {
+        self.peak.clone()
+    }
+
+    pub(crate) fn get_end(&self) -> TimeValue<Real> {
+        self.end.clone()
+    }
+}
+
+impl EventData for Data {}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{0},{1}", self.peak, self.end))
+    }
+}
+
+pub(crate) type ConstantFractionEvent = (Real, Data);
+
+#[derive(Default, Clone, Debug, PartialEq)]
+enum State {
+    #[default]
+    Idle,
+    /// The signal is above the arming threshold, but has not yet crossed zero
+    Armed,
+    /// The signal crossed zero at the given time
+    Triggered(Real),
+}
+
+/// Detects pulses by the zero crossing of the bipolar signal formed by subtracting a fraction
+/// of the signal from a delayed copy of itself. For pulses of the same shape the zero crossing
+/// is at the same point of each pulse regardless of its height, so the timing has no amplitude walk.
+///
+/// The signal should have its baseline removed. A zero crossing is only registered once the signal
+/// exceeds the arming threshold, and an event is given when the signal falls back below it.
+#[derive(Default, Clone)]
+pub(crate) struct ConstantFractionDetector {
+    delay: usize,
+    fraction: Real,
+    arming_threshold: Real,
+
+    delayed: VecDeque<Real>,
+    // Time and value of the bipolar signal at the previous sample
+    previous: Option<(Real, Real)>,
+    state: State,
+    peak: TimeValue<Real>,
+}
+
+impl ConstantFractionDetector {
+    pub(crate) fn new(delay: usize, fraction: Real, arming_threshold: Real) -> Self {
+        Self {
+            delay,
+            fraction,
+            arming_threshold,
+            delayed: VecDeque::with_capacity(delay + 1),
+            ..Default::default()
+        }
+    }
+}
+
+impl Detector for ConstantFractionDetector {
+    type TracePointType = (Real, Real);
+    type EventPointType = (Real, Data);
+
+    fn signal(&mut self, time: Real, value: Real) -> Option<ConstantFractionEvent> {
+        self.delayed.push_back(value);
+        if self.delayed.len() <= self.delay {
+            return None;
+        }
+        let delayed = self.delayed.pop_front()?;
+        let bipolar = delayed - self.fraction * value;
+
+        if value > self.arming_threshold {
+            if self.state == State::Idle {
+                self.state = State::Armed;
+                self.peak = TimeValue { time, value };
+            } else if value > self.peak.value {
+                self.peak = TimeValue { time, value };
+            }
+        }
+
+        if self.state == State::Armed {
+            if let Some((previous_time, previous_bipolar)) = self.previous {
+                if previous_bipolar < 0.0 && bipolar >= 0.0 {
+                    // Linearly interpolate the time of the zero crossing between the samples
+                    let crossing = previous_time
+                        + (time - previous_time) * previous_bipolar / (previous_bipolar - bipolar);
+                    self.state = State::Triggered(crossing);
+                }
+            }
+        }
+        self.previous = Some((time, bipolar));
+
+        if value <= self.arming_threshold {
+            match std::mem::take(&mut self.state) {
+                State::Triggered(crossing) => {
+                    return Some((
+                        crossing,
+                        Data {
+                            peak: self.peak.clone(),
+                            end: TimeValue { time, value },
+                        },
+                    ))
+                }
+                State::Armed | State::Idle => {}
+            }
+        }
+        None
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct ConstantFractionAssembler {}
+
+impl Assembler for ConstantFractionAssembler {
+    type DetectorType = ConstantFractionDetector;
+
+    fn assemble_pulses(
+        &mut self,
+        source: <Self::DetectorType as Detector>::EventPointType,
+    ) -> Option<Pulse> {
+        let (time, data) = source;
+        Some(Pulse {
+            start: TimeValueOptional {
+                time: Some(time),
+                ..Default::default()
+            },
+            peak: data.get_peak().into(),
+            end: data.get_end().into(),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::{test_traces::PulseShape, AssembleFilter, EventFilter};
+    use assert_approx_eq::assert_approx_eq;
+
+    /// Pulses with a fast rise and slow decay
+    const SHAPE: PulseShape = PulseShape {
+        rise_time: 3.0,
+        decay_time: 12.0,
+    };
+
+    #[test]
+    fn zero_data() {
+        let data: [(Real, Real); 0] = [];
+        let mut iter = data
+            .into_iter()
+            .events(ConstantFractionDetector::new(3, 0.5, 1.0));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn pulse_below_arming_threshold() {
+        let mut iter = SHAPE
+            .trace(200, &[(20.3, 2.0)])
+            .into_iter()
+            .events(ConstantFractionDetector::new(3, 0.5, 5.0));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn timing_independent_of_amplitude() {
+        let times: Vec<Real> = [10.0, 50.0, 200.0, 1000.0, 4000.0]
+            .into_iter()
+            .map(|amplitude| {
+                let events: Vec<_> = SHAPE
+                    .trace(200, &[(20.3, amplitude)])
+                    .into_iter()
+                    .events(ConstantFractionDetector::new(4, 0.3, 1.0))
+                    .collect();
+                assert_eq!(events.len(), 1);
+
+                let (time, data) = &events[0];
+                // Peak of the pulse shape is 0.472 of the amplitude, at 5.5 samples after the start
+                assert_approx_eq!(data.get_peak().value, 0.472 * amplitude, 0.001 * amplitude);
+                assert_approx_eq!(data.get_peak().time, 26.0, 1.0);
+                *time
+            })
+            .collect();
+
+        for time in &times {
+            assert!(*time > 20.3 && *time < 26.0);
+            assert_approx_eq!(*time, times[0], 1e-9);
+        }
+    }
+
+    #[test]
+    fn interpolates_between_samples() {
+        // Pulses shifted by a fraction of a sample are timed with the same shift,
+        // to within the error of the linear interpolation
+        let time = |start: Real| {
+            let (time, _) = SHAPE
+                .trace(200, &[(start, 100.0)])
+                .into_iter()
+                .events(ConstantFractionDetector::new(4, 0.3, 1.0))
+                .next()
+                .unwrap();
+            time
+        };
+        assert_approx_eq!(time(20.5) - time(20.0), 0.5, 0.1);
+        assert_approx_eq!(time(20.25) - time(20.0), 0.25, 0.1);
+    }
+
+    #[test]
+    fn consecutive_pulses() {
+        let pulses: Vec<_> = SHAPE
+            .trace(400, &[(20.0, 100.0), (150.0, 800.0), (300.0, 30.0)])
+            .into_iter()
+            .events(ConstantFractionDetector::new(4, 0.3, 1.0))
+            .assemble(ConstantFractionAssembler::default())
+            .collect();
+
+        assert_eq!(pulses.len(), 3);
+        let offset = pulses[0].start.time.unwrap() - 20.0;
+        for (pulse, (start, amplitude)) in
+            pulses
+                .iter()
+                .zip([(20.0, 100.0), (150.0, 800.0), (300.0, 30.0)])
+        {
+            assert_approx_eq!(pulse.start.time.unwrap(), start + offset, 1e-3);
+            assert_approx_eq!(
+                pulse.peak.value.unwrap(),
+                0.472 * amplitude,
+                0.001 * amplitude
+            );
+        }
+    }
+}
@@ -1,4 +1,6 @@
 pub mod advanced_muon_detector;
+pub mod constant_fraction_detector;
+pub mod template_detector;
 pub mod threshold_detector;
 
 use super::{
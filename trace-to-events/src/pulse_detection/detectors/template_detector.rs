@@ -0,0 +1,347 @@
+use super::{Assembler, Detector, EventData, Pulse, Real, TimeValue, TimeValueOptional};
+use anyhow::{anyhow, Error, Result};
+use std::{collections::VecDeque, fmt::Display, str::FromStr};
+
+/// The shape of a pulse, sampled at the same rate as the trace and scaled to a peak of one.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Template {
+    values: Vec<Real>,
+    peak_index: usize,
+    sum_of_squares: Real,
+}
+
+impl Template {
+    pub(crate) fn new(values: Vec<Real>) -> Result<Self> {
+        if values.iter().any(|value| !value.is_finite()) {
+            return Err(anyhow!("template values must be finite"));
+        }
+        let (peak_index, peak) = values
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .ok_or(anyhow!("template must not be empty"))?;
+        if peak <= 0.0 {
+            return Err(anyhow!("template must have a positive peak"));
+        }
+
+        let values: Vec<Real> = values.into_iter().map(|value| value / peak).collect();
+        let sum_of_squares = values.iter().map(|value| value * value).sum();
+        Ok(Self {
+            values,
+            peak_index,
+            sum_of_squares,
+        })
+    }
+
+    /// The difference of a decaying and a rising exponential, with time constants in samples,
+    /// of sufficient length for the pulse to have decayed to under 1% of its peak.
+    pub(crate) fn double_exponential(rise_time: Real, decay_time: Real) -> Result<Self> {
+        let length = (6.0 * decay_time).ceil() as usize + 1;
+        Self::new(
+            (0..length)
+                .map(|i| {
+                    let t = i as Real;
+                    (-t / decay_time).exp() - (-t / rise_time).exp()
+                })
+                .collect(),
+        )
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+/// Parses a template given as a list of values separated by commas or whitespace.
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(Real::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(values)
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub(crate) struct Data {
+    peak: TimeValue<Real>,
+    end: Real,
+    correlation: Real,
+}
+
+impl Data {
+    pub(crate) fn get_peak(&self) -> TimeValue<Real> {
+        self.peak.clone()
+    }
+
+    pub(crate) fn get_end(&self) -> Real {
+        self.end
+    }
+}
+
+impl EventData for Data {}
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{0},{1},{2}",
+            self.peak, self.end, self.correlation
+        ))
+    }
+}
+
+pub(crate) type TemplateEvent = (Real, Data);
+
+/// The best fit of the template to a pulse found so far, with the fits one sample either side of it.
+#[derive(Default, Clone, Debug)]
+struct Fit {
+    time: Real,
+    overlap: Real,
+    before: Option<Real>,
+    after: Option<Real>,
+    correlation: Real,
+}
+
+/// Detects pulses by fitting the template to the signal at each sample, the fitted amplitude
+/// being the least squares scaling of the template to the samples it overlaps.
+///
+/// A pulse is found while the fitted amplitude is at least the threshold, and an event is given
+/// when it falls below it. The event is timed at the start of the template where the fit is best,
+/// which is interpolated between samples, with the fitted amplitude as the value of the peak.
+/// Fits whose correlation with the template is less than `min_correlation` are discarded.
+///
+/// The signal should have its baseline removed.
+#[derive(Default, Clone)]
+pub(crate) struct TemplateDetector {
+    template: Template,
+    threshold: Real,
+    min_correlation: Real,
+
+    window: VecDeque<(Real, Real)>,
+    previous_overlap: Option<Real>,
+    best: Option<Fit>,
+}
+
+impl TemplateDetector {
+    pub(crate) fn new(template: Template, threshold: Real, min_correlation: Option<Real>) -> Self {
+        Self {
+            window: VecDeque::with_capacity(template.len()),
+            template,
+            threshold,
+            min_correlation: min_correlation.unwrap_or(Real::NEG_INFINITY),
+            ..Default::default()
+        }
+    }
+
+    fn generate_event(&self, fit: Fit) -> Option<TemplateEvent> {
+        if fit.correlation < self.min_correlation {
+            return None;
+        }
+        let (offset, overlap) = match (fit.before, fit.after) {
+            (Some(before), Some(after)) => {
+                let curvature = before - 2.0 * fit.overlap + after;
+                if curvature < 0.0 {
+                    let offset = (0.5 * (before - after) / curvature).clamp(-0.5, 0.5);
+                    (offset, fit.overlap - 0.25 * (before - after) * offset)
+                } else {
+                    (0.0, fit.overlap)
+                }
+            }
+            _ => (0.0, fit.overlap),
+        };
+        let time = fit.time + offset;
+        Some((
+            time,
+            Data {
+                peak: TimeValue {
+                    time: time + self.template.peak_index as Real,
+                    value: overlap / self.template.sum_of_squares,
+                },
+                end: time + (self.template.len() - 1) as Real,
+                correlation: fit.correlation,
+            },
+        ))
+    }
+}
+
+impl Detector for TemplateDetector {
+    type TracePointType = (Real, Real);
+    type EventPointType = (Real, Data);
+
+    fn signal(&mut self, time: Real, value: Real) -> Option<TemplateEvent> {
+        if self.template.len() == 0 {
+            return None;
+        }
+        self.window.push_back((time, value));
+        if self.window.len() > self.template.len() {
+            self.window.pop_front();
+        }
+        if self.window.len() < self.template.len() {
+            return None;
+        }
+
+        let overlap: Real = self
+            .window
+            .iter()
+            .zip(&self.template.values)
+            .map(|((_, value), template)| value * template)
+            .sum();
+        let amplitude = overlap / self.template.sum_of_squares;
+        let previous_overlap = self.previous_overlap.replace(overlap);
+
+        if let Some(best) = &mut self.best {
+            best.after.get_or_insert(overlap);
+        }
+
+        if amplitude >= self.threshold {
+            if !matches!(&self.best, Some(best) if best.overlap >= overlap) {
+                let norm: Real = self.window.iter().map(|(_, value)| value * value).sum();
+                self.best = Some(Fit {
+                    time: self.window[0].0,
+                    overlap,
+                    before: previous_overlap,
+                    after: None,
+                    correlation: overlap / (norm * self.template.sum_of_squares).sqrt(),
+                });
+            }
+            None
+        } else {
+            self.best.take().and_then(|best| self.generate_event(best))
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct TemplateAssembler {}
+
+impl Assembler for TemplateAssembler {
+    type DetectorType = TemplateDetector;
+
+    fn assemble_pulses(
+        &mut self,
+        source: <Self::DetectorType as Detector>::EventPointType,
+    ) -> Option<Pulse> {
+        let (time, data) = source;
+        Some(Pulse {
+            start: TimeValueOptional {
+                time: Some(time),
+                ..Default::default()
+            },
+            peak: data.get_peak().into(),
+            end: TimeValueOptional {
+                time: Some(data.get_end()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::{test_traces::PulseShape, AssembleFilter, EventFilter};
+    use assert_approx_eq::assert_approx_eq;
+
+    const RISE_TIME: Real = 2.0;
+    const DECAY_TIME: Real = 12.0;
+
+    /// Pulses of the shape of the template
+    const SHAPE: PulseShape = PulseShape {
+        rise_time: RISE_TIME,
+        decay_time: DECAY_TIME,
+    };
+
+    fn detector(threshold: Real, min_correlation: Option<Real>) -> TemplateDetector {
+        TemplateDetector::new(
+            Template::double_exponential(RISE_TIME, DECAY_TIME).unwrap(),
+            threshold,
+            min_correlation,
+        )
+    }
+
+    #[test]
+    fn parse_template() {
+        let template = Template::from_str("0, 1.0, 4.0\n2.0 1.0,,0.5\n").unwrap();
+        assert_eq!(template.values, vec![0.0, 0.25, 1.0, 0.5, 0.25, 0.125]);
+        assert_eq!(template.peak_index, 2);
+
+        assert!(Template::from_str("").is_err());
+        assert!(Template::from_str("0, -1.0").is_err());
+        assert!(Template::from_str("0, one").is_err());
+    }
+
+    #[test]
+    fn double_exponential_template() {
+        let template = Template::double_exponential(RISE_TIME, DECAY_TIME).unwrap();
+        assert_eq!(template.len(), 73);
+        assert_eq!(template.peak_index, 4);
+        assert_eq!(template.values[4], 1.0);
+        assert!(*template.values.last().unwrap() < 0.01);
+    }
+
+    #[test]
+    fn pulse_below_threshold() {
+        let mut iter = SHAPE
+            .trace_of_heights(200, &[(20.3, 40.0)])
+            .into_iter()
+            .events(detector(50.0, None));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn fitted_time_and_amplitude() {
+        for (start, height) in [(20.0, 100.0), (20.3, 250.0), (20.5, 60.0), (20.8, 1000.0)] {
+            let events: Vec<_> = SHAPE
+                .trace_of_heights(200, &[(start, height)])
+                .into_iter()
+                .events(detector(10.0, Some(0.99)))
+                .collect();
+            assert_eq!(events.len(), 1);
+
+            let (time, data) = &events[0];
+            assert_approx_eq!(*time, start, 0.1);
+            assert_approx_eq!(data.get_peak().value, height, 0.01 * height);
+            assert!(data.correlation > 0.99);
+        }
+    }
+
+    #[test]
+    fn consecutive_pulses() {
+        let pulses: Vec<_> = SHAPE
+            .trace_of_heights(400, &[(20.0, 100.0), (150.0, 800.0), (300.0, 30.0)])
+            .into_iter()
+            .events(detector(10.0, None))
+            .assemble(TemplateAssembler::default())
+            .collect();
+
+        assert_eq!(pulses.len(), 3);
+        for (pulse, (start, height)) in
+            pulses
+                .iter()
+                .zip([(20.0, 100.0), (150.0, 800.0), (300.0, 30.0)])
+        {
+            assert_approx_eq!(pulse.start.time.unwrap(), start, 0.1);
+            assert_approx_eq!(pulse.peak.value.unwrap(), height, 0.01 * height);
+        }
+    }
+
+    #[test]
+    fn poorly_correlated_pulse_discarded() {
+        // A square pulse has a very different shape to the template
+        let data: Vec<_> = (0..200)
+            .map(|i| (i as Real, if (50..90).contains(&i) { 100.0 } else { 0.0 }))
+            .collect();
+
+        let events = data.iter().copied().events(detector(10.0, None)).count();
+        assert_eq!(events, 1);
+
+        let events = data.into_iter().events(detector(10.0, Some(0.95))).count();
+        assert_eq!(events, 0);
+    }
+}
@@ -43,8 +43,12 @@ impl ThresholdClass for LowerThreshold {
 #[derive(Default, Clone)]
 pub(crate) struct ThresholdDetector<Class: ThresholdClass> {
     trigger: ThresholdDuration,
+    interpolate: bool,
     time_of_last_return: Option<Real>,
     time_crossed: Option<Real>,
+    // Time at which the threshold was crossed, interpolated between samples
+    interpolated_time_crossed: Option<Real>,
+    previous: Option<(Real, Real)>,
     phantom: PhantomData<Class>,
 }
 
@@ -55,6 +59,26 @@ impl<Class: ThresholdClass> ThresholdDetector<Class> {
             ..Default::default()
         }
     }
+
+    /// Events are given the time at which the signal crossed the threshold,
+    /// linearly interpolated between the samples either side of the crossing,
+    /// rather than the time of the first sample beyond the threshold.
+    pub(crate) fn with_interpolation(self) -> Self {
+        Self {
+            interpolate: true,
+            ..self
+        }
+    }
+
+    fn interpolate_crossing(&self, time: Real, value: Real) -> Option<Real> {
+        let (previous_time, previous_value) = self.previous?;
+        // The crossing may have been delayed by the cool-off, in which case it was not between these samples
+        (self.interpolate && !Class::test(previous_value, self.trigger.threshold)).then(|| {
+            previous_time
+                + (time - previous_time) * (self.trigger.threshold - previous_value)
+                    / (value - previous_value)
+        })
+    }
 }
 
 pub(crate) type ThresholdEvent = (Real, Data);
@@ -64,12 +88,23 @@ impl<Class: ThresholdClass> Detector for ThresholdDetector<Class> {
     type EventPointType = (Real, Data);
 
     fn signal(&mut self, time: Real, value: Real) -> Option<ThresholdEvent> {
+        let result = self.test_crossing(time, value);
+        self.previous = Some((time, value));
+        result
+    }
+}
+
+impl<Class: ThresholdClass> ThresholdDetector<Class> {
+    fn test_crossing(&mut self, time: Real, value: Real) -> Option<ThresholdEvent> {
         match self.time_crossed {
             Some(time_crossed) => {
                 // If we are already over the threshold
                 let result = if time - time_crossed == self.trigger.duration as Real {
                     // If the current value is below the threshold
-                    Some((time_crossed, Data {}))
+                    Some((
+                        self.interpolated_time_crossed.unwrap_or(time_crossed),
+                        Data {},
+                    ))
                 } else {
                     None
                 };
@@ -96,6 +131,9 @@ impl<Class: ThresholdClass> Detector for ThresholdDetector<Class> {
                         }
                         None => self.time_crossed = Some(time),
                     }
+                    if self.time_crossed.is_some() {
+                        self.interpolated_time_crossed = self.interpolate_crossing(time, value);
+                    }
                 }
                 None
             }
@@ -257,4 +295,45 @@ mod tests {
         assert_eq!(iter.next(), Some((8.0, Data {})));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_interpolation() {
+        let data = [4, 3, 2, 5, 6, 1, 5, 7, 2, 4];
+        let detector = ThresholdDetector::<UpperThreshold>::new(&ThresholdDuration {
+            threshold: 2.5,
+            cool_off: 0,
+            duration: 2,
+        })
+        .with_interpolation();
+        let mut iter = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v as Real))
+            .events(detector);
+        // No sample before the first, so the time cannot be interpolated
+        assert_eq!(iter.next(), Some((0.0, Data {})));
+        assert_eq!(iter.next(), Some((2.0 + 0.5 / 3.0, Data {})));
+        assert_eq!(iter.next(), Some((5.0 + 1.5 / 4.0, Data {})));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_interpolation_after_cool_off() {
+        // The second crossing is delayed by the cool-off until sample 5, which is not interpolated
+        let data = [1, 5, 1, 5, 5, 5, 5, 1];
+        let detector = ThresholdDetector::<UpperThreshold>::new(&ThresholdDuration {
+            threshold: 2.0,
+            cool_off: 3,
+            duration: 1,
+        })
+        .with_interpolation();
+        let mut iter = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v as Real))
+            .events(detector);
+        assert_eq!(iter.next(), Some((0.25, Data {})));
+        assert_eq!(iter.next(), Some((5.0, Data {})));
+        assert_eq!(iter.next(), None);
+    }
 }
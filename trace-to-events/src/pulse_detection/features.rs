@@ -0,0 +1,84 @@
+use super::{Pulse, Real};
+
+/// Properties of the shape of a pulse, with times in samples.
+/// Properties which cannot be found from the parts of the pulse found by the detector are `None`.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PulseFeatures {
+    pub(crate) peak_time: Option<Real>,
+    /// Time from the start of the pulse to its peak
+    pub(crate) rise_time: Option<Real>,
+    /// Time from the start of the pulse to its end
+    pub(crate) width: Option<Real>,
+    /// Sum of the signal from the start of the pulse to its end
+    pub(crate) area: Option<Real>,
+}
+
+impl PulseFeatures {
+    /// `signal` is the signal in which the pulse was found, in order of time.
+    pub(crate) fn new(pulse: &Pulse, signal: &[(Real, Real)]) -> Self {
+        let start = pulse.start.time;
+        let peak_time = pulse.peak.time;
+        let end = pulse.end.time;
+
+        let area = Option::zip(start, end).map(|(start, end)| {
+            let first = signal.partition_point(|(time, _)| *time < start);
+            let last = signal.partition_point(|(time, _)| *time <= end);
+            signal[first..last.max(first)]
+                .iter()
+                .map(|(_, value)| value)
+                .sum()
+        });
+
+        Self {
+            peak_time,
+            rise_time: Option::zip(start, peak_time).map(|(start, peak)| peak - start),
+            width: Option::zip(start, end).map(|(start, end)| end - start),
+            area,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::pulse::TimeValueOptional;
+
+    fn time(time: Real) -> TimeValueOptional<Real> {
+        TimeValueOptional {
+            time: Some(time),
+            value: None,
+        }
+    }
+
+    #[test]
+    fn complete_pulse() {
+        let signal: Vec<_> = [0.0, 1.0, 4.0, 6.0, 5.0, 3.0, 1.0, 0.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as Real, v))
+            .collect();
+        let pulse = Pulse {
+            start: time(0.5),
+            peak: time(3.2),
+            end: time(6.0),
+            ..Default::default()
+        };
+
+        let features = PulseFeatures::new(&pulse, &signal);
+        assert_eq!(features.peak_time, Some(3.2));
+        assert_eq!(features.rise_time, Some(2.7));
+        assert_eq!(features.width, Some(5.5));
+        assert_eq!(features.area, Some(20.0));
+    }
+
+    #[test]
+    fn start_only() {
+        let pulse = Pulse {
+            start: time(2.0),
+            ..Default::default()
+        };
+
+        let features = PulseFeatures::new(&pulse, &[(0.0, 1.0), (1.0, 2.0)]);
+        assert_eq!(features, PulseFeatures::default());
+    }
+}
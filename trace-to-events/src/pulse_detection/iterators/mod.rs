@@ -1,7 +1,5 @@
-use super::{Assembler, Detector, Pulse, Temporal, TracePoint};
+use super::{Assembler, Detector, Pulse, TracePoint};
 
 pub(crate) mod event;
-pub(crate) mod save_to_file;
 
 pub(crate) use event::{AssembleFilter, EventFilter};
-pub(crate) use save_to_file::SaveToFileFilter;
@@ -18,12 +18,19 @@ pub(crate) mod datatype;
 pub(crate) mod pulse;
 
 pub(crate) mod detectors;
+pub(crate) mod features;
 pub(crate) mod iterators;
+pub(crate) mod pile_up;
+#[cfg(test)]
+pub(crate) mod test_traces;
 pub(crate) mod window;
 
 pub(crate) use datatype::{EventData, EventPoint, RealArray, Stats, Temporal, TracePoint};
-pub(crate) use detectors::{advanced_muon_detector, threshold_detector, Assembler, Detector};
-pub(crate) use iterators::{AssembleFilter, EventFilter, SaveToFileFilter};
+pub(crate) use detectors::{
+    advanced_muon_detector, constant_fraction_detector, template_detector, threshold_detector,
+    Assembler, Detector,
+};
+pub(crate) use iterators::{AssembleFilter, EventFilter};
 #[cfg(test)]
 pub(crate) use window::WindowFilter;
 
@@ -0,0 +1,209 @@
+use super::{
+    pulse::{TimeValue, TimeValueOptional},
+    Pulse, Real, RealArray,
+};
+
+/// Splits pulses containing more than one peak, as happens when a second muon
+/// arrives before the pulse of the first has ended.
+///
+/// Two maxima of the signal are taken to be separate peaks if the signal falls by at least
+/// `prominence` below each of them between the two, otherwise they are the same peak.
+#[derive(Default, Clone)]
+pub(crate) struct PileUpSplitter {
+    prominence: Real,
+}
+
+impl PileUpSplitter {
+    pub(crate) fn new(prominence: Real) -> Self {
+        Self { prominence }
+    }
+
+    /// Returns the peaks of the samples, each with the lowest point between it and the next peak.
+    fn find_peaks(&self, samples: &[(Real, Real)]) -> Vec<(TimeValue<Real>, TimeValue<Real>)> {
+        let mut peaks = Vec::new();
+        let Some(&(time, value)) = samples.first() else {
+            return peaks;
+        };
+        let mut peak = TimeValue { time, value };
+        let mut valley = peak.clone();
+
+        for &(time, value) in &samples[1..] {
+            let deep_valley = peak.value - valley.value >= self.prominence;
+            if value > peak.value && !deep_valley {
+                // Still the same peak
+                peak = TimeValue { time, value };
+                valley = peak.clone();
+            } else if value < valley.value {
+                valley = TimeValue { time, value };
+            } else if deep_valley && value - valley.value >= self.prominence {
+                peaks.push((peak, valley));
+                peak = TimeValue { time, value };
+                valley = peak.clone();
+            }
+        }
+        peaks.push((peak, valley));
+        peaks
+    }
+
+    /// Returns the pulses into which the pulse is split, or just the pulse if it has a single peak.
+    /// `samples` is the signal in which the pulse was found, in order of time.
+    ///
+    /// The peaks, steepest rises and sharpest falls of split pulses are those of the samples.
+    pub(crate) fn split(&self, pulse: Pulse, samples: &[(Real, Real)]) -> Vec<Pulse> {
+        let (Some(start), Some(end)) = (pulse.start.time, pulse.end.time) else {
+            return vec![pulse];
+        };
+        let first = samples.partition_point(|(time, _)| *time < start);
+        let last = samples.partition_point(|(time, _)| *time <= end);
+        let samples = &samples[first..last];
+
+        let peaks = self.find_peaks(samples);
+        if peaks.len() < 2 {
+            return vec![pulse];
+        }
+
+        let mut pulse_start: TimeValueOptional<Real> = pulse.start.clone();
+        peaks
+            .iter()
+            .enumerate()
+            .map(|(i, (peak, valley))| {
+                let pulse_end = if i + 1 == peaks.len() {
+                    pulse.end.clone()
+                } else {
+                    valley.clone().into()
+                };
+                let split = Pulse {
+                    start: pulse_start.clone(),
+                    end: pulse_end.clone(),
+                    peak: peak.clone().into(),
+                    steepest_rise: superlative_difference(
+                        samples,
+                        pulse_start.time.unwrap_or(start),
+                        peak.time,
+                        |diff, superlative| diff > superlative,
+                    ),
+                    sharpest_fall: superlative_difference(
+                        samples,
+                        peak.time,
+                        pulse_end.time.unwrap_or(end),
+                        |diff, superlative| diff < superlative,
+                    ),
+                };
+                pulse_start = pulse_end;
+                split
+            })
+            .collect()
+    }
+}
+
+/// Returns the sample between the given times whose difference from the previous sample
+/// supersedes that of all others.
+fn superlative_difference(
+    samples: &[(Real, Real)],
+    from: Real,
+    to: Real,
+    supersedes: impl Fn(Real, Real) -> bool,
+) -> TimeValueOptional<RealArray<2>> {
+    samples
+        .windows(2)
+        .filter(|pair| pair[1].0 > from && pair[1].0 <= to)
+        .map(|pair| TimeValue {
+            time: pair[1].0,
+            value: RealArray::new([pair[1].1, pair[1].1 - pair[0].1]),
+        })
+        .reduce(|superlative, sample| {
+            if supersedes(sample.value[1], superlative.value[1]) {
+                sample
+            } else {
+                superlative
+            }
+        })
+        .map(Into::into)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::{
+        advanced_muon_detector::{AdvancedMuonDetector, BasicMuonAssembler},
+        test_traces::PulseShape,
+        window::FiniteDifferences,
+        AssembleFilter, EventFilter, WindowFilter,
+    };
+
+    const SHAPE: PulseShape = PulseShape {
+        rise_time: 2.0,
+        decay_time: 20.0,
+    };
+
+    fn detect(samples: &[(Real, Real)]) -> Vec<Pulse> {
+        samples
+            .iter()
+            .copied()
+            .window(FiniteDifferences::<2>::new())
+            .events(AdvancedMuonDetector::new(1.0, -1.0, -0.01, 3.0))
+            .assemble(BasicMuonAssembler::default())
+            .collect()
+    }
+
+    #[test]
+    fn single_peak_not_split() {
+        let samples = SHAPE.trace(150, &[(20.0, 100.0)]);
+        let pulses = detect(&samples);
+        assert_eq!(pulses.len(), 1);
+
+        let split = PileUpSplitter::new(2.0).split(pulses[0].clone(), &samples);
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].steepest_rise.time, pulses[0].steepest_rise.time);
+    }
+
+    #[test]
+    fn overlapping_pulses_split() {
+        // The second pulse arrives just after the first peaks, so the signal falls
+        // for less than the duration the detector needs to find the first peak
+        let samples = SHAPE.trace(200, &[(20.0, 100.0), (28.0, 100.0)]);
+        let pulses = detect(&samples);
+        assert_eq!(pulses.len(), 1);
+
+        let split = PileUpSplitter::new(2.0).split(pulses[0].clone(), &samples);
+        assert_eq!(split.len(), 2);
+
+        // The onset of the first pulse is only found after it has started to rise,
+        // but the second pulse rises most steeply just after it starts
+        let rise = |pulse: &Pulse| pulse.steepest_rise.time.unwrap();
+        assert!(rise(&split[0]) <= split[0].peak.time.unwrap());
+        assert!(rise(&split[1]) > 28.0 && rise(&split[1]) < 32.0);
+
+        // The first pulse ends where the second starts
+        assert_eq!(split[0].end.time, split[1].start.time);
+        assert_eq!(split[0].start.time, pulses[0].start.time);
+        assert_eq!(split[1].end.time, pulses[0].end.time);
+        assert!(split[0].peak.time.unwrap() < split[0].end.time.unwrap());
+        assert!(split[1].sharpest_fall.time.unwrap() > split[1].peak.time.unwrap());
+    }
+
+    #[test]
+    fn shallow_dip_not_split() {
+        // The signal only falls by 4.5 between the peaks
+        let samples = SHAPE.trace(200, &[(20.0, 100.0), (28.0, 100.0)]);
+        let pulses = detect(&samples);
+
+        let split = PileUpSplitter::new(5.0).split(pulses[0].clone(), &samples);
+        assert_eq!(split.len(), 1);
+    }
+
+    #[test]
+    fn three_pulses_split() {
+        let samples = SHAPE.trace(300, &[(20.0, 100.0), (27.0, 100.0), (34.0, 100.0)]);
+        let pulses = detect(&samples);
+        assert_eq!(pulses.len(), 1);
+
+        let split = PileUpSplitter::new(2.0).split(pulses[0].clone(), &samples);
+        assert_eq!(split.len(), 3);
+        for (pulse, start) in split.iter().skip(1).zip([27.0, 34.0]) {
+            let rise = pulse.steepest_rise.time.unwrap();
+            assert!(rise > start && rise < start + 4.0);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Synthetic traces used by the tests of the detectors.
+
+use super::Real;
+
+/// Shape of a pulse which rises and decays exponentially, with the time constants in samples.
+pub(crate) struct PulseShape {
+    pub(crate) rise_time: Real,
+    pub(crate) decay_time: Real,
+}
+
+impl PulseShape {
+    /// Value of a pulse of unit amplitude, `t` samples after it starts.
+    fn value(&self, t: Real) -> Real {
+        if t < 0.0 {
+            0.0
+        } else {
+            (-t / self.decay_time).exp() - (-t / self.rise_time).exp()
+        }
+    }
+
+    /// Height of a pulse of unit amplitude at its peak.
+    pub(crate) fn peak(&self) -> Real {
+        let peak_time = (self.decay_time / self.rise_time).ln() * self.rise_time * self.decay_time
+            / (self.decay_time - self.rise_time);
+        self.value(peak_time)
+    }
+
+    /// Samples of the sum of pulses, each given by its start and amplitude.
+    pub(crate) fn trace(&self, length: usize, pulses: &[(Real, Real)]) -> Vec<(Real, Real)> {
+        (0..length)
+            .map(|i| {
+                let time = i as Real;
+                let value = pulses
+                    .iter()
+                    .map(|&(start, amplitude)| amplitude * self.value(time - start))
+                    .sum();
+                (time, value)
+            })
+            .collect()
+    }
+
+    /// Samples of the sum of pulses, each given by its start and the height of its peak.
+    pub(crate) fn trace_of_heights(
+        &self,
+        length: usize,
+        pulses: &[(Real, Real)],
+    ) -> Vec<(Real, Real)> {
+        let peak = self.peak();
+        let pulses: Vec<_> = pulses
+            .iter()
+            .map(|&(start, height)| (start, height / peak))
+            .collect();
+        self.trace(length, &pulses)
+    }
+}
@@ -1,4 +1,58 @@
 use super::{Real, Window};
+use std::collections::VecDeque;
+
+/// The median of the most recent samples, of which there are at most `size`.
+#[derive(Default, Clone)]
+struct RollingMedian {
+    size: usize,
+    window: VecDeque<Real>,
+    sorted: Vec<Real>,
+}
+
+impl RollingMedian {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            window: VecDeque::with_capacity(size + 1),
+            sorted: Vec::with_capacity(size + 1),
+        }
+    }
+
+    fn push(&mut self, value: Real) {
+        self.window.push_back(value);
+        let index = self.sorted.partition_point(|x| *x < value);
+        self.sorted.insert(index, value);
+
+        if self.window.len() > self.size {
+            if let Some(oldest) = self.window.pop_front() {
+                let index = self.sorted.partition_point(|x| *x < oldest);
+                self.sorted.remove(index);
+            }
+        }
+    }
+
+    fn median(&self) -> Option<Real> {
+        let middle = self.sorted.len() / 2;
+        match self.sorted.len() {
+            0 => None,
+            len if len % 2 == 1 => Some(self.sorted[middle]),
+            _ => Some(0.5 * (self.sorted[middle - 1] + self.sorted[middle])),
+        }
+    }
+}
+
+/// How the baseline is updated after the warm up.
+#[derive(Default, Clone)]
+enum Tracking {
+    /// The baseline found during the warm up is used for the rest of the trace.
+    #[default]
+    Fixed,
+    /// The baseline is an exponential moving average of the samples within `gate` of it,
+    /// so is not updated while the signal is part of a pulse.
+    Gated { gate: Real, smoothing_factor: Real },
+    /// The baseline is the median of the most recent samples, including those of the warm up.
+    RollingMedian(RollingMedian),
+}
 
 #[derive(Default, Clone)]
 pub(crate) struct Baseline {
@@ -7,6 +61,7 @@ pub(crate) struct Baseline {
     smoothing_factor: Real,
     warm_up: usize,
     time: usize,
+    tracking: Tracking,
 }
 
 impl Baseline {
@@ -17,6 +72,27 @@ impl Baseline {
             ..Default::default()
         }
     }
+
+    /// After the warm up the baseline follows slow changes of the signal, samples which differ
+    /// from the baseline by more than `gate` being taken to be part of a pulse and ignored.
+    pub(crate) fn with_gated_tracking(self, gate: Real, smoothing_factor: Real) -> Self {
+        Self {
+            tracking: Tracking::Gated {
+                gate,
+                smoothing_factor,
+            },
+            ..self
+        }
+    }
+
+    /// The baseline of each sample is the median of the `size` samples before it.
+    /// Pulses do not affect the median as long as they make up less than half of these samples.
+    pub(crate) fn with_rolling_median(self, size: usize) -> Self {
+        Self {
+            tracking: Tracking::RollingMedian(RollingMedian::new(size)),
+            ..self
+        }
+    }
 }
 
 impl Window for Baseline {
@@ -25,13 +101,33 @@ impl Window for Baseline {
     type OutputType = Real;
 
     fn push(&mut self, value: Real) -> bool {
-        self.value = value - self.baseline;
-        if self.time < self.warm_up {
-            self.baseline = if self.time == 0 {
-                value
-            } else {
-                value * self.smoothing_factor + self.baseline * (1. - self.smoothing_factor)
-            };
+        let warming_up = self.time < self.warm_up;
+        match &mut self.tracking {
+            Tracking::RollingMedian(median) => {
+                self.value = value - median.median().unwrap_or(value);
+                median.push(value);
+            }
+            Tracking::Gated {
+                gate,
+                smoothing_factor,
+            } if !warming_up => {
+                self.value = value - self.baseline;
+                if self.value.abs() <= *gate {
+                    self.baseline += *smoothing_factor * self.value;
+                }
+            }
+            _ => {
+                self.value = value - self.baseline;
+                if warming_up {
+                    self.baseline = if self.time == 0 {
+                        value
+                    } else {
+                        value * self.smoothing_factor + self.baseline * (1. - self.smoothing_factor)
+                    };
+                }
+            }
+        }
+        if warming_up {
             self.time += 1;
             false
         } else {
@@ -122,4 +218,84 @@ mod tests {
         assert_approx_eq!(output[2], 1.04, 1e-8);
         assert_approx_eq!(output[3], 2.04, 1e-8);
     }
+
+    const PULSES: [usize; 3] = [200, 500, 900];
+
+    /// A baseline drifting from 100 to 120, with pulses of height 50 at the given samples.
+    fn drifting_trace() -> impl Iterator<Item = (Real, Real)> {
+        (0..1000).map(|i| {
+            let pulses: Real = PULSES
+                .iter()
+                .filter(|&&start| i >= start)
+                .map(|&start| 50.0 * (-((i - start) as Real) / 5.0).exp())
+                .sum();
+            (i as Real, 100.0 + 0.02 * i as Real + pulses)
+        })
+    }
+
+    /// The heights of the pulses, and the largest deviation from zero between pulses.
+    fn heights_and_residual(baseline: Baseline) -> (Vec<Real>, Real) {
+        let output: Vec<_> = drifting_trace()
+            .window(baseline)
+            .map(|(time, value)| (time as usize + 50, value))
+            .collect();
+        let heights = PULSES
+            .iter()
+            .map(|&start| {
+                output
+                    .iter()
+                    .filter(|(i, _)| (start..start + 5).contains(i))
+                    .map(|(_, value)| *value)
+                    .fold(Real::MIN, Real::max)
+            })
+            .collect();
+        let residual = output
+            .iter()
+            .filter(|(i, _)| PULSES.iter().all(|start| !(*start..start + 30).contains(i)))
+            .map(|(_, value)| value.abs())
+            .fold(0.0, Real::max);
+        (heights, residual)
+    }
+
+    #[test]
+    fn fixed_baseline_drifts() {
+        let (heights, residual) = heights_and_residual(Baseline::new(50, 0.1));
+        assert!(heights[2] - 50.0 > 15.0);
+        assert!(residual > 15.0);
+    }
+
+    #[test]
+    fn gated_baseline_tracks_drift() {
+        let (heights, residual) =
+            heights_and_residual(Baseline::new(50, 0.1).with_gated_tracking(2.0, 0.1));
+        for height in heights {
+            assert_approx_eq!(height, 50.0, 1.5);
+        }
+        assert!(residual < 1.5);
+    }
+
+    #[test]
+    fn rolling_median_baseline_tracks_drift() {
+        let (heights, residual) =
+            heights_and_residual(Baseline::new(50, 0.1).with_rolling_median(101));
+        for height in heights {
+            assert_approx_eq!(height, 50.0, 1.5);
+        }
+        assert!(residual < 1.5);
+    }
+
+    #[test]
+    fn gated_baseline_ignores_pulses() {
+        let input = (0..100).map(|i| {
+            let value = if (40..60).contains(&i) { 500.0 } else { 10.0 };
+            (i as Real, value)
+        });
+        let output: Vec<_> = input
+            .window(Baseline::new(10, 0.1).with_gated_tracking(2.0, 0.5))
+            .map(|(_, value)| value)
+            .collect();
+
+        assert_eq!(output[40], 490.0);
+        assert!(output[50..].iter().all(|value| *value == 0.0));
+    }
 }
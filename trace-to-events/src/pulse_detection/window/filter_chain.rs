@@ -0,0 +1,125 @@
+use super::{LowPass, PoleZeroCorrection, Real, SavitzkyGolay, TrapezoidalFilter, Window};
+
+/// One of the filters which can be chosen at runtime to be applied to a signal.
+#[derive(Clone)]
+pub(crate) enum Filter {
+    Trapezoidal(TrapezoidalFilter),
+    PoleZero(PoleZeroCorrection),
+    SavitzkyGolay(SavitzkyGolay),
+    LowPass(LowPass),
+}
+
+impl Window for Filter {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        match self {
+            Filter::Trapezoidal(filter) => filter.push(value),
+            Filter::PoleZero(filter) => filter.push(value),
+            Filter::SavitzkyGolay(filter) => filter.push(value),
+            Filter::LowPass(filter) => filter.push(value),
+        }
+    }
+
+    fn output(&self) -> Option<Real> {
+        match self {
+            Filter::Trapezoidal(filter) => filter.output(),
+            Filter::PoleZero(filter) => filter.output(),
+            Filter::SavitzkyGolay(filter) => filter.output(),
+            Filter::LowPass(filter) => filter.output(),
+        }
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        match self {
+            Filter::Trapezoidal(filter) => filter.apply_time_shift(time),
+            Filter::PoleZero(filter) => filter.apply_time_shift(time),
+            Filter::SavitzkyGolay(filter) => filter.apply_time_shift(time),
+            Filter::LowPass(filter) => filter.apply_time_shift(time),
+        }
+    }
+}
+
+/// Applies each of a sequence of filters in turn, so that the filters applied to a signal can be
+/// given in the configuration. A chain of no filters leaves the signal unchanged.
+#[derive(Default, Clone)]
+pub(crate) struct FilterChain {
+    filters: Vec<Filter>,
+    value: Real,
+}
+
+impl FilterChain {
+    pub(crate) fn new(filters: Vec<Filter>) -> Self {
+        Self {
+            filters,
+            value: 0.0,
+        }
+    }
+}
+
+impl Window for FilterChain {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        let mut value = value;
+        for filter in &mut self.filters {
+            match filter.push(value).then(|| filter.output()).flatten() {
+                Some(output) => value = output,
+                None => return false,
+            }
+        }
+        self.value = value;
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        Some(self.value)
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        self.filters
+            .iter()
+            .fold(time, |time, filter| filter.apply_time_shift(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::window::WindowFilter;
+
+    fn trace() -> impl Iterator<Item = (Real, Real)> + Clone {
+        (0..100).map(|i| (i as Real, (i as Real * 0.3).sin() * 10.0 + i as Real))
+    }
+
+    #[test]
+    fn empty_chain() {
+        let output: Vec<_> = trace().window(FilterChain::default()).collect();
+        assert_eq!(output, trace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn chain_same_as_windows() {
+        let chained: Vec<_> = trace()
+            .window(FilterChain::new(vec![
+                Filter::PoleZero(PoleZeroCorrection::new(10.0)),
+                Filter::Trapezoidal(TrapezoidalFilter::new(4, 2)),
+                Filter::SavitzkyGolay(SavitzkyGolay::new(5, 2)),
+                Filter::LowPass(LowPass::new(0.2)),
+            ]))
+            .collect();
+        let windowed: Vec<_> = trace()
+            .window(PoleZeroCorrection::new(10.0))
+            .window(TrapezoidalFilter::new(4, 2))
+            .window(SavitzkyGolay::new(5, 2))
+            .window(LowPass::new(0.2))
+            .collect();
+
+        assert_eq!(chained.len(), 100 - 9 - 4);
+        assert_eq!(chained, windowed);
+    }
+}
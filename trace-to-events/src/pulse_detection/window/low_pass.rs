@@ -0,0 +1,111 @@
+use super::{Real, Window};
+use std::f64::consts::{PI, SQRT_2};
+
+/// Second order Butterworth low-pass filter, with `cutoff` the frequency as a fraction of the
+/// sample rate, which must be between 0 and 0.5.
+///
+/// The filter starts as if the signal had been at its first value forever,
+/// so that a nonzero baseline does not cause a transient.
+/// The filter delays slowly varying signals by around `0.225 / cutoff` samples,
+/// which is not removed from the times of the output.
+#[derive(Default, Clone)]
+pub(crate) struct LowPass {
+    numerator: [Real; 3],
+    denominator: [Real; 2],
+    inputs: Option<[Real; 2]>,
+    outputs: [Real; 2],
+}
+
+impl LowPass {
+    pub(crate) fn new(cutoff: Real) -> Self {
+        if !(cutoff > 0.0 && cutoff < 0.5) {
+            panic!("Cutoff must be between 0 and 0.5");
+        }
+        // Coefficients found by the bilinear transform
+        let k = (PI * cutoff).tan();
+        let norm = 1.0 / (1.0 + SQRT_2 * k + k * k);
+        let b0 = k * k * norm;
+        Self {
+            numerator: [b0, 2.0 * b0, b0],
+            denominator: [
+                2.0 * (k * k - 1.0) * norm,
+                (1.0 - SQRT_2 * k + k * k) * norm,
+            ],
+            ..Default::default()
+        }
+    }
+}
+
+impl Window for LowPass {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        let [x1, x2] = match self.inputs {
+            Some(inputs) => inputs,
+            None => {
+                self.outputs = [value, value];
+                [value, value]
+            }
+        };
+        let [y1, y2] = self.outputs;
+        let [b0, b1, b2] = self.numerator;
+        let [a1, a2] = self.denominator;
+
+        let output = b0 * value + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+        self.inputs = Some([value, x1]);
+        self.outputs = [output, y1];
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        Some(self.outputs[0])
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::window::WindowFilter;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn amplitude(cutoff: Real, period: Real) -> Real {
+        (0..1000)
+            .map(|i| (i as Real, (2.0 * PI * i as Real / period).sin()))
+            .window(LowPass::new(cutoff))
+            .skip(500)
+            .map(|(_, value)| value.abs())
+            .fold(0.0, Real::max)
+    }
+
+    #[test]
+    #[should_panic]
+    fn cutoff_too_high() {
+        LowPass::new(0.5);
+    }
+
+    #[test]
+    fn constant_unchanged() {
+        let output: Vec<_> = (0..50)
+            .map(|i| (i as Real, 25.0))
+            .window(LowPass::new(0.1))
+            .collect();
+        assert_eq!(output.len(), 50);
+        for (_, value) in output {
+            assert_approx_eq!(value, 25.0, 1e-9);
+        }
+    }
+
+    #[test]
+    fn frequency_response() {
+        // Pass band, cutoff and stop band
+        assert_approx_eq!(amplitude(0.1, 100.0), 1.0, 0.01);
+        assert_approx_eq!(amplitude(0.1, 10.0), 1.0 / SQRT_2, 0.01);
+        assert!(amplitude(0.1, 2.5) < 0.1);
+    }
+}
@@ -1,11 +1,21 @@
 pub(crate) mod baseline;
+pub(crate) mod filter_chain;
 pub(crate) mod finite_differences;
+pub(crate) mod low_pass;
+pub(crate) mod pole_zero;
+pub(crate) mod savitzky_golay;
 pub(crate) mod smoothing_window;
+pub(crate) mod trapezoidal;
 
 use super::{Real, RealArray, Stats, Temporal, TracePoint};
 pub(crate) use baseline::Baseline;
+pub(crate) use filter_chain::{Filter, FilterChain};
 pub(crate) use finite_differences::FiniteDifferences;
+pub(crate) use low_pass::LowPass;
+pub(crate) use pole_zero::PoleZeroCorrection;
+pub(crate) use savitzky_golay::SavitzkyGolay;
 pub(crate) use smoothing_window::SmoothingWindow;
+pub(crate) use trapezoidal::TrapezoidalFilter;
 
 pub(crate) trait Window: Clone {
     type TimeType: Temporal;
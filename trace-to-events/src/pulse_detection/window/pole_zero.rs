@@ -0,0 +1,87 @@
+use super::{Real, Window};
+
+/// Removes the exponential decay of pulses, with time constant `decay_time` in samples,
+/// so that each pulse becomes a step of the height of the pulse.
+///
+/// The signal should have its baseline removed, as any remaining offset accumulates.
+/// This is intended to be followed by a [TrapezoidalFilter](super::TrapezoidalFilter),
+/// which turns each step back into a pulse.
+#[derive(Default, Clone)]
+pub(crate) struct PoleZeroCorrection {
+    decay_factor: Real,
+    previous: Option<Real>,
+    value: Real,
+}
+
+impl PoleZeroCorrection {
+    pub(crate) fn new(decay_time: Real) -> Self {
+        Self {
+            decay_factor: (-1.0 / decay_time).exp(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Window for PoleZeroCorrection {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        if let Some(previous) = self.previous.replace(value) {
+            self.value += value - self.decay_factor * previous;
+        } else {
+            self.value = value;
+        }
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        Some(self.value)
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::window::WindowFilter;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn decay_becomes_step() {
+        let output: Vec<_> = (0..100)
+            .map(|i| {
+                let value = if i < 10 {
+                    0.0
+                } else {
+                    50.0 * (-((i - 10) as Real) / 20.0).exp()
+                };
+                (i as Real, value)
+            })
+            .window(PoleZeroCorrection::new(20.0))
+            .collect();
+
+        assert_eq!(output.len(), 100);
+        assert_eq!(output[9], (9.0, 0.0));
+        for (_, value) in &output[10..] {
+            assert_approx_eq!(value, 50.0, 1e-9);
+        }
+    }
+
+    #[test]
+    fn wrong_decay_time() {
+        // Too short a decay time over corrects the decay, so the step keeps rising
+        let output: Vec<_> = (0..100)
+            .map(|i| (i as Real, 50.0 * (-(i as Real) / 20.0).exp()))
+            .window(PoleZeroCorrection::new(10.0))
+            .map(|(_, value)| value)
+            .collect();
+
+        assert_eq!(output[0], 50.0);
+        assert!(output[99] > output[50] && output[50] > output[1]);
+    }
+}
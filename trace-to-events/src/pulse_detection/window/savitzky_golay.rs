@@ -0,0 +1,177 @@
+use super::{Real, Window};
+use std::collections::VecDeque;
+
+/// Smooths the signal by fitting a polynomial of degree `order` to each window of `size` samples
+/// by least squares, and outputting its value at the middle of the window.
+///
+/// Unlike a moving average, this preserves the heights and widths of peaks which are narrow
+/// compared to the window.
+#[derive(Default, Clone)]
+pub(crate) struct SavitzkyGolay {
+    coefficients: Vec<Real>,
+    window: VecDeque<Real>,
+    value: Real,
+}
+
+impl SavitzkyGolay {
+    pub(crate) fn new(size: usize, order: usize) -> Self {
+        if size & 1 == 0 {
+            panic!("Size must be odd");
+        }
+        if order >= size {
+            panic!("Order must be less than size");
+        }
+        Self {
+            coefficients: Self::coefficients(size, order),
+            window: VecDeque::with_capacity(size),
+            value: 0.0,
+        }
+    }
+
+    /// The weights of the samples in the value of the fitted polynomial at the middle of the window,
+    /// found by solving the normal equations of the fit for the constant term.
+    fn coefficients(size: usize, order: usize) -> Vec<Real> {
+        let half = (size / 2) as i32;
+        let offsets: Vec<Real> = (-half..=half).map(Real::from).collect();
+        let terms = order + 1;
+
+        // Augmented matrix of the normal equations, with the first unit vector as right hand side
+        let mut matrix: Vec<Vec<Real>> = (0..terms)
+            .map(|row| {
+                let mut equation: Vec<Real> = (0..terms)
+                    .map(|column| offsets.iter().map(|x| x.powi((row + column) as i32)).sum())
+                    .collect();
+                equation.push(if row == 0 { 1.0 } else { 0.0 });
+                equation
+            })
+            .collect();
+
+        // Gaussian elimination with partial pivoting
+        for pivot in 0..terms {
+            let best = (pivot..terms)
+                .max_by(|&a, &b| matrix[a][pivot].abs().total_cmp(&matrix[b][pivot].abs()))
+                .unwrap_or(pivot);
+            matrix.swap(pivot, best);
+            let pivot_equation = matrix[pivot].clone();
+            for (row, equation) in matrix.iter_mut().enumerate() {
+                if row != pivot {
+                    let factor = equation[pivot] / pivot_equation[pivot];
+                    for (value, pivot_value) in equation.iter_mut().zip(&pivot_equation).skip(pivot)
+                    {
+                        *value -= factor * pivot_value;
+                    }
+                }
+            }
+        }
+        let solution: Vec<Real> = (0..terms)
+            .map(|row| matrix[row][terms] / matrix[row][row])
+            .collect();
+
+        offsets
+            .iter()
+            .map(|x| {
+                solution
+                    .iter()
+                    .enumerate()
+                    .map(|(power, z)| z * x.powi(power as i32))
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn is_full(&self) -> bool {
+        self.window.len() == self.coefficients.len()
+    }
+}
+
+impl Window for SavitzkyGolay {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        if self.is_full() {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+        if !self.is_full() {
+            return false;
+        }
+        self.value = self
+            .window
+            .iter()
+            .zip(&self.coefficients)
+            .map(|(value, coefficient)| value * coefficient)
+            .sum();
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        self.is_full().then_some(self.value)
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time - (self.coefficients.len() / 2) as Real
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::window::WindowFilter;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    #[should_panic]
+    fn even_size() {
+        SavitzkyGolay::new(4, 2);
+    }
+
+    #[test]
+    fn known_coefficients() {
+        for (size, order, expected) in [
+            (5, 2, vec![-3.0, 12.0, 17.0, 12.0, -3.0]),
+            (7, 3, vec![-2.0, 3.0, 6.0, 7.0, 6.0, 3.0, -2.0]),
+        ] {
+            let coefficients = SavitzkyGolay::new(size, order).coefficients;
+            let norm: Real = expected.iter().sum();
+            for (coefficient, expected) in coefficients.iter().zip(expected) {
+                assert_approx_eq!(coefficient, expected / norm, 1e-12);
+            }
+        }
+        // A fit of degree zero is a moving average
+        let coefficients = SavitzkyGolay::new(5, 0).coefficients;
+        assert!(coefficients.iter().all(|c| (c - 0.2).abs() < 1e-12));
+    }
+
+    #[test]
+    fn polynomial_preserved() {
+        let cubic = |x: Real| 0.01 * x.powi(3) - 0.5 * x.powi(2) + 3.0 * x - 7.0;
+        let output: Vec<_> = (0..30)
+            .map(|i| (i as Real, cubic(i as Real)))
+            .window(SavitzkyGolay::new(9, 3))
+            .collect();
+
+        assert_eq!(output.len(), 22);
+        assert_eq!(output[0].0, 4.0);
+        for (time, value) in output {
+            assert_approx_eq!(value, cubic(time), 1e-9);
+        }
+    }
+
+    #[test]
+    fn peak_height_preserved() {
+        let pulse = |x: Real| 100.0 * (-(x - 20.0).powi(2) / 8.0).exp();
+        let peak = |size, order| {
+            (0..40)
+                .map(|i| (i as Real, pulse(i as Real)))
+                .window(SavitzkyGolay::new(size, order))
+                .map(|(_, value)| value)
+                .fold(Real::MIN, Real::max)
+        };
+
+        // A moving average of the same size flattens the peak much more
+        assert!(peak(9, 4) > 95.0);
+        assert!(peak(9, 0) < 60.0);
+    }
+}
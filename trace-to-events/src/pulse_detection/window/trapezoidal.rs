@@ -0,0 +1,120 @@
+use super::{Real, Window};
+use std::collections::VecDeque;
+
+/// Shapes steps in the signal into trapezoids, which rise over `rise` samples, stay at the height
+/// of the step for a further `flat_top` samples, then fall over `rise` samples.
+///
+/// The output is the mean of the most recent `rise` samples, less the mean of the `rise` samples
+/// ending `rise + flat_top` samples earlier, so averages out noise over the rise.
+/// Each output is timed at the middle of the rise of the trapezoid of a step at that time.
+#[derive(Default, Clone)]
+pub(crate) struct TrapezoidalFilter {
+    rise: usize,
+    flat_top: usize,
+    window: VecDeque<Real>,
+    value: Real,
+}
+
+impl TrapezoidalFilter {
+    pub(crate) fn new(rise: usize, flat_top: usize) -> Self {
+        if rise < 1 {
+            panic!("Rise must be >= 1");
+        }
+        Self {
+            rise,
+            flat_top,
+            window: VecDeque::with_capacity(2 * rise + flat_top),
+            value: 0.0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.window.len() == 2 * self.rise + self.flat_top
+    }
+}
+
+impl Window for TrapezoidalFilter {
+    type TimeType = Real;
+    type InputType = Real;
+    type OutputType = Real;
+
+    fn push(&mut self, value: Real) -> bool {
+        if self.is_full() {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+        if !self.is_full() {
+            return false;
+        }
+        let delay = self.rise + self.flat_top;
+        let recent: Real = self.window.range(delay..).sum();
+        let earlier: Real = self.window.range(..self.rise).sum();
+        self.value = (recent - earlier) / self.rise as Real;
+        true
+    }
+
+    fn output(&self) -> Option<Real> {
+        self.is_full().then_some(self.value)
+    }
+
+    fn apply_time_shift(&self, time: Real) -> Real {
+        time - (self.rise - 1) as Real / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_detection::window::{PoleZeroCorrection, WindowFilter};
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    #[should_panic]
+    fn zero_rise() {
+        TrapezoidalFilter::new(0, 1);
+    }
+
+    #[test]
+    fn step_becomes_trapezoid() {
+        let output: Vec<_> = (0..40)
+            .map(|i| (i as Real, if i < 20 { 0.0 } else { 8.0 }))
+            .window(TrapezoidalFilter::new(4, 3))
+            .collect();
+
+        // The first output is once the window of 11 samples is full
+        assert_eq!(output.len(), 30);
+        assert_eq!(output[0], (8.5, 0.0));
+
+        let values: Vec<_> = output.iter().map(|(_, value)| *value).collect();
+        assert_eq!(
+            values[9..22],
+            [0.0, 2.0, 4.0, 6.0, 8.0, 8.0, 8.0, 8.0, 6.0, 4.0, 2.0, 0.0, 0.0]
+        );
+        // The middle of the rise is at the time of the step
+        assert_eq!((output[10].0 + output[13].0) / 2.0, 20.0);
+    }
+
+    #[test]
+    fn pulse_height_with_pole_zero_correction() {
+        let output: Vec<_> = (0..200)
+            .map(|i| {
+                let pulses = [(20, 50.0), (60, 30.0)]
+                    .into_iter()
+                    .filter(|(start, _)| i >= *start)
+                    .map(|(start, height)| height * (-((i - start) as Real) / 15.0).exp())
+                    .sum();
+                (i as Real, pulses)
+            })
+            .window(PoleZeroCorrection::new(15.0))
+            .window(TrapezoidalFilter::new(5, 10))
+            .map(|(_, value)| value)
+            .collect();
+
+        let max = output.iter().copied().fold(Real::MIN, Real::max);
+        assert_approx_eq!(max, 50.0, 1e-9);
+        // The second pulse is separated from the first, the first output being of sample 19
+        assert_approx_eq!(output[50 - 19], 0.0, 1e-9);
+        assert_approx_eq!(output[64 - 19], 30.0, 1e-9);
+        assert_approx_eq!(*output.last().unwrap(), 0.0, 1e-9);
+    }
+}
@@ -0,0 +1,530 @@
+use crate::{
+    config,
+    offline::{self, TraceFrame},
+    parameters::DetectorConfig,
+    processing,
+};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use rayon::prelude::*;
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+use supermusr_common::{Channel, DigitizerId, FrameNumber};
+use supermusr_streaming_types::{
+    dat1_digitizer_analog_trace_v1_generated::root_as_digitizer_analog_trace_message,
+    dev1_digitizer_event_v1_generated::root_as_digitizer_event_list_message,
+};
+use tracing::info;
+
+#[derive(Debug, Parser)]
+pub(crate) struct ScanArgs {
+    /// Detector config file with a `scan` table giving the values of each parameter to scan over. See README.md.
+    #[clap(long)]
+    scan_file: PathBuf,
+
+    /// Trace file written by trace-archiver or stream-to-file, which may be given more than once.
+    #[clap(long = "input", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// CSV file of the true events in the traces. See README.md.
+    #[clap(long)]
+    truth: PathBuf,
+
+    /// Largest difference in nanoseconds between the times of a detected and a true event for them to be matched.
+    #[clap(long, default_value = "10")]
+    tolerance: f64,
+
+    /// File to which the results of each setting are written, as JSON if its extension is "json", otherwise as CSV.
+    #[clap(long)]
+    output: PathBuf,
+}
+
+/// The values of the scanned parameters of a single setting, in the order of their names.
+type Setting = Vec<(String, Value)>;
+
+/// The times in nanoseconds of the true events of each channel of each digitiser frame.
+type Truth = HashMap<(DigitizerId, FrameNumber, Channel), Vec<f64>>;
+
+fn describe(setting: &Setting) -> String {
+    setting
+        .iter()
+        .map(|(name, value)| format!("{name} = {value}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Finds every combination of the values of the scanned parameters, and the detector
+/// configuration given by each, in which the scanned parameters replace the default parameters.
+fn settings(text: &str) -> Result<Vec<(Setting, DetectorConfig)>> {
+    let mut table = config::parse_table(text)?;
+    let scan: BTreeMap<String, Vec<Value>> = match table.remove("scan") {
+        Some(scan) => serde_json::from_value(scan)?,
+        None => return Err(anyhow!("scan file should have a scan table")),
+    };
+
+    let mut settings = vec![Setting::new()];
+    for (name, values) in &scan {
+        if values.is_empty() {
+            return Err(anyhow!("no values are given for {name}"));
+        }
+        settings = settings
+            .into_iter()
+            .flat_map(|setting| {
+                values.iter().map(move |value| {
+                    let mut setting = setting.clone();
+                    setting.push((name.clone(), value.clone()));
+                    setting
+                })
+            })
+            .collect();
+    }
+
+    settings
+        .into_iter()
+        .map(|setting| {
+            let mut table = table.clone();
+            table.extend(setting.iter().cloned());
+            let config = config::config_from_table(table)
+                .map_err(|e| anyhow!("{}: {e}", describe(&setting)))?;
+            Ok((setting, config))
+        })
+        .collect()
+}
+
+/// Parses true events from CSV with the columns `digitiser_id`, `frame_number`, `channel`
+/// and `time`, in any order, and with a header giving the columns.
+fn parse_truth(text: &str) -> Result<Truth> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines.next().ok_or(anyhow!("header is missing"))?;
+    let header: Vec<_> = header.split(',').map(str::trim).collect();
+    let column = |name| {
+        header
+            .iter()
+            .position(|&column| column == name)
+            .ok_or(anyhow!("column {name} is missing"))
+    };
+    let columns = [
+        column("digitiser_id")?,
+        column("frame_number")?,
+        column("channel")?,
+        column("time")?,
+    ];
+
+    let mut truth = Truth::new();
+    for (index, line) in lines {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let [digitiser_id, frame_number, channel, time] =
+            columns.map(|column| fields.get(column).copied().unwrap_or_default());
+        let event = || -> Result<_> {
+            Ok((
+                (
+                    digitiser_id.parse()?,
+                    frame_number.parse()?,
+                    channel.parse()?,
+                ),
+                time.parse()?,
+            ))
+        };
+        let (key, time) = event().map_err(|e| anyhow!("line {}: {e}", index + 1))?;
+        truth.entry(key).or_default().push(time);
+    }
+    Ok(truth)
+}
+
+/// Matches each true event to the closest unmatched detected event within the tolerance,
+/// returning the difference of the detected and true times of each match.
+fn match_events(truth: &[f64], detected: &[f64], tolerance: f64) -> Vec<f64> {
+    let mut matched = vec![false; detected.len()];
+    truth
+        .iter()
+        .filter_map(|&true_time| {
+            let (index, offset) = detected
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| !matched[index])
+                .map(|(index, &time)| (index, time - true_time))
+                .filter(|(_, offset)| offset.abs() <= tolerance)
+                .min_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))?;
+            matched[index] = true;
+            Some(offset)
+        })
+        .collect()
+}
+
+/// The performance of the detector with a single setting.
+#[derive(Debug, Default)]
+struct Evaluation {
+    frames: usize,
+    true_events: usize,
+    detected_events: usize,
+    offsets: Vec<f64>,
+}
+
+impl Evaluation {
+    fn columns(&self) -> [(&'static str, Value); 9] {
+        let matched = self.offsets.len();
+        let false_positives = self.detected_events - matched;
+        let mean = self.offsets.iter().sum::<f64>() / matched as f64;
+        let variance = self
+            .offsets
+            .iter()
+            .map(|offset| (offset - mean).powi(2))
+            .sum::<f64>()
+            / matched as f64;
+        [
+            ("frames", self.frames.into()),
+            ("true_events", self.true_events.into()),
+            ("detected_events", self.detected_events.into()),
+            ("matched_events", matched.into()),
+            (
+                "efficiency",
+                (matched as f64 / self.true_events as f64).into(),
+            ),
+            ("false_positives", false_positives.into()),
+            (
+                "false_positive_rate",
+                (false_positives as f64 / self.detected_events as f64).into(),
+            ),
+            ("mean_time_offset", mean.into()),
+            ("time_resolution", variance.sqrt().into()),
+        ]
+    }
+}
+
+/// Finds the events in each frame with the given configuration, and compares them with the true events.
+fn evaluate(
+    frames: &[(TraceFrame, Vec<u8>)],
+    truth: &Truth,
+    config: &DetectorConfig,
+    tolerance: f64,
+) -> Result<Evaluation> {
+    let mut evaluation = Evaluation::default();
+    for (frame, message) in frames {
+        let trace = root_as_digitizer_analog_trace_message(message)?;
+        let events = processing::process(&trace, config, None, false);
+        let events = root_as_digitizer_event_list_message(&events)?;
+
+        let mut detected = HashMap::<Channel, Vec<f64>>::new();
+        for (channel, time) in events
+            .channel()
+            .into_iter()
+            .flatten()
+            .zip(events.time().into_iter().flatten())
+        {
+            detected.entry(channel).or_default().push(time as f64);
+        }
+
+        for (channel, _) in &frame.channels {
+            let truth = truth
+                .get(&(frame.digitiser_id, frame.frame_number, *channel))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let detected = detected.get(channel).map(Vec::as_slice).unwrap_or_default();
+            evaluation.true_events += truth.len();
+            evaluation.detected_events += detected.len();
+            evaluation
+                .offsets
+                .extend(match_events(truth, detected, tolerance));
+        }
+        evaluation.frames += 1;
+    }
+    Ok(evaluation)
+}
+
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn write_results(path: &Path, results: &[(Setting, Evaluation)]) -> Result<()> {
+    let text = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let results: Vec<_> = results
+            .iter()
+            .map(|(setting, evaluation)| {
+                let mut row = Map::new();
+                row.insert(
+                    "parameters".to_owned(),
+                    Value::Object(setting.iter().cloned().collect()),
+                );
+                for (name, value) in evaluation.columns() {
+                    row.insert(name.to_owned(), value);
+                }
+                Value::Object(row)
+            })
+            .collect();
+        serde_json::to_string_pretty(&results)?
+    } else {
+        let mut lines = Vec::new();
+        if let Some((setting, evaluation)) = results.first() {
+            let header = setting
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .chain(evaluation.columns().map(|(name, _)| name));
+            lines.push(header.collect::<Vec<_>>().join(","));
+        }
+        for (setting, evaluation) in results {
+            let row = setting
+                .iter()
+                .map(|(_, value)| csv_field(value))
+                .chain(evaluation.columns().map(|(_, value)| csv_field(&value)));
+            lines.push(row.collect::<Vec<_>>().join(","));
+        }
+        lines.join("\n") + "\n"
+    };
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Evaluates the detector with each setting of the scanned parameters on the traces, writing the results to the output file.
+pub(crate) fn run(args: &ScanArgs) -> Result<()> {
+    let settings = fs::read_to_string(&args.scan_file)
+        .map_err(anyhow::Error::from)
+        .and_then(|text| settings(&text))
+        .map_err(|e| anyhow!("{}: {e}", args.scan_file.display()))?;
+    let truth = fs::read_to_string(&args.truth)
+        .map_err(anyhow::Error::from)
+        .and_then(|text| parse_truth(&text))
+        .map_err(|e| anyhow!("{}: {e}", args.truth.display()))?;
+
+    let mut frames = Vec::new();
+    for input in &args.inputs {
+        offline::read_trace_file(input, &mut |frame| {
+            let message = frame.to_message();
+            frames.push((frame, message));
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}: {e}", input.display()))?;
+    }
+    info!(
+        "Scanning {} settings over {} digitiser frames",
+        settings.len(),
+        frames.len()
+    );
+
+    let results = settings
+        .into_par_iter()
+        .map(|(setting, config)| {
+            let evaluation = evaluate(&frames, &truth, &config, args.tolerance)?;
+            info!("{}: {evaluation:?}", describe(&setting));
+            Ok((setting, evaluation))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    write_results(&args.output, &results)?;
+    info!("Wrote results to {}", args.output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use supermusr_common::Intensity;
+
+    const SCAN: &str = r#"
+        mode = "constant-phase-discriminator"
+        threshold_trigger = "-950,1,0"
+
+        [scan]
+        threshold_trigger = ["-990,1,0", "-950,1,0", "-950,3,0", "-800,1,0"]
+    "#;
+
+    /// Traces of baseline 1000 with pulses of depth 100 and of two samples at the given times,
+    /// and a pulse of depth 20 at sample 80 which is noise
+    fn frame(digitiser_id: DigitizerId, pulses: &[usize]) -> (TraceFrame, Vec<u8>) {
+        let voltage: Vec<Intensity> = (0..100)
+            .map(|i| {
+                if pulses.iter().any(|&pulse| i == pulse || i == pulse + 1) {
+                    900
+                } else if i == 80 {
+                    980
+                } else {
+                    1000
+                }
+            })
+            .collect();
+        let frame = TraceFrame {
+            digitiser_id,
+            frame_number: 1,
+            timestamp: Utc::now(),
+            sample_rate: 1_000_000_000,
+            channels: vec![(0, voltage)],
+        };
+        let message = frame.to_message();
+        (frame, message)
+    }
+
+    #[test]
+    fn cartesian_product() {
+        let settings = settings(
+            r#"
+            mode = "constant-phase-discriminator"
+            threshold_trigger = "-950,1,0"
+
+            [scan]
+            threshold_trigger = ["-950,1,0", "-900,1,0"]
+            channel = [1, 2, 3]
+            "#,
+        );
+        // The channel is not a parameter of the detector
+        assert!(settings.is_err());
+
+        let settings = settings_of(SCAN);
+        assert_eq!(settings.len(), 4);
+        assert_eq!(
+            settings[1].0,
+            vec![("threshold_trigger".to_owned(), Value::from("-950,1,0"))]
+        );
+    }
+
+    fn settings_of(text: &str) -> Vec<(Setting, DetectorConfig)> {
+        settings(text).unwrap()
+    }
+
+    /// The blocks of the given language in the README
+    fn readme_blocks(language: &str) -> Vec<&'static str> {
+        include_str!("../README.md")
+            .split(&format!("```{language}\n"))
+            .skip(1)
+            .map(|block| block.split("```").next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn readme_example() {
+        let scans: Vec<_> = readme_blocks("toml")
+            .into_iter()
+            .filter(|block| block.contains("[scan]"))
+            .collect();
+        assert_eq!(scans.len(), 1);
+        assert_eq!(settings(scans[0]).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn invalid_scan() {
+        assert!(settings(r#"mode = "constant-phase-discriminator""#).is_err());
+        assert!(settings(&SCAN.replace("\"-800,1,0\"", "\"-800,0,0\"")).is_err());
+        assert!(settings(
+            r#"
+            mode = "constant-phase-discriminator"
+            [scan]
+            threshold_trigger = []
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn truth() {
+        let truth = parse_truth(
+            "time,channel,digitiser_id,frame_number\n\
+             10,0,1,5\n\
+             \n\
+             20.5,0,1,5\n\
+             30,2,1,5\n",
+        )
+        .unwrap();
+        assert_eq!(truth[&(1, 5, 0)], vec![10.0, 20.5]);
+        assert_eq!(truth[&(1, 5, 2)], vec![30.0]);
+
+        assert!(parse_truth("digitiser_id,frame_number,channel\n1,2,3\n").is_err());
+        assert!(parse_truth("digitiser_id,frame_number,channel,time\n1,2,3\n").is_err());
+        assert!(parse_truth("digitiser_id,frame_number,channel,time\n1,2,x,4\n").is_err());
+    }
+
+    #[test]
+    fn matching() {
+        let offsets = match_events(&[10.0, 20.0, 50.0], &[11.0, 12.0, 19.0, 70.0], 3.0);
+        assert_eq!(offsets, vec![1.0, -1.0]);
+
+        // Each detected event is matched at most once
+        let offsets = match_events(&[10.0, 11.0], &[10.5], 3.0);
+        assert_eq!(offsets, vec![0.5]);
+    }
+
+    #[test]
+    fn roc() {
+        let frames = vec![frame(0, &[10, 40]), frame(1, &[20])];
+        let truth = parse_truth(
+            "digitiser_id,frame_number,channel,time\n\
+             0,1,0,10\n\
+             0,1,0,40\n\
+             1,1,0,20\n",
+        )
+        .unwrap();
+
+        let results: Vec<_> = settings_of(SCAN)
+            .into_iter()
+            .map(|(_, config)| evaluate(&frames, &truth, &config, 2.0).unwrap())
+            .collect();
+        let column = |index: usize, name| {
+            results[index]
+                .columns()
+                .into_iter()
+                .find(|(column, _)| *column == name)
+                .unwrap()
+                .1
+        };
+
+        // Too low a threshold also finds the noise
+        assert_eq!(column(0, "detected_events"), 5);
+        assert_eq!(column(0, "efficiency"), 1.0);
+        assert_eq!(column(0, "false_positive_rate"), 0.4);
+        assert_eq!(column(1, "efficiency"), 1.0);
+        assert_eq!(column(1, "false_positives"), 0);
+        assert_eq!(column(1, "mean_time_offset"), 0.0);
+        assert_eq!(column(1, "time_resolution"), 0.0);
+        // The pulses are two samples long, so are missed if three are required
+        assert_eq!(column(2, "detected_events"), 0);
+        assert_eq!(column(2, "efficiency"), 0.0);
+        assert_eq!(column(2, "false_positive_rate"), Value::Null);
+        // Too high a threshold finds no pulses
+        assert_eq!(column(3, "matched_events"), 0);
+        assert_eq!(column(3, "frames"), 2);
+    }
+
+    #[test]
+    fn csv() {
+        let mut evaluation = Evaluation {
+            frames: 1,
+            true_events: 2,
+            detected_events: 2,
+            offsets: vec![1.0, -1.0],
+        };
+        let path =
+            std::env::temp_dir().join(format!("trace-to-events-scan-{}.csv", std::process::id()));
+        let setting = vec![("threshold_trigger".to_owned(), Value::from("-950,1,0"))];
+        write_results(&path, &[(setting.clone(), evaluation)]).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "threshold_trigger,frames,true_events,detected_events,matched_events,efficiency,false_positives,false_positive_rate,mean_time_offset,time_resolution");
+        assert_eq!(lines[1], "\"-950,1,0\",1,2,2,2,1.0,0,0.0,0.0,1.0");
+
+        evaluation = Evaluation::default();
+        let path = path.with_extension("json");
+        write_results(&path, &[(setting, evaluation)]).unwrap();
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json[0]["parameters"]["threshold_trigger"], "-950,1,0");
+        assert_eq!(json[0]["efficiency"], Value::Null);
+        fs::remove_file(path.with_extension("csv")).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}